@@ -1,10 +1,15 @@
 
+use eddeserus::file::*;
+use eddeserus::parallel::*;
+use eddeserus::process::*;
 use eddeserus::types::*;
+use std::fs;
+use std::io;
 use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
 
 
-fn deserialize(x: &String) -> () {
-    let stream = serde_json::Deserializer::from_str(&x)
+fn deserialize(x: &str) {
+    let stream = serde_json::Deserializer::from_str(x)
                  .into_iter::<Event>();
 
     for event in stream {
@@ -60,7 +65,7 @@ fn deserialize_diagnosis(c: &mut Criterion) {
     {\"domain\":\"Diagnosis\",\
      \"patient_id\":\"abc\",\
      \"time\":{\"begin\":0,\"end\":1},\
-     \"facts\":{\"code\":{\"code\":\"99.01\",\"codebook\":\"ICD\"},\
+     \"facts\":{\"code\":{\"code\":\"99.01\",\"codebook\":\"ICD9\"},\
                \"location\":\"Inpatient\",\
                \"claim\":{\"id\":\"98918\",\"index\":900}},\
      \"source\":{\"table\":\"somewhere\",\"db\":\"optum\"},\
@@ -111,8 +116,87 @@ fn deserialize_procedure(c: &mut Criterion) {
 }
 
 
+fn process_diagnosis(c: &mut Criterion) {
+
+    let mut group = c.benchmark_group("process");
+    group.sample_size(10);
+
+    let val = "[\
+    \"abc\",2,null,\"Diagnosis\",[],\
+    {\"domain\":\"Diagnosis\",\
+     \"patient_id\":\"abc\",\
+     \"time\":{\"begin\":0,\"end\":1},\
+     \"facts\":{\"code\":{\"code\":\"99.01\",\"codebook\":\"ICD9\"},\
+               \"location\":\"Inpatient\",\
+               \"claim\":{\"id\":\"98918\",\"index\":900}},\
+     \"source\":{\"table\":\"somewhere\",\"db\":\"optum\"},\
+     \"misc\":{\"key1\":\"val1\",\"key2\":\"val2\",\"key3\":\"val3\",\
+               \"key4\":\"val4\",\"key5\":\"val5\",\"key5\":\"val5\"}}\
+    ]\n";
+
+    let json = val.repeat(100_000);
+
+    group.bench_with_input(
+        BenchmarkId::new("serial", "100000 diagnosis"),
+         &json,
+        |b, j| b.iter(|| {
+            let mut input = ReaderIn::from_reader(j.as_bytes());
+            process(&mut input, &mut map(|e| e), &mut WriterOut::new(io::sink())).unwrap()
+        }));
+
+    for order in [Order::Input, Order::Arrival].iter() {
+        let options = Options { order: *order, ..Options::default() };
+        group.bench_with_input(
+            BenchmarkId::new("parallel", format!("100000 diagnosis {:?}", order)),
+             &json,
+            |b, j| b.iter(|| process_parallel(j.as_bytes(), || map(|e| e),
+                                               &mut io::sink(), &mut Stop, &options).unwrap() ));
+    }
+}
+
+
+fn process_file(c: &mut Criterion) {
+
+    let mut group = c.benchmark_group("file");
+    group.sample_size(10);
+
+    let val = "[\
+    \"abc\",2,null,\"Procedure\",[],\
+    {\"domain\":\"Procedure\",\
+     \"patient_id\":\"abc\",\
+     \"time\":{\"begin\":0,\"end\":1},\
+     \"facts\":{\"code\":{\"code\":\"99.01\",\"codebook\":\"CPT\"},\
+               \"location\":\"Outpatient\"}}\
+    ]\n";
+
+    let path = std::env::temp_dir().join("eddeserus-bench-file.json");
+    fs::write(&path, val.repeat(100_000)).unwrap();
+
+    group.bench_with_input(
+        BenchmarkId::new("string", "100000 procedure"),
+         &path,
+        |b, p| b.iter(|| {
+            let json = fs::read_to_string(p).unwrap();
+            process_str(&json, &mut map(|e| e), &mut WriterOut::new(io::sink())).unwrap()
+        }));
+
+    group.bench_with_input(
+        BenchmarkId::new("mmap", "100000 procedure"),
+         &path,
+        |b, p| b.iter(|| {
+            let file = unsafe { EventFile::open(p) }.unwrap();
+            process_bytes(file.as_bytes(), &mut map(|e| e), &mut WriterOut::new(io::sink())).unwrap()
+        }));
+
+    group.finish();
+    fs::remove_file(path).unwrap();
+}
+
+
 criterion_group!(benches, 
     deserialize_demographics, 
     deserialize_diagnosis,
-    deserialize_procedure);
+    deserialize_procedure,
+    process_diagnosis,
+    process_file);
 criterion_main!(benches);
\ No newline at end of file
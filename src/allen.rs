@@ -0,0 +1,308 @@
+//! Allen's interval algebra: the 13 ways two intervals can relate.
+//!
+//! The [`Allen`] trait gives the [`Relation`] between any two values with a
+//! begin and an optional end: [`Interval`]s, [`Event`]s (by their header
+//! times `b` and `e`), and `(EventTime, Option<EventTime>)` pairs such as an
+//! analysis window.
+//!
+//! Intervals are compared by their endpoints, so exactly one relation holds
+//! between any two of them, including intervals whose begin equals their end.
+//!
+//! Open ends: an end of `None` is an interval that has not ended, so it is
+//! later than every time. An open interval `Contains`, is `StartedBy`, is
+//! `OverlappedBy`, is `MetBy` or is `After` a closed one; against another open
+//! interval it `Equals`, `Finishes` or is `FinishedBy` it, by their begins. It
+//! is never `Before`, `Meets`, `Overlaps`, `Starts` or `During` anything.
+//!
+//! Mixed representations: an [`Interval`] is compared by its parsed times
+//! (see [`Interval::begin`]), not by whether it is an `IntervalInt` or an
+//! `IntervalStr`; `IntervalStr { begin: "5", .. }` is the offset 5, like
+//! `IntervalInt { begin: 5, .. }`. Offsets and dates cannot be compared, so
+//! there is no relation between an interval of offsets and one of dates, nor
+//! with an interval whose times do not parse.
+//!
+//! Example:
+//! ```
+//! use eddeserus::allen::*;
+//! use eddeserus::types::{EventTime, Interval};
+//!
+//! let enrollment = Interval::IntervalStr { begin: "2010-01-01".to_string(), end: None };
+//! let diagnosis = Interval::IntervalStr {
+//!     begin: "2010-03-01".to_string(),
+//!     end: Some("2010-03-02".to_string()),
+//! };
+//! assert!(diagnosis.during(&enrollment));
+//! assert_eq!(enrollment.relation(&diagnosis), Some(Relation::Contains));
+//!
+//! let offsets = (EventTime::Offset(0), Some(EventTime::Offset(10)));
+//! assert_eq!(diagnosis.relation(&offsets), None);
+//! ```
+
+use std::cmp::Ordering;
+
+use crate::types::{Event, EventTime, Interval};
+
+/// How interval `a` relates to interval `b`, where `a` has endpoints
+/// `a1 <= a2` and `b` has `b1 <= b2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Relation {
+    /// `a2 < b1`
+    Before,
+    /// `a2 == b1`, and `a1 < b1`
+    Meets,
+    /// `a1 < b1 < a2 < b2`
+    Overlaps,
+    /// `a1 == b1` and `a2 < b2`
+    Starts,
+    /// `b1 < a1` and `a2 < b2`
+    During,
+    /// `b1 < a1` and `a2 == b2`
+    Finishes,
+    /// `a1 == b1` and `a2 == b2`
+    Equals,
+    /// The inverse of `Finishes`.
+    FinishedBy,
+    /// The inverse of `During`.
+    Contains,
+    /// The inverse of `Starts`.
+    StartedBy,
+    /// The inverse of `Overlaps`.
+    OverlappedBy,
+    /// The inverse of `Meets`.
+    MetBy,
+    /// The inverse of `Before`.
+    After,
+}
+
+impl Relation {
+    pub const ALL : [Relation; 13] = [
+        Relation::Before, Relation::Meets, Relation::Overlaps, Relation::Starts,
+        Relation::During, Relation::Finishes, Relation::Equals, Relation::FinishedBy,
+        Relation::Contains, Relation::StartedBy, Relation::OverlappedBy, Relation::MetBy,
+        Relation::After,
+    ];
+
+    /// The relation of `b` to `a` when `a` has this relation to `b`.
+    pub fn inverse(self) -> Relation {
+        match self {
+            Relation::Before       => Relation::After,
+            Relation::Meets        => Relation::MetBy,
+            Relation::Overlaps     => Relation::OverlappedBy,
+            Relation::Starts       => Relation::StartedBy,
+            Relation::During       => Relation::Contains,
+            Relation::Finishes     => Relation::FinishedBy,
+            Relation::Equals       => Relation::Equals,
+            Relation::FinishedBy   => Relation::Finishes,
+            Relation::Contains     => Relation::During,
+            Relation::StartedBy    => Relation::Starts,
+            Relation::OverlappedBy => Relation::Overlaps,
+            Relation::MetBy        => Relation::Meets,
+            Relation::After        => Relation::Before,
+        }
+    }
+}
+
+/// Compares a time with an end, which is later than every time if open.
+fn cmp_end(t: EventTime, end: Option<EventTime>) -> Option<Ordering> {
+    match end {
+        Some(end) => t.partial_cmp(&end),
+        None => Some(Ordering::Less),
+    }
+}
+
+fn cmp_ends(a: Option<EventTime>, b: Option<EventTime>) -> Option<Ordering> {
+    match (a, b) {
+        (Some(a), b) => cmp_end(a, b),
+        (None, Some(b)) => cmp_end(b, None).map(Ordering::reverse),
+        (None, None) => Some(Ordering::Equal),
+    }
+}
+
+/// The relation between intervals `(a1, a2)` and `(b1, b2)`.
+fn relate(a1: EventTime, a2: Option<EventTime>, b1: EventTime, b2: Option<EventTime>)
+          -> Option<Relation> {
+    use Ordering::*;
+    let relation = match (a1.partial_cmp(&b1)?, cmp_ends(a2, b2)?) {
+        (Equal, Equal)     => Relation::Equals,
+        (Equal, Less)      => Relation::Starts,
+        (Equal, Greater)   => Relation::StartedBy,
+        (Greater, Equal)   => Relation::Finishes,
+        (Less, Equal)      => Relation::FinishedBy,
+        (Greater, Less)    => Relation::During,
+        (Less, Greater)    => Relation::Contains,
+        (Less, Less)       => match cmp_end(b1, a2)?.reverse() {
+            Less    => Relation::Before,
+            Equal   => Relation::Meets,
+            Greater => Relation::Overlaps,
+        },
+        (Greater, Greater) => match cmp_end(a1, b2)? {
+            Greater => Relation::After,
+            Equal   => Relation::MetBy,
+            Less    => Relation::OverlappedBy,
+        },
+    };
+    Some(relation)
+}
+
+/// A value with a begin and an optional end.
+pub trait Allen {
+    /// The begin and end, or `None` if they are not valid times.
+    fn bounds(&self) -> Option<(EventTime, Option<EventTime>)>;
+
+    /// The relation of `self` to `other`, or `None` if they cannot be compared.
+    fn relation<T: Allen + ?Sized>(&self, other: &T) -> Option<Relation> {
+        let (a1, a2) = self.bounds()?;
+        let (b1, b2) = other.bounds()?;
+        relate(a1, a2, b1, b2)
+    }
+
+    fn before<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Before)
+    }
+
+    fn meets<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Meets)
+    }
+
+    fn overlaps<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Overlaps)
+    }
+
+    fn starts<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Starts)
+    }
+
+    fn during<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::During)
+    }
+
+    fn finishes<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Finishes)
+    }
+
+    fn equals<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Equals)
+    }
+
+    fn finished_by<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::FinishedBy)
+    }
+
+    fn contains<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::Contains)
+    }
+
+    fn started_by<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::StartedBy)
+    }
+
+    fn overlapped_by<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::OverlappedBy)
+    }
+
+    fn met_by<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::MetBy)
+    }
+
+    fn after<T: Allen + ?Sized>(&self, other: &T) -> bool {
+        self.relation(other) == Some(Relation::After)
+    }
+}
+
+impl Allen for Interval {
+    fn bounds(&self) -> Option<(EventTime, Option<EventTime>)> {
+        Some((self.begin().ok()?, self.end().ok()?))
+    }
+}
+
+/// Events relate by their header times `b` and `e`.
+impl Allen for Event<'_> {
+    fn bounds(&self) -> Option<(EventTime, Option<EventTime>)> {
+        Some((self.b, self.e))
+    }
+}
+
+impl Allen for (EventTime, Option<EventTime>) {
+    fn bounds(&self) -> Option<(EventTime, Option<EventTime>)> {
+        Some(*self)
+    }
+}
+
+#[cfg(test)]
+mod test_allen {
+    use crate::allen::*;
+    use crate::types::*;
+
+    fn iv(b: i64, e: Option<i64>) -> (EventTime, Option<EventTime>) {
+        (EventTime::Offset(b), e.map(EventTime::Offset))
+    }
+
+    #[test]
+    fn test_closed() {
+        let b = iv(10, Some(20));
+        let cases = [
+            (iv(0, Some(5)),   Relation::Before),
+            (iv(0, Some(10)),  Relation::Meets),
+            (iv(5, Some(15)),  Relation::Overlaps),
+            (iv(10, Some(15)), Relation::Starts),
+            (iv(12, Some(15)), Relation::During),
+            (iv(15, Some(20)), Relation::Finishes),
+            (iv(10, Some(20)), Relation::Equals),
+            (iv(5, Some(20)),  Relation::FinishedBy),
+            (iv(5, Some(25)),  Relation::Contains),
+            (iv(10, Some(25)), Relation::StartedBy),
+            (iv(15, Some(25)), Relation::OverlappedBy),
+            (iv(20, Some(25)), Relation::MetBy),
+            (iv(21, Some(25)), Relation::After),
+        ];
+        for (a, r) in cases.iter() {
+            assert_eq!(a.relation(&b), Some(*r), "{:?}", a);
+            assert_eq!(b.relation(a), Some(r.inverse()), "{:?}", a);
+        }
+        assert_eq!(cases.iter().map(|(_, r)| *r).collect::<Vec<_>>(), Relation::ALL.to_vec());
+    }
+
+    #[test]
+    fn test_open() {
+        let open = iv(10, None);
+        assert_eq!(open.relation(&iv(10, None)), Some(Relation::Equals));
+        assert_eq!(open.relation(&iv(5, None)), Some(Relation::Finishes));
+        assert_eq!(open.relation(&iv(0, Some(5))), Some(Relation::After));
+        assert_eq!(open.relation(&iv(0, Some(10))), Some(Relation::MetBy));
+        assert_eq!(open.relation(&iv(5, Some(15))), Some(Relation::OverlappedBy));
+        assert_eq!(open.relation(&iv(10, Some(15))), Some(Relation::StartedBy));
+        assert_eq!(open.relation(&iv(12, Some(15))), Some(Relation::Contains));
+        assert_eq!(iv(12, Some(15)).relation(&open), Some(Relation::During));
+    }
+
+    #[test]
+    fn test_points() {
+        let b = iv(10, Some(20));
+        assert!(iv(10, Some(10)).starts(&b));
+        assert!(iv(15, Some(15)).during(&b));
+        assert!(iv(20, Some(20)).finishes(&b));
+        assert!(iv(5, Some(5)).equals(&iv(5, Some(5))));
+    }
+
+    #[test]
+    fn test_mixed() {
+        let int = Interval::IntervalInt { begin: 5, end: Some(8) };
+        let str_offset = Interval::IntervalStr { begin: "5".to_string(), end: None };
+        let dates = Interval::IntervalStr { begin: "2010-01-01".to_string(), end: None };
+        let bad = Interval::IntervalStr { begin: "01/01/2010".to_string(), end: None };
+
+        assert!(int.starts(&str_offset));
+        assert_eq!(int.relation(&dates), None);
+        assert_eq!(dates.relation(&bad), None);
+        assert!(!int.before(&dates) && !int.after(&dates));
+    }
+
+    #[test]
+    fn test_events() {
+        let json = include_str!("../resources/50events.json");
+        let events : Vec<Event> = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let window = (EventTime::Date(Date::from_ymd(2000, 1, 1).unwrap()), None);
+        assert!(events.iter().all(|e| e.relation(&window).is_some()));
+        assert!(events.iter().all(|e| e.relation(&e.context.time) == Some(Relation::Equals)));
+    }
+}
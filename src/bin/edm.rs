@@ -0,0 +1,592 @@
+//! `edm`: validate, count, filter and convert files of EDM events.
+//!
+//! Input files hold one JSON event per line and may be gzip-compressed.
+//! With no input files, or `-`, events are read from stdin.
+//!
+//! Exit codes:
+//!
+//! * `0`: success.
+//! * `1`: some input is not valid (a line is not an event, or `validate`
+//!   found a problem).
+//! * `2`: bad arguments, or a file could not be read or written.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use flate2::bufread::MultiGzDecoder;
+
+use eddeserus::codes::NormalizeNdc;
+use eddeserus::flat::{Column, CsvByDomain, CsvOut};
+use eddeserus::import::{import, ImportConfig, ImportError, RowError};
+use eddeserus::parallel::{process_parallel, Options, Order};
+use eddeserus::process::*;
+use eddeserus::sort::{SortOptions, Sorter};
+use eddeserus::types::{Event, SubjectID};
+use eddeserus::validate::validate;
+use eddeserus::valueset::ValueSet;
+
+#[derive(Parser)]
+#[command(name = "edm", version, about = "Validate, count, filter and convert EDM event files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that each line is an event whose header agrees with its context
+    /// and whose code has the syntax of its codebook
+    Validate {
+        #[command(flatten)]
+        input: InputFiles,
+    },
+
+    /// Count events, in total or by domain or patient
+    Count {
+        #[arg(long, value_enum)]
+        by: Option<CountBy>,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Write the first events
+    Head {
+        /// Number of events to write
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Write the events that match all of the given options; an option given
+    /// more than once matches any of its values
+    Filter {
+        #[arg(long)]
+        domain: Vec<String>,
+
+        #[arg(long)]
+        patient: Vec<String>,
+
+        /// Match `--patient` values as integer IDs rather than string IDs
+        #[arg(long)]
+        integer_ids: bool,
+
+        #[arg(long)]
+        concept: Vec<String>,
+
+        /// Tag events with the concepts of this value set (CSV or JSON) before
+        /// filtering
+        #[arg(long)]
+        value_set: Option<PathBuf>,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        #[command(flatten)]
+        parallel: ParallelArgs,
+    },
+
+    /// Sort events by patient, begin, end and domain, spilling to temporary
+    /// files when they do not fit in memory
+    Sort {
+        /// Memory to use for buffering events, in MiB
+        #[arg(long, default_value_t = 256)]
+        memory: usize,
+
+        /// Directory for temporary files
+        #[arg(long)]
+        tmp_dir: Option<PathBuf>,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Rewrite events in another format
+    Convert {
+        #[arg(long, value_enum, default_value_t = Format::Ndjson)]
+        to: Format,
+
+        /// Normalize NDC and NDC9 codes
+        #[arg(long)]
+        normalize_ndc: bool,
+
+        /// With `--to csv`, the columns to write, separated by commas
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<Column>,
+
+        /// With `--to csv`, write the events of each domain to `<domain>.csv`
+        /// in the output directory, by default with the columns that apply to
+        /// the domain
+        #[arg(long, requires = "output")]
+        by_domain: bool,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        #[command(flatten)]
+        output: OutputArgs,
+
+        #[command(flatten)]
+        parallel: ParallelArgs,
+    },
+
+    /// Build events from CSV or TSV tables; rows that fail to map are reported
+    /// on stderr
+    Import {
+        /// JSON file mapping columns to the fields of each domain
+        #[arg(long)]
+        config: PathBuf,
+
+        /// Input tables (`-` for stdin); gzip-compressed files are detected
+        inputs: Vec<PathBuf>,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(Args)]
+struct InputFiles {
+    /// Input files (`-` for stdin); gzip-compressed files are detected
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct InputArgs {
+    #[command(flatten)]
+    files: InputFiles,
+
+    /// Report lines that are not events on stderr and go on
+    #[arg(long)]
+    skip_errors: bool,
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct ParallelArgs {
+    /// Number of worker threads, or 0 for one per CPU
+    #[arg(short = 'j', long, default_value_t = 1)]
+    threads: usize,
+
+    /// Write events in the order that workers finish them rather than in
+    /// input order
+    #[arg(long)]
+    unordered: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CountBy {
+    Domain,
+    Patient,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// Newline-delimited JSON
+    Ndjson,
+    /// Newline-delimited JSON with events as keyed objects
+    Keyed,
+    /// CSV with one column per field, written on one thread
+    Csv,
+}
+
+/*----------------------------------------------------------------------------*/
+// Input and output
+
+impl InputFiles {
+    fn paths(&self) -> Vec<PathBuf> {
+        if self.inputs.is_empty() {
+            vec![PathBuf::from("-")]
+        } else {
+            self.inputs.clone()
+        }
+    }
+}
+
+impl InputArgs {
+    fn paths(&self) -> Vec<PathBuf> {
+        self.files.paths()
+    }
+}
+
+/// Opens a file, or stdin for `-`, decompressing it if it starts with the
+/// gzip magic number.
+fn open(path: &Path) -> io::Result<Box<dyn BufRead + Send>> {
+    let reader : Box<dyn Read + Send> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(|e| with_path(path, e))?)
+    };
+
+    let mut reader = BufReader::new(reader);
+    let gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if gzip {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn create(output: &OutputArgs) -> io::Result<Box<dyn Write>> {
+    match &output.output {
+        Some(path) => Ok(Box::new(File::create(path).map_err(|e| with_path(path, e))?)),
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+fn with_path(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+}
+
+/// Prints failed lines to stderr.
+struct Report<'p> {
+    path: &'p Path,
+    failed: usize,
+}
+
+impl ErrorHandler for Report<'_> {
+    fn handle_error(&mut self, error: LineError) -> io::Result<()> {
+        self.failed += 1;
+        eprintln!("{}:{}: {}", self.path.display(), error.line, error.error);
+        Ok(())
+    }
+}
+
+/// Runs a pipeline over every input, returning the number of lines that are
+/// not events.
+fn run<P, O>(input: &InputArgs, processor: &mut P, output: &mut O) -> io::Result<usize>
+where P: Processor + ?Sized,
+      O: for<'a> OutHandler<'a> + ?Sized
+{
+    let mut failed = 0;
+    for path in input.paths() {
+        let mut reader = ReaderIn::new(open(&path)?);
+        if input.skip_errors {
+            let mut report = Report { path: &path, failed: 0 };
+            process_with_errors(&mut reader, processor, output, &mut report)?;
+            failed += report.failed;
+        } else {
+            process(&mut reader, processor, output).map_err(|e| with_path(&path, e))?;
+        }
+    }
+    Ok(failed)
+}
+
+/// Runs a pipeline over every input and writes the events to the output, on
+/// worker threads if more than one is asked for. Each worker makes its own
+/// processor. Events are written as arrays, or as objects if `keyed`.
+fn run_to<F, P>(input: &InputArgs, make_processor: F, output: &OutputArgs,
+                parallel: &ParallelArgs, keyed: bool) -> io::Result<usize>
+where F: Fn() -> P + Sync,
+      P: Processor
+{
+    if parallel.threads == 1 {
+        let writer = create(output)?;
+        let mut out = if keyed { WriterOut::keyed(writer) } else { WriterOut::new(writer) };
+        return run(input, &mut make_processor(), &mut out);
+    }
+
+    let mut options = Options { keyed, ..Options::default() };
+    if parallel.threads > 0 {
+        options.threads = parallel.threads;
+    }
+    if parallel.unordered {
+        options.order = Order::Arrival;
+    }
+
+    let mut writer = create(output)?;
+    let mut failed = 0;
+    for path in input.paths() {
+        let reader = open(&path)?;
+        if input.skip_errors {
+            let mut report = Report { path: &path, failed: 0 };
+            process_parallel(reader, &make_processor, &mut writer, &mut report, &options)?;
+            failed += report.failed;
+        } else {
+            process_parallel(reader, &make_processor, &mut writer, &mut Stop, &options)
+                .map_err(|e| with_path(&path, e))?;
+        }
+    }
+    Ok(failed)
+}
+
+/// Calls `f` with the path, line number and text of each line of each input,
+/// until `f` returns `false`.
+fn for_each_line<F>(input: &InputFiles, mut f: F) -> io::Result<()>
+where F: FnMut(&Path, usize, &[u8]) -> io::Result<bool>
+{
+    for path in input.paths() {
+        let mut reader = ReaderIn::new(open(&path)?);
+        let mut n = 0;
+        while let Some(line) = reader.next_line() {
+            n += 1;
+            if !f(&path, n, line?)? {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/*----------------------------------------------------------------------------*/
+// Commands
+
+/// How a command ended, if it did not fail with an I/O error.
+enum Status {
+    Ok,
+    Invalid,
+}
+
+impl Status {
+    fn from_failures(n: usize) -> Status {
+        if n == 0 { Status::Ok } else { Status::Invalid }
+    }
+}
+
+fn validate_cmd(input: &InputFiles) -> io::Result<Status> {
+    let mut events = 0;
+    let mut invalid = 0;
+    for_each_line(input, |path, n, line| {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(true);
+        }
+
+        let mut problems = Vec::new();
+        match serde_json::from_slice::<Event>(line) {
+            Ok(event) => {
+                events += 1;
+                problems.extend(validate(&event).iter().map(|v| v.to_string()));
+                if let Some(Err(e)) = event.context.facts.code().map(|c| c.check()) {
+                    problems.push(e.to_string());
+                }
+            },
+            Err(e) => problems.push(e.to_string()),
+        }
+
+        if !problems.is_empty() {
+            invalid += 1;
+            for p in problems {
+                println!("{}:{}: {}", path.display(), n, p);
+            }
+        }
+        Ok(true)
+    })?;
+
+    eprintln!("{} events, {} invalid lines", events, invalid);
+    Ok(Status::from_failures(invalid))
+}
+
+fn count_cmd(by: Option<CountBy>, input: &InputArgs) -> io::Result<Status> {
+    let mut counts : BTreeMap<String, usize> = BTreeMap::new();
+    let mut total = 0;
+    let mut output = callback(|e: Event| {
+        total += 1;
+        match by {
+            Some(CountBy::Domain)  => *counts.entry(e.d.into_owned()).or_default() += 1,
+            Some(CountBy::Patient) => *counts.entry(e.p.to_string()).or_default() += 1,
+            None => (),
+        }
+        Ok(())
+    });
+    let failed = run(input, &mut map(|e| e), &mut output)?;
+
+    match by {
+        Some(_) => for (k, n) in counts { println!("{}\t{}", k, n) },
+        None => println!("{}", total),
+    }
+    Ok(Status::from_failures(failed))
+}
+
+fn head_cmd(lines: usize, input: &InputArgs, output: &OutputArgs) -> io::Result<Status> {
+    let mut out = WriterOut::new(create(output)?);
+    let mut written = 0;
+    let mut failed = 0;
+    if lines > 0 {
+        for_each_line(&input.files, |path, n, line| {
+            let line = line.trim_ascii();
+            if line.is_empty() {
+                return Ok(true);
+            }
+            match serde_json::from_slice::<Event>(line) {
+                Ok(event) => {
+                    out.write_event(event)?;
+                    written += 1;
+                },
+                Err(e) if input.skip_errors => {
+                    failed += 1;
+                    eprintln!("{}:{}: {}", path.display(), n, e);
+                },
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                    format!("{}:{}: {}", path.display(), n, e))),
+            }
+            Ok(written < lines)
+        })?;
+    }
+    OutHandler::flush(&mut out)?;
+    Ok(Status::from_failures(failed))
+}
+
+/// Parses `--patient` values as string IDs, or as integer IDs if
+/// `integer_ids`, so that `123` matches only the ID of its kind.
+fn subject_ids(patient: &[String], integer_ids: bool) -> io::Result<Vec<SubjectID<'static>>> {
+    patient.iter()
+        .map(|p| if integer_ids {
+            p.parse().map(SubjectID::Idint).map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidInput, format!("--patient {:?} is not an integer ID", p)))
+        } else {
+            Ok(SubjectID::IDstr(Cow::Owned(p.clone())))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn filter_cmd(domain: &[String], patient: &[String], integer_ids: bool, concept: &[String],
+              value_set: Option<&Path>, input: &InputArgs, output: &OutputArgs,
+              parallel: &ParallelArgs) -> io::Result<Status> {
+    let value_set = match value_set {
+        Some(path) => ValueSet::from_path(path).map_err(|e| with_path(path, e))?,
+        None => ValueSet::new(),
+    };
+    let patient = subject_ids(patient, integer_ids)?;
+
+    let keep = |e: &Event| {
+        (domain.is_empty() || domain.iter().any(|d| *d == e.d))
+            && (patient.is_empty() || patient.contains(&e.p))
+            && (concept.is_empty() || concept.iter().any(|c| e.concepts.contains(c)))
+    };
+    let make_processor = || value_set.tagger().then(filter(keep));
+
+    let failed = run_to(input, make_processor, output, parallel, false)?;
+    Ok(Status::from_failures(failed))
+}
+
+fn sort_cmd(memory: usize, tmp_dir: Option<&Path>, input: &InputArgs, output: &OutputArgs)
+            -> io::Result<Status> {
+    let mut options = SortOptions { memory: memory << 20, ..SortOptions::default() };
+    if let Some(dir) = tmp_dir {
+        options.dir = dir.to_path_buf();
+    }
+
+    let mut sorter = Sorter::new(options);
+    let mut failed = 0;
+    for path in input.paths() {
+        let mut reader = ReaderIn::new(open(&path)?);
+        if input.skip_errors {
+            let mut report = Report { path: &path, failed: 0 };
+            sorter.add(&mut reader, &mut report)?;
+            failed += report.failed;
+        } else {
+            sorter.add(&mut reader, &mut Stop).map_err(|e| with_path(&path, e))?;
+        }
+    }
+    sorter.finish(&mut create(output)?)?;
+    Ok(Status::from_failures(failed))
+}
+
+fn convert_cmd(to: Format, normalize_ndc: bool, columns: &[Column], by_domain: bool,
+               input: &InputArgs, output: &OutputArgs, parallel: &ParallelArgs)
+               -> io::Result<Status> {
+    let make_processor = || -> Box<dyn Processor> {
+        if normalize_ndc {
+            Box::new(NormalizeNdc::new(|e| eprintln!("{}", e)))
+        } else {
+            Box::new(map(|e| e))
+        }
+    };
+    let columns = if columns.is_empty() { None } else { Some(columns.to_vec()) };
+
+    let failed = match to {
+        Format::Ndjson => run_to(input, make_processor, output, parallel, false)?,
+        Format::Keyed => run_to(input, make_processor, output, parallel, true)?,
+        Format::Csv if by_domain => {
+            let dir = output.output.as_deref().expect("required by clap");
+            fs::create_dir_all(dir).map_err(|e| with_path(dir, e))?;
+            run(input, &mut make_processor(), &mut CsvByDomain::new(dir, columns))?
+        }
+        Format::Csv => {
+            let columns = columns.unwrap_or_else(|| Column::ALL.to_vec());
+            run(input, &mut make_processor(), &mut CsvOut::new(create(output)?, columns)?)?
+        }
+    };
+    Ok(Status::from_failures(failed))
+}
+
+fn import_cmd(config: &Path, inputs: &[PathBuf], output: &OutputArgs) -> io::Result<Status> {
+    let file = File::open(config).map_err(|e| with_path(config, e))?;
+    let config = ImportConfig::from_json(file)
+        .map_err(|e| with_path(config, io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+
+    let paths = if inputs.is_empty() { vec![PathBuf::from("-")] } else { inputs.to_vec() };
+    let mut out = WriterOut::new(create(output)?);
+    let mut failed = 0;
+    for path in paths {
+        let report = |e: RowError| match e.domain {
+            Some(domain) => eprintln!("{}:{}: {}: {}", path.display(), e.line, domain, e.error),
+            None => eprintln!("{}:{}: {}", path.display(), e.line, e.error),
+        };
+        let summary = import(&config, open(&path)?, &mut out, report)
+            .map_err(|e| match e {
+                ImportError::Io(e) => with_path(&path, e),
+                ImportError::Csv(e) if e.is_io_error() => with_path(&path, e.into()),
+                e => with_path(&path, io::Error::new(io::ErrorKind::InvalidInput, e)),
+            })?;
+        failed += summary.failed;
+    }
+    Ok(Status::from_failures(failed))
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let result = match &cli.command {
+        Command::Validate { input } =>
+            validate_cmd(input),
+        Command::Count { by, input } =>
+            count_cmd(*by, input),
+        Command::Head { lines, input, output } =>
+            head_cmd(*lines, input, output),
+        Command::Filter { domain, patient, integer_ids, concept, value_set, input, output, parallel } =>
+            filter_cmd(domain, patient, *integer_ids, concept, value_set.as_deref(), input, output,
+                       parallel),
+        Command::Sort { memory, tmp_dir, input, output } =>
+            sort_cmd(*memory, tmp_dir.as_deref(), input, output),
+        Command::Convert { to, normalize_ndc, columns, by_domain, input, output, parallel } =>
+            convert_cmd(*to, *normalize_ndc, columns, *by_domain, input, output, parallel),
+        Command::Import { config, inputs, output } =>
+            import_cmd(config, inputs, output),
+    };
+
+    let code = match result {
+        Ok(Status::Ok) => 0,
+        Ok(Status::Invalid) => 1,
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(e) => {
+            eprintln!("edm: {}", e);
+            if e.kind() == io::ErrorKind::InvalidData { 1 } else { 2 }
+        },
+    };
+    process::exit(code);
+}
@@ -0,0 +1,606 @@
+//! Compact binary encodings of events.
+//!
+//! Each format is behind a feature of its own: [`MessagePack`] (`msgpack`),
+//! [`Cbor`] (`cbor`) and [`Bincode`] (`bincode`). All of them encode the
+//! same plain representation of an event, in which `source` and `misc` are
+//! kept as their raw JSON text, so decoding gives back an event that
+//! serializes to the same JSON.
+//!
+//! In a stream, each event is a frame: its length as a 4-byte little-endian
+//! integer followed by its encoding. [`FrameWriter`] writes frames and
+//! [`FrameReader`] reads them back.
+//!
+//! ```
+//! # #[cfg(feature = "msgpack")] {
+//! use eddeserus::binary::*;
+//! use eddeserus::sede::*;
+//! let json = "\
+//!     [\"xyz\",\"2010-01-01\",null,\"Claim\",[],\
+//!      {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Claim\",\"facts\":{\"claim\":{\"id\":\"claim1\"}},\
+//!       \"source\":{\"table\": \"claims\"}}]";
+//! let bytes = serialize_event_binary::<MessagePack>(&deserialize_event(json).unwrap()).unwrap();
+//! let event = deserialize_event_binary::<MessagePack>(&bytes).unwrap();
+//! assert_eq!(serialize_event(&event).unwrap(), json);
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+
+use crate::process::OutHandler;
+use crate::time::{Date, EventTime};
+use crate::types::*;
+
+/*----------------------------------------------------------------------------*/
+// Errors
+
+#[derive(Debug)]
+pub enum Error {
+    /// The format could not encode or decode an event.
+    Format(Box<dyn std::error::Error + Send + Sync>),
+    /// Demographic `info`, `source` or `misc` was not valid JSON.
+    Json(serde_json::Error),
+    /// An encoded event does not fit in a frame, or a frame is longer than
+    /// the reader allows.
+    TooLarge(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Format(e) => write!(f, "{}", e),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::TooLarge(n) => write!(f, "frame of {} bytes is too large", n),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Formats
+
+/// A binary format for serde values.
+pub trait Encoding {
+    /// Appends the encoding of `value` to `out`.
+    fn encode<T: Serialize>(value: &T, out: &mut Vec<u8>) -> Result<(), Error>;
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// [MessagePack](https://msgpack.org), with structs as arrays (feature
+/// `msgpack`).
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Encoding for MessagePack {
+    fn encode<T: Serialize>(value: &T, out: &mut Vec<u8>) -> Result<(), Error> {
+        rmp_serde::encode::write(out, value).map_err(|e| Error::Format(e.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        rmp_serde::from_slice(bytes).map_err(|e| Error::Format(e.into()))
+    }
+}
+
+/// [CBOR](https://cbor.io) (feature `cbor`).
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Encoding for Cbor {
+    fn encode<T: Serialize>(value: &T, out: &mut Vec<u8>) -> Result<(), Error> {
+        ciborium::into_writer(value, out).map_err(|e| Error::Format(e.to_string().into()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        ciborium::from_reader(bytes).map_err(|e| Error::Format(e.to_string().into()))
+    }
+}
+
+/// [bincode](https://docs.rs/bincode/1) (feature `bincode`).
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Encoding for Bincode {
+    fn encode<T: Serialize>(value: &T, out: &mut Vec<u8>) -> Result<(), Error> {
+        bincode::serialize_into(out, value).map_err(|e| Error::Format(e))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        bincode::deserialize(bytes).map_err(|e| Error::Format(e))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Plain representation of events
+//
+// Unlike the JSON representation, these types need neither untagged enums,
+// flattened fields nor skipped fields, which formats without field names
+// (bincode, MessagePack arrays) cannot decode.
+
+#[derive(Serialize, Deserialize)]
+struct Wire<'a> {
+    p: WireSubject<'a>,
+    b: WireTime,
+    e: Option<WireTime>,
+    d: Cow<'a, str>,
+    concepts: Vec<Cow<'a, str>>,
+    patient_id: WireSubject<'a>,
+    time: WireInterval<'a>,
+    facts: WireFacts<'a>,
+    source: Option<Cow<'a, str>>,
+    misc: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireSubject<'a> {
+    Str(Cow<'a, str>),
+    Int(u64),
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireTime {
+    Date(i32),
+    Offset(i64),
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireInterval<'a> {
+    Int(u64, Option<u64>),
+    Str(Cow<'a, str>, Option<Cow<'a, str>>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireCode<'a> {
+    code: Cow<'a, str>,
+    codebook: Option<Codebook>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireClaim<'a> {
+    id: Cow<'a, str>,
+    r#type: Option<Cow<'a, str>>,
+    index: Option<i32>,
+    procedure: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireCost<'a> {
+    charge: Option<Cow<'a, str>>,
+    cost: Cow<'a, str>,
+    allowed: Option<Cow<'a, str>>,
+    transaction: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WireFill<'a> {
+    days_supply: Option<i32>,
+    quantity: Option<i32>,
+    strength: Option<Cow<'a, str>>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum WireFacts<'a> {
+    Claim(WireClaim<'a>, Option<Location>, Option<WireCost<'a>>),
+    Death,
+    /// The field and its info as JSON text.
+    Demographics(DemographicField, Option<String>),
+    Diagnosis(WireCode<'a>, Option<WireClaim<'a>>, Option<Location>),
+    Eligibility,
+    Enrollment,
+    /// The code, the value's text, number and units, the claim and location.
+    Labs(WireCode<'a>, Option<Cow<'a, str>>, Option<f64>, Cow<'a, str>, Option<WireClaim<'a>>, Option<Location>),
+    Medication(WireCode<'a>, Option<WireFill<'a>>, Option<Location>, Option<WireClaim<'a>>),
+    Procedure(WireCode<'a>, Option<WireClaim<'a>>, Option<Location>),
+    Undefined,
+}
+
+fn borrow(s: &str) -> Cow<'_, str> {
+    Cow::Borrowed(s)
+}
+
+fn borrow_opt(s: &Option<impl AsRef<str>>) -> Option<Cow<'_, str>> {
+    s.as_ref().map(|s| Cow::Borrowed(s.as_ref()))
+}
+
+fn owned(s: Cow<str>) -> Cow<'static, str> {
+    Cow::Owned(s.into_owned())
+}
+
+fn owned_opt(s: Option<Cow<str>>) -> Option<Cow<'static, str>> {
+    s.map(owned)
+}
+
+impl WireSubject<'_> {
+    fn from<'a>(id: &'a SubjectID) -> WireSubject<'a> {
+        match id {
+            SubjectID::IDstr(s) => WireSubject::Str(borrow(s)),
+            SubjectID::Idint(i) => WireSubject::Int(*i),
+        }
+    }
+
+    fn into_subject(self) -> SubjectID<'static> {
+        match self {
+            WireSubject::Str(s) => SubjectID::IDstr(owned(s)),
+            WireSubject::Int(i) => SubjectID::Idint(i),
+        }
+    }
+}
+
+impl WireTime {
+    fn from(t: EventTime) -> Self {
+        match t {
+            EventTime::Date(d) => WireTime::Date(d.days()),
+            EventTime::Offset(x) => WireTime::Offset(x),
+        }
+    }
+
+    fn into_time(self) -> EventTime {
+        match self {
+            WireTime::Date(d) => EventTime::Date(Date::from_days(d)),
+            WireTime::Offset(x) => EventTime::Offset(x),
+        }
+    }
+}
+
+impl WireCode<'_> {
+    fn from<'a>(c: &'a Code) -> WireCode<'a> {
+        WireCode { code: borrow(&c.code), codebook: c.codebook }
+    }
+
+    fn into_code(self) -> Code<'static> {
+        Code { code: owned(self.code), codebook: self.codebook }
+    }
+}
+
+impl WireClaim<'_> {
+    fn from<'a>(c: &'a Claim) -> WireClaim<'a> {
+        WireClaim {
+            id: borrow(&c.id),
+            r#type: borrow_opt(&c.r#type),
+            index: c.index,
+            procedure: borrow_opt(&c.procedure),
+        }
+    }
+
+    fn into_claim(self) -> Claim<'static> {
+        Claim {
+            id: owned(self.id),
+            r#type: self.r#type.map(Cow::into_owned),
+            index: self.index,
+            procedure: self.procedure.map(Cow::into_owned),
+        }
+    }
+}
+
+impl<'a> Wire<'a> {
+    fn from(event: &'a Event) -> Self {
+        let facts = match &event.context.facts {
+            Domain::Claim(x) => WireFacts::Claim(
+                WireClaim::from(&x.claim),
+                x.location,
+                x.cost.as_ref().map(|c| WireCost {
+                    charge: borrow_opt(&c.charge),
+                    cost: borrow(&c.cost),
+                    allowed: borrow_opt(&c.allowed),
+                    transaction: borrow_opt(&c.transaction),
+                }),
+            ),
+            Domain::Death(_) => WireFacts::Death,
+            Domain::Demographics(x) => WireFacts::Demographics(
+                x.demo.field,
+                x.demo.info.as_ref().map(|info| info.to_string()),
+            ),
+            Domain::Diagnosis(x) => WireFacts::Diagnosis(
+                WireCode::from(&x.code),
+                x.claim.as_ref().map(WireClaim::from),
+                x.location,
+            ),
+            Domain::Eligibility(_) => WireFacts::Eligibility,
+            Domain::Enrollment(_) => WireFacts::Enrollment,
+            Domain::Labs(x) => WireFacts::Labs(
+                WireCode::from(&x.code),
+                borrow_opt(&x.value.text),
+                x.value.number,
+                borrow(&x.value.units),
+                x.claim.as_ref().map(WireClaim::from),
+                x.location,
+            ),
+            Domain::Medication(x) => WireFacts::Medication(
+                WireCode::from(&x.code),
+                x.fill.as_ref().map(|f| WireFill {
+                    days_supply: f.days_supply,
+                    quantity: f.quantity,
+                    strength: borrow_opt(&f.strength),
+                }),
+                x.location,
+                x.claim.as_ref().map(WireClaim::from),
+            ),
+            Domain::Procedure(x) => WireFacts::Procedure(
+                WireCode::from(&x.code),
+                x.claim.as_ref().map(WireClaim::from),
+                x.location,
+            ),
+            Domain::Undefined(_) => WireFacts::Undefined,
+        };
+        let time = match &event.context.time {
+            Interval::IntervalInt { begin, end } => WireInterval::Int(*begin, *end),
+            Interval::IntervalStr { begin, end } => WireInterval::Str(borrow(begin), borrow_opt(end)),
+        };
+        Wire {
+            p: WireSubject::from(&event.p),
+            b: WireTime::from(event.b),
+            e: event.e.map(WireTime::from),
+            d: borrow(&event.d),
+            concepts: event.concepts.iter().map(|c| borrow(c)).collect(),
+            patient_id: WireSubject::from(&event.context.patient_id),
+            time,
+            facts,
+            source: event.context.source.as_deref().map(|raw| borrow(raw.get())),
+            misc: event.context.misc.as_deref().map(|raw| borrow(raw.get())),
+        }
+    }
+
+    fn into_event(self) -> Result<OwnedEvent, Error> {
+        let facts = match self.facts {
+            WireFacts::Claim(claim, location, cost) => Domain::Claim(ClaimFacts {
+                claim: claim.into_claim(),
+                location,
+                cost: cost.map(|c| Cost {
+                    charge: owned_opt(c.charge),
+                    cost: owned(c.cost),
+                    allowed: owned_opt(c.allowed),
+                    transaction: owned_opt(c.transaction),
+                }),
+            }),
+            WireFacts::Death => Domain::Death(DeathFacts {}),
+            WireFacts::Demographics(field, info) => Domain::Demographics(DemographicFacts {
+                demo: DemographicInfo {
+                    field,
+                    info: info.map(|info| serde_json::from_str(&info)).transpose().map_err(Error::Json)?,
+                },
+            }),
+            WireFacts::Diagnosis(code, claim, location) => Domain::Diagnosis(DiagnosisFacts {
+                code: code.into_code(),
+                claim: claim.map(WireClaim::into_claim),
+                location,
+            }),
+            WireFacts::Eligibility => Domain::Eligibility(EligibilityFacts {}),
+            WireFacts::Enrollment => Domain::Enrollment(EnrollmentFacts {}),
+            WireFacts::Labs(code, text, number, units, claim, location) => Domain::Labs(LabsFacts {
+                code: code.into_code(),
+                value: LabValue { text: owned_opt(text), number, units: owned(units) },
+                claim: claim.map(WireClaim::into_claim),
+                location,
+            }),
+            WireFacts::Medication(code, fill, location, claim) => Domain::Medication(MedicationFacts {
+                code: code.into_code(),
+                fill: fill.map(|f| Fill {
+                    days_supply: f.days_supply,
+                    quantity: f.quantity,
+                    strength: owned_opt(f.strength),
+                }),
+                location,
+                claim: claim.map(WireClaim::into_claim),
+            }),
+            WireFacts::Procedure(code, claim, location) => Domain::Procedure(ProcedureFacts {
+                code: code.into_code(),
+                claim: claim.map(WireClaim::into_claim),
+                location,
+            }),
+            WireFacts::Undefined => Domain::Undefined(UndefinedFacts {}),
+        };
+        let time = match self.time {
+            WireInterval::Int(begin, end) => Interval::IntervalInt { begin, end },
+            WireInterval::Str(begin, end) => Interval::IntervalStr {
+                begin: begin.into_owned(),
+                end: end.map(Cow::into_owned),
+            },
+        };
+        let raw = |s: Option<Cow<str>>| {
+            s.map(|s| RawValue::from_string(s.into_owned()).map(Cow::Owned))
+                .transpose()
+                .map_err(Error::Json)
+        };
+        Ok(Event {
+            p: self.p.into_subject(),
+            b: self.b.into_time(),
+            e: self.e.map(WireTime::into_time),
+            d: owned(self.d),
+            concepts: self.concepts.into_iter().map(Cow::into_owned).collect(),
+            context: Context {
+                patient_id: self.patient_id.into_subject(),
+                time,
+                facts,
+                source: raw(self.source)?,
+                misc: raw(self.misc)?,
+            },
+        })
+    }
+}
+
+/// Appends the encoding of an event to `out`.
+pub fn encode<F: Encoding>(event: &Event, out: &mut Vec<u8>) -> Result<(), Error> {
+    F::encode(&Wire::from(event), out)
+}
+
+/// Decodes an event encoded by [`encode`].
+pub fn decode<F: Encoding>(bytes: &[u8]) -> Result<OwnedEvent, Error> {
+    F::decode::<Wire>(bytes)?.into_event()
+}
+
+/*----------------------------------------------------------------------------*/
+// Framing
+
+/// Writes events as length-prefixed frames.
+pub struct FrameWriter<F, W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+    format: PhantomData<F>,
+}
+
+impl<F: Encoding, W: Write> FrameWriter<F, W> {
+    pub fn new(writer: W) -> Self {
+        FrameWriter { writer, buffer: Vec::new(), format: PhantomData }
+    }
+
+    pub fn write(&mut self, event: &Event) -> Result<(), Error> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&[0; 4]);
+        encode::<F>(event, &mut self.buffer)?;
+        let len = self.buffer.len() - 4;
+        let prefix = u32::try_from(len).map_err(|_| Error::TooLarge(len))?;
+        self.buffer[..4].copy_from_slice(&prefix.to_le_bytes());
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<'a, F: Encoding, W: Write> OutHandler<'a> for FrameWriter<F, W> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        self.write(&event).map_err(|e| match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// The largest frame that [`FrameReader::new`] reads: 64 MiB.
+pub const MAX_FRAME: usize = 64 << 20;
+
+/// Reads the events of a stream of frames written by [`FrameWriter`].
+pub struct FrameReader<F, R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    max_frame: usize,
+    format: PhantomData<F>,
+}
+
+impl<F: Encoding, R: Read> FrameReader<F, R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_max_frame(reader, MAX_FRAME)
+    }
+
+    /// A reader that fails with [`Error::TooLarge`] at a frame longer than
+    /// `max_frame` bytes, rather than reading it.
+    pub fn with_max_frame(reader: R, max_frame: usize) -> Self {
+        FrameReader { reader, buffer: Vec::new(), max_frame, format: PhantomData }
+    }
+
+    /// Reads the next event, or `None` at the end of the stream.
+    pub fn read(&mut self) -> Result<Option<OwnedEvent>, Error> {
+        let mut prefix = [0; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.reader.read(&mut prefix[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let len = u32::from_le_bytes(prefix) as usize;
+        if len > self.max_frame {
+            return Err(Error::TooLarge(len));
+        }
+        // The buffer grows with the bytes read, not with the prefix, so a
+        // truncated stream does not allocate the whole frame.
+        self.buffer.clear();
+        (&mut self.reader).take(len as u64).read_to_end(&mut self.buffer)?;
+        if self.buffer.len() < len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        decode::<F>(&self.buffer).map(Some)
+    }
+}
+
+impl<F: Encoding, R: Read> Iterator for FrameReader<F, R> {
+    type Item = Result<OwnedEvent, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+
+#[cfg(all(test, any(feature = "msgpack", feature = "cbor", feature = "bincode")))]
+mod test_binary {
+    use crate::binary::*;
+    use crate::test_support::EVENTS;
+
+    /// Encodes every event alone and in a stream of frames, and checks that
+    /// each decodes to the same JSON.
+    fn round_trip<F: Encoding>() {
+        let mut writer = FrameWriter::<F, _>::new(Vec::new());
+        for line in EVENTS.lines() {
+            let event: Event = serde_json::from_str(line).unwrap();
+            let mut bytes = Vec::new();
+            encode::<F>(&event, &mut bytes).unwrap();
+            assert_eq!(serde_json::to_string(&decode::<F>(&bytes).unwrap()).unwrap(), line);
+            writer.write_event(event).unwrap();
+        }
+        let stream = writer.into_inner();
+        let events = FrameReader::<F, _>::new(&stream[..])
+            .map(|e| serde_json::to_string(&e.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(events, EVENTS.lines().collect::<Vec<_>>());
+
+        let mut truncated = FrameReader::<F, _>::new(&stream[..stream.len() - 1]);
+        assert!(matches!(truncated.nth(EVENTS.lines().count() - 1), Some(Err(Error::Io(_)))));
+
+        let first = u32::from_le_bytes([stream[0], stream[1], stream[2], stream[3]]) as usize;
+        let mut small = FrameReader::<F, _>::with_max_frame(&stream[..], first - 1);
+        assert!(matches!(small.next(), Some(Err(Error::TooLarge(n))) if n == first));
+        let mut huge = FrameReader::<F, _>::new(&[0xff, 0xff, 0xff, 0x7f, 0][..]);
+        assert!(matches!(huge.next(), Some(Err(Error::TooLarge(0x7fff_ffff)))));
+        let mut short = FrameReader::<F, _>::with_max_frame(&[0xff, 0xff, 0xff, 0x7f, 0][..], usize::MAX);
+        assert!(matches!(short.next(), Some(Err(Error::Io(e))) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    #[cfg(feature = "msgpack")]
+    fn test_msgpack() {
+        round_trip::<MessagePack>();
+    }
+
+    #[test]
+    #[cfg(feature = "cbor")]
+    fn test_cbor() {
+        round_trip::<Cbor>();
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn test_bincode() {
+        round_trip::<Bincode>();
+    }
+}
@@ -0,0 +1,711 @@
+//! Codebook-specific handling of `Code`s.
+//!
+//! [`Codebook::check`] and [`Code::check`] test that a code has the syntax of
+//! its codebook. They check shape only: a well-formed code may still be
+//! absent from the codebook.
+//!
+//! | Codebook       | Accepted forms                                            |
+//! |----------------|-----------------------------------------------------------|
+//! | `ICD9`         | `DDD`, `VDD`, `EDDD`, each optionally followed by up to 2 (`E`: 1) digits, with or without a dot |
+//! | `ICD9PC`       | `DD` optionally followed by up to 2 digits, with or without a dot |
+//! | `ICD10`        | a letter, a digit, a letter or digit, then up to 4 letters or digits, with or without a dot |
+//! | `ICD10PC`      | 7 letters or digits, without `I` or `O`                   |
+//! | `CPT`          | 4 digits followed by a digit or `F`, `M`, `T`, `U`        |
+//! | `HCPCS`        | a letter `A`-`V` followed by 4 digits, or a CPT code      |
+//! | `CDT`          | `D` followed by 4 digits                                  |
+//! | `LOINC`        | 1-7 digits, `-`, and a valid mod-10 check digit           |
+//! | `NDC`          | 10 or 11 digits, or hyphenated 4-4-2, 5-3-2, 5-4-1 or 5-4-2 |
+//! | `NDC9`         | 9 digits, or hyphenated 4-4, 5-3 or 5-4                   |
+//! | `US_STATE`     | a USPS state or territory abbreviation, or a 2-digit FIPS state code |
+//!
+//! Codes in the `medicaid_cat`, `NABSP` and `UB92` codebooks are not checked.
+//!
+//! [`Code::normalize_ndc`] converts NDCs to a canonical form, and the
+//! [`NormalizeNdc`] processor does so for every event in a pipeline.
+//! [`Code::normalize_icd`] puts ICD codes in a canonical dotted or undotted
+//! form, and [`Code::is_descendant_of`] matches ICD codes to categories.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::process::Processor;
+use crate::types::{Code, Codebook, Event};
+
+/*----------------------------------------------------------------------------*/
+// Errors
+
+/// Why a code does not have the syntax of its codebook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeErrorKind {
+    /// The code has the wrong number of characters.
+    Length,
+    /// The code has characters that cannot appear at their position.
+    Format,
+    /// The code's check digit is wrong.
+    CheckDigit,
+    /// The code is not a known value (e.g. a state abbreviation).
+    Unknown,
+}
+
+/// A code that does not have the syntax of its codebook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeError {
+    pub codebook: Codebook,
+    pub code: String,
+    pub kind: CodeErrorKind,
+}
+
+impl fmt::Display for CodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let why = match self.kind {
+            CodeErrorKind::Length     => "has the wrong length",
+            CodeErrorKind::Format     => "is malformed",
+            CodeErrorKind::CheckDigit => "has a wrong check digit",
+            CodeErrorKind::Unknown    => "is not a known code",
+        };
+        write!(f, "{} code {:?} {}", self.codebook, self.code, why)
+    }
+}
+
+impl std::error::Error for CodeError {}
+
+/*----------------------------------------------------------------------------*/
+// Checks
+
+impl Codebook {
+    /// The name of the codebook, as it is serialized.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codebook::CDT         => "CDT",
+            Codebook::CPT         => "CPT",
+            Codebook::HCPCS       => "HCPCS",
+            Codebook::ICD9        => "ICD9",
+            Codebook::ICD9PC      => "ICD9PC",
+            Codebook::ICD10       => "ICD10",
+            Codebook::ICD10PC     => "ICD10PC",
+            Codebook::LOINC       => "LOINC",
+            Codebook::MedicaidCat => "medicaid_cat",
+            Codebook::NABSP       => "NABSP",
+            Codebook::NDC         => "NDC",
+            Codebook::NDC9        => "NDC9",
+            Codebook::UB92        => "UB92",
+            Codebook::USSTATE     => "US_STATE",
+        }
+    }
+
+    /// Checks that `code` has the syntax of this codebook.
+    pub fn check(&self, code: &str) -> Result<(), CodeError> {
+        let result = match self {
+            Codebook::CDT     => check_cdt(code),
+            Codebook::CPT     => check_cpt(code),
+            Codebook::HCPCS   => check_hcpcs(code),
+            Codebook::ICD9    => check_icd9(code),
+            Codebook::ICD9PC  => check_icd9pc(code),
+            Codebook::ICD10   => check_icd10(code),
+            Codebook::ICD10PC => check_icd10pc(code),
+            Codebook::LOINC   => check_loinc(code),
+            Codebook::NDC     => check_ndc(code),
+            Codebook::NDC9    => check_ndc9(code),
+            Codebook::USSTATE => check_us_state(code),
+            Codebook::MedicaidCat | Codebook::NABSP | Codebook::UB92 => Ok(()),
+        };
+        result.map_err(|kind| CodeError {
+            codebook: *self,
+            code: code.to_string(),
+            kind,
+        })
+    }
+}
+
+impl fmt::Display for Codebook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Code<'_> {
+    /// Checks that the code has the syntax of its codebook. Codes without a
+    /// codebook are not checked.
+    pub fn check(&self) -> Result<(), CodeError> {
+        match self.codebook {
+            Some(cb) => cb.check(&self.code),
+            None => Ok(()),
+        }
+    }
+}
+
+type Check = Result<(), CodeErrorKind>;
+
+fn digits(s: &str) -> bool {
+    s.bytes().all(|c| c.is_ascii_digit())
+}
+
+fn alnum(s: &str) -> bool {
+    s.bytes().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+}
+
+fn require(ok: bool, kind: CodeErrorKind) -> Check {
+    if ok { Ok(()) } else { Err(kind) }
+}
+
+/// Splits an ICD code at its dot, if it has one, after checking that the dot
+/// is right after the `head` characters.
+fn split_dot(code: &str, head: usize) -> Result<(&str, &str), CodeErrorKind> {
+    require(code.is_ascii(), CodeErrorKind::Format)?;
+    match code.find('.') {
+        Some(i) if i == head && i + 1 < code.len() => Ok((&code[..i], &code[i + 1..])),
+        Some(_) => Err(CodeErrorKind::Format),
+        None if code.len() >= head => Ok(code.split_at(head)),
+        None => Err(CodeErrorKind::Length),
+    }
+}
+
+fn check_icd9(code: &str) -> Check {
+    let (head_len, max_tail) = match code.as_bytes().first() {
+        Some(b'E') => (4, 1),
+        _          => (3, 2),
+    };
+    let (head, tail) = split_dot(code, head_len)?;
+    require(tail.len() <= max_tail, CodeErrorKind::Length)?;
+    let head_ok = match head.as_bytes()[0] {
+        b'E' | b'V' => digits(&head[1..]),
+        _ => digits(head),
+    };
+    require(head_ok && digits(tail), CodeErrorKind::Format)
+}
+
+fn check_icd9pc(code: &str) -> Check {
+    let (head, tail) = split_dot(code, 2)?;
+    require(tail.len() <= 2, CodeErrorKind::Length)?;
+    require(digits(head) && digits(tail), CodeErrorKind::Format)
+}
+
+fn check_icd10(code: &str) -> Check {
+    let (head, tail) = split_dot(code, 3)?;
+    require(tail.len() <= 4, CodeErrorKind::Length)?;
+    let b = head.as_bytes();
+    require(b[0].is_ascii_uppercase() && b[1].is_ascii_digit() && alnum(&head[2..])
+            && alnum(tail), CodeErrorKind::Format)
+}
+
+fn check_icd10pc(code: &str) -> Check {
+    require(code.len() == 7, CodeErrorKind::Length)?;
+    require(alnum(code) && !code.contains(['I', 'O']),
+            CodeErrorKind::Format)
+}
+
+fn check_cpt(code: &str) -> Check {
+    require(code.len() == 5, CodeErrorKind::Length)?;
+    require(code.is_ascii(), CodeErrorKind::Format)?;
+    require(digits(&code[..4]) && matches!(code.as_bytes()[4],
+            b'0'..=b'9' | b'F' | b'M' | b'T' | b'U'), CodeErrorKind::Format)
+}
+
+fn check_hcpcs(code: &str) -> Check {
+    require(code.len() == 5, CodeErrorKind::Length)?;
+    require(code.is_ascii(), CodeErrorKind::Format)?;
+    match code.as_bytes()[0] {
+        b'A'..=b'V' => require(digits(&code[1..]), CodeErrorKind::Format),
+        _ => check_cpt(code),
+    }
+}
+
+fn check_cdt(code: &str) -> Check {
+    require(code.len() == 5, CodeErrorKind::Length)?;
+    require(code.is_ascii(), CodeErrorKind::Format)?;
+    require(code.starts_with('D') && digits(&code[1..]), CodeErrorKind::Format)
+}
+
+/// The mod-10 check digit that LOINC appends to a code's digits.
+fn loinc_check_digit(digits: &str) -> u32 {
+    let sum : u32 = digits.bytes().rev().enumerate().map(|(i, c)| {
+        let d = u32::from(c - b'0');
+        if i % 2 == 0 { let d = d * 2; d / 10 + d % 10 } else { d }
+    }).sum();
+    (10 - sum % 10) % 10
+}
+
+fn check_loinc(code: &str) -> Check {
+    let (body, check) = code.split_once('-').ok_or(CodeErrorKind::Format)?;
+    require(!body.is_empty() && body.len() <= 7 && check.len() == 1,
+            CodeErrorKind::Length)?;
+    require(digits(body) && digits(check), CodeErrorKind::Format)?;
+    require(u32::from(check.as_bytes()[0] - b'0') == loinc_check_digit(body),
+            CodeErrorKind::CheckDigit)
+}
+
+/// Checks that `code` is all digits with one of the lengths in `plain`, or
+/// hyphenated with segment lengths matching one of `layouts`.
+fn check_segments(code: &str, plain: &[usize], layouts: &[&[usize]]) -> Check {
+    if !code.contains('-') {
+        require(plain.contains(&code.len()), CodeErrorKind::Length)?;
+        return require(digits(code), CodeErrorKind::Format);
+    }
+    let segments : Vec<&str> = code.split('-').collect();
+    require(segments.iter().all(|s| digits(s)), CodeErrorKind::Format)?;
+    let lengths : Vec<usize> = segments.iter().map(|s| s.len()).collect();
+    require(layouts.iter().any(|l| *l == &lengths[..]), CodeErrorKind::Format)
+}
+
+fn check_ndc(code: &str) -> Check {
+    check_segments(code, &[10, 11], &[&[4, 4, 2], &[5, 3, 2], &[5, 4, 1], &[5, 4, 2]])
+}
+
+fn check_ndc9(code: &str) -> Check {
+    check_segments(code, &[9], &[&[4, 4], &[5, 3], &[5, 4]])
+}
+
+const US_STATES: [&str; 57] = [
+    "AK", "AL", "AR", "AS", "AZ", "CA", "CO", "CT", "DC", "DE", "FL", "GA",
+    "GU", "HI", "IA", "ID", "IL", "IN", "KS", "KY", "LA", "MA", "MD", "ME",
+    "MI", "MN", "MO", "MP", "MS", "MT", "NC", "ND", "NE", "NH", "NJ", "NM",
+    "NV", "NY", "OH", "OK", "OR", "PA", "PR", "RI", "SC", "SD", "TN", "TX",
+    "UM", "UT", "VA", "VI", "VT", "WA", "WI", "WV", "WY",
+];
+
+const US_STATE_FIPS: [&str; 57] = [
+    "01", "02", "04", "05", "06", "08", "09", "10", "11", "12", "13", "15",
+    "16", "17", "18", "19", "20", "21", "22", "23", "24", "25", "26", "27",
+    "28", "29", "30", "31", "32", "33", "34", "35", "36", "37", "38", "39",
+    "40", "41", "42", "44", "45", "46", "47", "48", "49", "50", "51", "53",
+    "54", "55", "56", "60", "66", "69", "72", "74", "78",
+];
+
+fn check_us_state(code: &str) -> Check {
+    require(code.len() == 2, CodeErrorKind::Length)?;
+    require(US_STATES.contains(&code) || US_STATE_FIPS.contains(&code),
+            CodeErrorKind::Unknown)
+}
+
+/*----------------------------------------------------------------------------*/
+// NDC normalization
+
+/// Why an NDC could not be normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NdcError {
+    /// The code's codebook is not `NDC` or `NDC9`.
+    NotNdc(Option<Codebook>),
+    /// The code does not have the syntax of its codebook.
+    Invalid(CodeError),
+    /// The code is unhyphenated and short, so its layout cannot be known.
+    /// `candidates` are its normalized forms under each possible layout.
+    Ambiguous { code: String, candidates: Vec<String> },
+}
+
+impl fmt::Display for NdcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NdcError::NotNdc(Some(cb)) => write!(f, "{} code is not an NDC", cb),
+            NdcError::NotNdc(None) => write!(f, "code without a codebook is not an NDC"),
+            NdcError::Invalid(e) => e.fmt(f),
+            NdcError::Ambiguous { code, candidates } =>
+                write!(f, "NDC {:?} is ambiguous: could be any of {}",
+                       code, candidates.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for NdcError {}
+
+/// Pads each hyphenated segment of `code` to the widths in `widths`, or
+/// returns all candidate paddings of an unhyphenated code of length `short`.
+fn pad_segments(code: &str, widths: &[usize], layouts: &[&[usize]], short: usize)
+                -> Result<String, NdcError> {
+    let pad = |segments: &[&str]| -> String {
+        segments.iter().zip(widths)
+            .map(|(s, w)| format!("{:0>w$}", s, w = w))
+            .collect()
+    };
+
+    if code.contains('-') {
+        let segments : Vec<&str> = code.split('-').collect();
+        return Ok(pad(&segments));
+    }
+    if code.len() != short {
+        return Ok(code.to_string());
+    }
+
+    let candidates = layouts.iter().map(|layout| {
+        let mut rest = code;
+        let segments : Vec<&str> = layout.iter().map(|n| {
+            let (s, r) = rest.split_at(*n);
+            rest = r;
+            s
+        }).collect();
+        pad(&segments)
+    }).collect();
+    Err(NdcError::Ambiguous { code: code.to_string(), candidates })
+}
+
+impl Code<'_> {
+    /// Normalizes an NDC to its canonical form: 11 digits (5-4-2, without
+    /// hyphens) for `NDC` codes and 9 digits (5-4) for `NDC9` codes.
+    ///
+    /// Hyphenated codes are padded with zeros segment by segment. Unhyphenated
+    /// 10-digit NDCs (and 8-digit NDC9s) are ambiguous, since any segment may
+    /// be the short one.
+    pub fn normalize_ndc(&self) -> Result<Code<'static>, NdcError> {
+        let codebook = match self.codebook {
+            Some(cb @ Codebook::NDC) | Some(cb @ Codebook::NDC9) => cb,
+            other => return Err(NdcError::NotNdc(other)),
+        };
+
+        let code = &self.code;
+        let normalized = if codebook == Codebook::NDC {
+            codebook.check(code).map_err(NdcError::Invalid)?;
+            pad_segments(code, &[5, 4, 2], &[&[4, 4, 2], &[5, 3, 2], &[5, 4, 1]], 10)?
+        } else if !code.contains('-') && code.len() == 8 && digits(code) {
+            pad_segments(code, &[5, 4], &[&[4, 4], &[5, 3]], 8)?
+        } else {
+            codebook.check(code).map_err(NdcError::Invalid)?;
+            pad_segments(code, &[5, 4], &[], 8)?
+        };
+
+        Ok(Code { code: normalized.into(), codebook: Some(codebook) })
+    }
+
+    /// The NDC9 product code (labeler and product) of an `NDC` or `NDC9` code.
+    pub fn ndc9(&self) -> Result<Code<'static>, NdcError> {
+        let mut code = self.normalize_ndc()?;
+        if let Cow::Owned(s) = &mut code.code {
+            s.truncate(9);
+        }
+        code.codebook = Some(Codebook::NDC9);
+        Ok(code)
+    }
+}
+
+/// A processor that normalizes the NDC of every event whose code is in the
+/// `NDC` or `NDC9` codebook (see [`Code::normalize_ndc`]).
+///
+/// Codes that cannot be normalized are left as they are and passed to
+/// `on_error`.
+pub struct NormalizeNdc<F> {
+    on_error: F,
+}
+
+impl<F: FnMut(NdcError)> NormalizeNdc<F> {
+    pub fn new(on_error: F) -> Self {
+        NormalizeNdc { on_error }
+    }
+}
+
+impl<F: FnMut(NdcError)> Processor for NormalizeNdc<F> {
+    fn process<'a>(&mut self, mut event: Event<'a>) -> Option<Event<'a>> {
+        if let Some(code) = event.context.facts.code_mut() {
+            if matches!(code.codebook, Some(Codebook::NDC) | Some(Codebook::NDC9)) {
+                match code.normalize_ndc() {
+                    Ok(c) => *code = c,
+                    Err(e) => (self.on_error)(e),
+                }
+            }
+        }
+        Some(event)
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// ICD codes
+
+/// Whether a normalized ICD code has a dot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dots {
+    /// E.g. `250.00`, `E11.65`.
+    With,
+    /// E.g. `25000`, `E1165`.
+    Without,
+}
+
+/// Why an ICD code could not be normalized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcdError {
+    /// The code's codebook is not `ICD9`, `ICD9PC`, `ICD10` or `ICD10PC`.
+    NotIcd(Option<Codebook>),
+    /// The code, without dots, does not have the syntax of its codebook.
+    Invalid(CodeError),
+}
+
+impl fmt::Display for IcdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IcdError::NotIcd(Some(cb)) => write!(f, "{} code is not an ICD code", cb),
+            IcdError::NotIcd(None) => write!(f, "code without a codebook is not an ICD code"),
+            IcdError::Invalid(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for IcdError {}
+
+impl Codebook {
+    pub fn is_icd(&self) -> bool {
+        matches!(self, Codebook::ICD9 | Codebook::ICD9PC | Codebook::ICD10 | Codebook::ICD10PC)
+    }
+
+    /// The number of characters before the dot of a (undotted) ICD code, or
+    /// `None` if codes of this codebook have no dot.
+    fn icd_dot_position(&self, code: &str) -> Option<usize> {
+        match self {
+            Codebook::ICD9 if code.starts_with('E') => Some(4),
+            Codebook::ICD9 | Codebook::ICD10 => Some(3),
+            Codebook::ICD9PC => Some(2),
+            _ => None,
+        }
+    }
+}
+
+/// Removes dots and surrounding whitespace and converts to upper case.
+pub(crate) fn strip_dots(code: &str) -> String {
+    code.trim().chars()
+        .filter(|c| *c != '.')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+impl Code<'_> {
+    /// Normalizes an ICD code: removes any dots (wherever they are), converts
+    /// to upper case and, for `Dots::With`, puts a dot where its codebook
+    /// expects one. `ICD10PC` codes never have dots.
+    ///
+    /// So `"z2.1"` becomes `"Z21"`, and `"25000"` becomes `"250.00"` with
+    /// dots or `"250.00"` becomes `"25000"` without.
+    pub fn normalize_icd(&self, dots: Dots) -> Result<Code<'static>, IcdError> {
+        let codebook = match self.codebook {
+            Some(cb) if cb.is_icd() => cb,
+            other => return Err(IcdError::NotIcd(other)),
+        };
+
+        let mut code = strip_dots(&self.code);
+        codebook.check(&code).map_err(IcdError::Invalid)?;
+        if dots == Dots::With {
+            match codebook.icd_dot_position(&code) {
+                Some(i) if i < code.len() => code.insert(i, '.'),
+                _ => (),
+            }
+        }
+
+        Ok(Code { code: code.into(), codebook: Some(codebook) })
+    }
+
+    /// Whether this ICD code is in the category `category` (or is the
+    /// category itself), i.e. whether, ignoring dots and case, the code
+    /// starts with the category.
+    ///
+    /// Categories are prefixes and need not be valid codes: `"E11"`, `"E1"`
+    /// and `"250.0"` are all categories. Codes in different codebooks, and
+    /// codes that are not ICD codes, are never descendants.
+    pub fn is_descendant_of(&self, category: &Code) -> bool {
+        match self.codebook {
+            Some(cb) if cb.is_icd() && category.codebook == Some(cb) =>
+                strip_dots(&self.code).starts_with(&strip_dots(&category.code)),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_check {
+    use crate::codes::*;
+
+    fn ok(cb: Codebook, codes: &[&str]) {
+        for c in codes {
+            assert_eq!(cb.check(c), Ok(()), "{} {}", cb, c);
+        }
+    }
+
+    fn err(cb: Codebook, kind: CodeErrorKind, codes: &[&str]) {
+        for c in codes {
+            assert_eq!(cb.check(c).map_err(|e| e.kind), Err(kind), "{} {}", cb, c);
+        }
+    }
+
+    #[test]
+    fn test_icd() {
+        ok(Codebook::ICD9, &["250", "250.00", "25000", "V45.81", "E950.0", "E9500"]);
+        err(Codebook::ICD9, CodeErrorKind::Format, &["25A", "2.50", "E95.00"]);
+        err(Codebook::ICD9, CodeErrorKind::Length, &["25", "250001"]);
+
+        ok(Codebook::ICD9PC, &["45", "45.13", "4513"]);
+        err(Codebook::ICD9PC, CodeErrorKind::Length, &["45131"]);
+
+        ok(Codebook::ICD10, &["Z21", "E11.65", "E1165", "S72.001A"]);
+        err(Codebook::ICD10, CodeErrorKind::Format, &["121", "Z2.1", "e11.65"]);
+        err(Codebook::ICD10, CodeErrorKind::Length, &["Z2", "S72.001AB"]);
+
+        ok(Codebook::ICD10PC, &["0016070", "B2151ZZ"]);
+        err(Codebook::ICD10PC, CodeErrorKind::Format, &["0O16070"]);
+        err(Codebook::ICD10PC, CodeErrorKind::Length, &["001607"]);
+    }
+
+    #[test]
+    fn test_procedures() {
+        ok(Codebook::CPT, &["99213", "0001F", "0019T"]);
+        err(Codebook::CPT, CodeErrorKind::Format, &["J1234", "9921A"]);
+        ok(Codebook::HCPCS, &["J1234", "99213"]);
+        err(Codebook::HCPCS, CodeErrorKind::Format, &["Z1234"]);
+        ok(Codebook::CDT, &["D0120"]);
+        err(Codebook::CDT, CodeErrorKind::Length, &["D012"]);
+        err(Codebook::CPT, CodeErrorKind::Format, &["\u{e9}123"]);
+    }
+
+    #[test]
+    fn test_loinc() {
+        ok(Codebook::LOINC, &["2345-7", "718-7", "4548-4"]);
+        err(Codebook::LOINC, CodeErrorKind::CheckDigit, &["2345-6"]);
+        err(Codebook::LOINC, CodeErrorKind::Format, &["23457"]);
+    }
+
+    #[test]
+    fn test_ndc() {
+        ok(Codebook::NDC, &["0002-1433-80", "12345-6789-1", "50090-347-01", "00002143380", "0002143380"]);
+        err(Codebook::NDC, CodeErrorKind::Format, &["002-1433-80", "0002-14A3-80"]);
+        err(Codebook::NDC, CodeErrorKind::Length, &["000214338"]);
+        ok(Codebook::NDC9, &["000021433", "0002-1433", "50090-347"]);
+        err(Codebook::NDC9, CodeErrorKind::Length, &["00002143380"]);
+    }
+
+    #[test]
+    fn test_us_state() {
+        ok(Codebook::USSTATE, &["NC", "PR", "37", "06"]);
+        err(Codebook::USSTATE, CodeErrorKind::Unknown, &["XX", "03", "nc"]);
+    }
+
+    #[test]
+    fn test_code_error() {
+        use crate::types::Code;
+        let code = Code { code: "Z2.1".into(), codebook: Some(Codebook::ICD10) };
+        let e = code.check().unwrap_err();
+        assert_eq!(e.codebook, Codebook::ICD10);
+        assert_eq!(e.code, "Z2.1");
+        assert_eq!(e.to_string(), "ICD10 code \"Z2.1\" is malformed");
+
+        let code = Code { code: "anything".into(), codebook: None };
+        assert!(code.check().is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_ndc {
+    use crate::codes::*;
+
+    fn ndc(code: &str) -> Code<'_> {
+        Code { code: code.into(), codebook: Some(Codebook::NDC) }
+    }
+
+    fn ndc9(code: &str) -> Code<'_> {
+        Code { code: code.into(), codebook: Some(Codebook::NDC9) }
+    }
+
+    #[test]
+    fn test_normalize() {
+        for (code, expected) in [("0002-1433-80", "00002143380"),
+                                 ("50090-347-01", "50090034701"),
+                                 ("12345-6789-1", "12345678901"),
+                                 ("12345-6789-01", "12345678901"),
+                                 ("00002143380", "00002143380")].iter() {
+            assert_eq!(ndc(code).normalize_ndc().unwrap().code, *expected);
+        }
+
+        assert_eq!(ndc9("0002-1433").normalize_ndc().unwrap().code, "000021433");
+        assert_eq!(ndc9("50090-347").normalize_ndc().unwrap().code, "500900347");
+    }
+
+    #[test]
+    fn test_ndc9() {
+        let code = ndc("0002-1433-80").ndc9().unwrap();
+        assert_eq!(code.code, "000021433");
+        assert_eq!(code.codebook, Some(Codebook::NDC9));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(ndc("0002143380").normalize_ndc(), Err(NdcError::Ambiguous {
+            code: "0002143380".to_string(),
+            candidates: vec!["00002143380".to_string(),
+                             "00021043380".to_string(),
+                             "00021433800".to_string()],
+        }));
+        assert!(matches!(ndc9("00021433").normalize_ndc(),
+                         Err(NdcError::Ambiguous { .. })));
+        assert!(matches!(ndc("0002-14A3-80").normalize_ndc(),
+                         Err(NdcError::Invalid(_))));
+
+        let code = Code { code: "Z21".into(), codebook: Some(Codebook::ICD10) };
+        assert_eq!(code.normalize_ndc(), Err(NdcError::NotNdc(Some(Codebook::ICD10))));
+    }
+
+    #[test]
+    fn test_processor() {
+        use crate::process::*;
+
+        let json = "\
+        [\"xyz\",0,null,\"Medication\",[],\
+         {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":null},\
+          \"domain\":\"Medication\",\
+          \"facts\":{\"code\":{\"code\":\"0002-1433-80\",\"codebook\":\"NDC\"}}}]\n\
+        [\"xyz\",0,null,\"Medication\",[],\
+         {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":null},\
+          \"domain\":\"Medication\",\
+          \"facts\":{\"code\":{\"code\":\"0002143380\",\"codebook\":\"NDC\"}}}]\n";
+
+        let mut errors = Vec::new();
+        let mut out : Vec<Event> = Vec::new();
+        process_str(json, &mut NormalizeNdc::new(|e| errors.push(e)), &mut out).unwrap();
+
+        let codes : Vec<&str> = out.iter()
+            .map(|e| e.context.facts.code().unwrap().code.as_ref())
+            .collect();
+        assert_eq!(codes, vec!["00002143380", "0002143380"]);
+        assert_eq!(errors.len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_icd {
+    use crate::codes::*;
+
+    fn code(cb: Codebook, code: &str) -> Code<'_> {
+        Code { code: code.into(), codebook: Some(cb) }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let cases = [
+            (Codebook::ICD9,    "250.00",   "250.00",   "25000"),
+            (Codebook::ICD9,    "25000",    "250.00",   "25000"),
+            (Codebook::ICD9,    "250",      "250",      "250"),
+            (Codebook::ICD9,    "E9500",    "E950.0",   "E9500"),
+            (Codebook::ICD9PC,  "4513",     "45.13",    "4513"),
+            (Codebook::ICD10,   "Z2.1",     "Z21",      "Z21"),
+            (Codebook::ICD10,   " e11.65 ", "E11.65",   "E1165"),
+            (Codebook::ICD10PC, "0016070",  "0016070",  "0016070"),
+        ];
+        for (cb, input, with, without) in cases.iter() {
+            let c = code(*cb, input);
+            assert_eq!(c.normalize_icd(Dots::With).unwrap().code, *with);
+            assert_eq!(c.normalize_icd(Dots::Without).unwrap().code, *without);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(code(Codebook::NDC, "00002143380").normalize_icd(Dots::With),
+                   Err(IcdError::NotIcd(Some(Codebook::NDC))));
+        assert!(matches!(code(Codebook::ICD10, "Z2").normalize_icd(Dots::With),
+                         Err(IcdError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_descendant() {
+        let e11 = code(Codebook::ICD10, "E11");
+        assert!(code(Codebook::ICD10, "E11.65").is_descendant_of(&e11));
+        assert!(code(Codebook::ICD10, "E1165").is_descendant_of(&e11));
+        assert!(code(Codebook::ICD10, "E11").is_descendant_of(&e11));
+        assert!(!code(Codebook::ICD10, "E10.9").is_descendant_of(&e11));
+        assert!(!code(Codebook::ICD9, "E1165").is_descendant_of(&e11));
+
+        let dm = code(Codebook::ICD9, "250.0");
+        assert!(code(Codebook::ICD9, "25000").is_descendant_of(&dm));
+        assert!(!code(Codebook::ICD9, "250.10").is_descendant_of(&dm));
+    }
+}
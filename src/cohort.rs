@@ -0,0 +1,329 @@
+//! Cohorts: index dates of subjects selected by criteria over their timelines.
+//!
+//! A [`Cohort`] is a declarative definition, usually read from JSON:
+//!
+//! ```json
+//! {
+//!   "index": {"concept": "diabetes"},
+//!   "occurrence": "first",
+//!   "criteria": [
+//!     {"name": "prior metformin", "events": {"concept": "metformin"},
+//!      "window": [-365, -1]},
+//!     {"name": "no prior insulin", "events": {"concept": "insulin"},
+//!      "window": [null, -1], "exclude": true},
+//!     {"name": "two visits", "events": {"domain": "Diagnosis"},
+//!      "window": [-365, 0], "at_least": 2}
+//!   ]
+//! }
+//! ```
+//!
+//! The begins of the events matching `index` are candidate index dates: the
+//! first of them, or every distinct one. Each criterion keeps an index date if
+//! at least `at_least` (by default 1) events match it with begins in `window`,
+//! days relative to the index date and inclusive at both ends, where `null` is
+//! unbounded. An `exclude` criterion keeps an index date if fewer events
+//! match. Criteria are applied in order.
+//!
+//! [`Cohort::apply`] finds the index dates of one timeline, counting how many
+//! subjects and index dates remain after each step in an [`Attrition`].
+//!
+//! Example:
+//! ```
+//! use eddeserus::cohort::*;
+//! use eddeserus::timeline::Timeline;
+//! use eddeserus::types::*;
+//!
+//! let cohort = Cohort::from_json(r#"{
+//!     "index": {"domain": "Death"},
+//!     "occurrence": "first",
+//!     "criteria": [{"name": "enrolled", "events": {"domain": "Enrollment"},
+//!                   "window": [-30, 0]}]
+//! }"#.as_bytes()).unwrap();
+//!
+//! let json = "\
+//!     [\"abc\",0,9,\"Enrollment\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":9},\
+//!       \"domain\":\"Enrollment\",\"facts\":{}}]\n\
+//!     [\"abc\",5,null,\"Death\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":5,\"end\":null},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n";
+//! let mut timeline = Timeline::new(SubjectID::IDstr("abc".into()));
+//! timeline.events = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+//!
+//! let mut attrition = Attrition::new(&cohort);
+//! assert_eq!(cohort.apply(&timeline, &mut attrition), vec![EventTime::Offset(5)]);
+//! assert_eq!(attrition.steps[1].subjects, 1);
+//! ```
+
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::timeline::Timeline;
+use crate::types::{Event, EventTime};
+
+/// Which events an index or criterion refers to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Selector {
+    /// Events with the concept among their `concepts`.
+    Concept(String),
+    /// Diagnosis, Labs, Medication and Procedure events with the code.
+    Code(String),
+    /// Events of the domain.
+    Domain(String),
+}
+
+impl Selector {
+    pub fn matches(&self, event: &Event) -> bool {
+        match self {
+            Selector::Concept(c) => event.concepts.iter().any(|x| x == c),
+            Selector::Code(c) => event.context.facts.code().is_some_and(|x| x.code == *c),
+            Selector::Domain(d) => event.d == *d,
+        }
+    }
+}
+
+/// Which of the index events give index dates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Occurrence {
+    First,
+    Every,
+}
+
+/// Days relative to an index date, inclusive at both ends. `None` is
+/// unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Window(pub Option<i64>, pub Option<i64>);
+
+impl Window {
+    /// Whether `t` lies in the window around `index`. Times of different
+    /// kinds (date and offset) never do.
+    pub fn contains(&self, index: EventTime, t: EventTime) -> bool {
+        match index.days_until(&t) {
+            Some(n) => self.0.is_none_or(|b| b <= n) && self.1.is_none_or(|e| n <= e),
+            None => false,
+        }
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+fn is_false(x: &bool) -> bool {
+    !x
+}
+
+/// An inclusion or exclusion criterion.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Criterion {
+    pub name: String,
+    pub events: Selector,
+    pub window: Window,
+    #[serde(default = "one")]
+    pub at_least: usize,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub exclude: bool,
+}
+
+impl Criterion {
+    /// Whether the criterion keeps `index` for the events of a timeline.
+    pub fn holds(&self, events: &[Event], index: EventTime) -> bool {
+        let n = events.iter()
+            .filter(|e| self.events.matches(e) && self.window.contains(index, e.b))
+            .take(self.at_least)
+            .count();
+        (n >= self.at_least) != self.exclude
+    }
+}
+
+/// A cohort definition.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Cohort {
+    pub index: Selector,
+    pub occurrence: Occurrence,
+    #[serde(default)]
+    pub criteria: Vec<Criterion>,
+}
+
+/// The number of subjects and index dates remaining after a step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Step {
+    pub name: String,
+    pub subjects: usize,
+    pub index_dates: usize,
+}
+
+/// Counts of subjects and index dates: the first step counts all subjects
+/// seen, the second those with an index event, and the rest those remaining
+/// after each criterion.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Attrition {
+    pub steps: Vec<Step>,
+}
+
+impl Attrition {
+    pub fn new(cohort: &Cohort) -> Self {
+        let step = |name: &str| Step { name: name.to_string(), subjects: 0, index_dates: 0 };
+        let mut steps = vec![step("all subjects"), step("index event")];
+        steps.extend(cohort.criteria.iter().map(|c| step(&c.name)));
+        Attrition { steps }
+    }
+
+    fn count(&mut self, step: usize, index_dates: usize) {
+        if index_dates > 0 {
+            self.steps[step].subjects += 1;
+            self.steps[step].index_dates += index_dates;
+        }
+    }
+
+    /// Writes the steps as CSV with the columns `step`, `subjects` and
+    /// `index_dates`.
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["step", "subjects", "index_dates"])?;
+        for step in &self.steps {
+            writer.serialize((&step.name, step.subjects, step.index_dates))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Cohort {
+    pub fn from_json<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(io::BufReader::new(reader))
+    }
+
+    /// The candidate index dates of a timeline, in order.
+    pub fn index_candidates(&self, timeline: &Timeline) -> Vec<EventTime> {
+        let mut dates: Vec<EventTime> = timeline.events.iter()
+            .filter(|e| self.index.matches(e))
+            .map(|e| e.b)
+            .collect();
+        dates.sort_by(|a, b| a.total_cmp(b));
+        dates.dedup();
+        if self.occurrence == Occurrence::First {
+            dates.truncate(1);
+        }
+        dates
+    }
+
+    /// The index dates of a timeline that meet every criterion, in order.
+    /// Adds the subject to the counts of `attrition`, which should have been
+    /// made for this cohort.
+    pub fn apply(&self, timeline: &Timeline, attrition: &mut Attrition) -> Vec<EventTime> {
+        attrition.steps[0].subjects += 1;
+        let mut dates = self.index_candidates(timeline);
+        attrition.count(1, dates.len());
+        for (i, criterion) in self.criteria.iter().enumerate() {
+            dates.retain(|t| criterion.holds(&timeline.events, *t));
+            attrition.count(i + 2, dates.len());
+        }
+        dates
+    }
+}
+
+#[cfg(test)]
+mod test_cohort {
+    use crate::cohort::*;
+    use crate::types::*;
+
+    fn event(b: i64, d: &str, concepts: &[&str]) -> OwnedEvent {
+        crate::test_support::event(1, b, None, d, concepts, "{}")
+    }
+
+    fn timeline(events: Vec<OwnedEvent>) -> Timeline<'static> {
+        let mut t = Timeline::new(SubjectID::Idint(1));
+        t.events = events;
+        t.sort();
+        t
+    }
+
+    fn cohort(json: &str) -> Cohort {
+        Cohort::from_json(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_definition() {
+        let c = cohort(r#"{
+            "index": {"concept": "dm"},
+            "occurrence": "every",
+            "criteria": [
+                {"name": "a", "events": {"domain": "Death"}, "window": [null, -1], "exclude": true},
+                {"name": "b", "events": {"concept": "x"}, "window": [-365, 0], "at_least": 2}
+            ]}"#);
+        assert_eq!(c.index, Selector::Concept("dm".to_string()));
+        assert_eq!(c.criteria[0].window, Window(None, Some(-1)));
+        assert_eq!((c.criteria[0].at_least, c.criteria[0].exclude), (1, true));
+        assert_eq!((c.criteria[1].at_least, c.criteria[1].exclude), (2, false));
+        assert_eq!(cohort(&serde_json::to_string(&c).unwrap()), c);
+
+        assert!(Cohort::from_json(r#"{"index": {"ndc": "x"}, "occurrence": "first"}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let w = Window(Some(-365), Some(-1));
+        let t = EventTime::Offset;
+        assert!(w.contains(t(400), t(35)));
+        assert!(w.contains(t(400), t(399)));
+        assert!(!w.contains(t(400), t(34)));
+        assert!(!w.contains(t(400), t(400)));
+        let date = EventTime::Date(Date::from_ymd(2010, 1, 1).unwrap());
+        assert!(!w.contains(date, t(0)));
+        assert!(Window(None, None).contains(date, date.add_days(-10000)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let every = cohort(r#"{
+            "index": {"concept": "dm"},
+            "occurrence": "every",
+            "criteria": [
+                {"name": "two x", "events": {"concept": "x"}, "window": [-100, 0], "at_least": 2},
+                {"name": "no prior death", "events": {"domain": "Death"}, "window": [null, -1], "exclude": true}
+            ]}"#);
+        let mut first = every.clone();
+        first.occurrence = Occurrence::First;
+
+        let t1 = timeline(vec![
+            event(0, "Undefined", &["x"]),
+            event(10, "Undefined", &["dm", "x"]),
+            event(10, "Enrollment", &["dm"]),
+            event(50, "Undefined", &["dm"]),
+            event(200, "Undefined", &["dm", "x"]),
+        ]);
+        let t2 = timeline(vec![
+            event(0, "Undefined", &["x"]),
+            event(1, "Undefined", &["x"]),
+            event(2, "Death", &[]),
+            event(3, "Undefined", &["dm"]),
+        ]);
+        let t3 = timeline(vec![event(0, "Undefined", &["x"])]);
+
+        let t = EventTime::Offset;
+        assert_eq!(every.index_candidates(&t1), vec![t(10), t(50), t(200)]);
+
+        let mut attrition = Attrition::new(&every);
+        assert_eq!(every.apply(&t1, &mut attrition), vec![t(10), t(50)]);
+        assert_eq!(every.apply(&t2, &mut attrition), vec![]);
+        assert_eq!(every.apply(&t3, &mut attrition), vec![]);
+        let counts: Vec<_> = attrition.steps.iter().map(|s| (s.subjects, s.index_dates)).collect();
+        assert_eq!(counts, vec![(3, 0), (2, 4), (2, 3), (1, 2)]);
+
+        let mut attrition = Attrition::new(&first);
+        assert_eq!(first.apply(&t1, &mut attrition), vec![t(10)]);
+        assert_eq!(first.apply(&t2, &mut attrition), vec![]);
+        let mut csv = Vec::new();
+        attrition.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+            step,subjects,index_dates\n\
+            all subjects,2,0\n\
+            index event,2,2\n\
+            two x,2,2\n\
+            no prior death,1,1\n");
+    }
+}
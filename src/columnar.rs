@@ -0,0 +1,600 @@
+//! Arrow record batches and Parquet files of events (feature `parquet`).
+//!
+//! [`to_record_batch`] lays events out in columns of the following
+//! [`schema`]:
+//!
+//! | column | type |
+//! |--------|------|
+//! | `p`, `patient_id` | struct of `str` (Utf8) and `int` (UInt64), one of them set |
+//! | `b`, `e` (nullable) | struct of `date` (Date32) and `offset` (Int64), one of them set |
+//! | `d`, `domain` | Utf8: the header's domain and the domain of the facts |
+//! | `concepts` | list of Utf8 |
+//! | `time` | struct of `begin_int`, `end_int` (UInt64) and `begin_str`, `end_str` (Utf8) |
+//! | `claim`, `demographics`, `diagnosis`, `labs`, `medication`, `procedure` | nullable structs of the facts of each domain |
+//! | `source`, `misc` (nullable) | Utf8: the raw JSON, as it was |
+//!
+//! An event has a value in the struct column of its domain only; the domains
+//! without facts (`Death`, `Eligibility`, `Enrollment`, `Undefined`) are
+//! recorded in `domain` alone. Within the structs, codebooks, locations and
+//! demographic fields are written by name and demographic `info` as JSON.
+//! [`from_record_batch`] reads the columns back into the same events.
+//!
+//! [`ParquetOut`] writes events to a Parquet file in batches, and
+//! [`read_parquet`] reads them back.
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::types::{Date32Type, Float64Type, Int32Type, Int64Type, UInt64Type};
+use arrow_array::{Array, ArrayRef, ArrowPrimitiveType, Date32Array, Float64Array, Int32Array,
+                  Int64Array, ListArray, PrimitiveArray, RecordBatch, StringArray, StructArray,
+                  UInt64Array};
+use arrow_buffer::NullBuffer;
+use arrow_schema::{ArrowError, Field, Fields, Schema, SchemaRef};
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::errors::ParquetError;
+use parquet::file::reader::ChunkReader;
+use serde_json::value::RawValue;
+
+use crate::flat::variant_name;
+use crate::process::OutHandler;
+use crate::types::*;
+
+/*----------------------------------------------------------------------------*/
+// Events to columns
+
+/// A struct array of named children, which is null where `valid` is false.
+fn structs(children: Vec<(&str, ArrayRef, bool)>, valid: Option<Vec<bool>>)
+           -> Result<ArrayRef, ArrowError> {
+    let fields: Fields = children.iter()
+        .map(|(name, array, nullable)| Field::new(*name, array.data_type().clone(), *nullable))
+        .collect();
+    let arrays = children.into_iter().map(|(_, array, _)| array).collect();
+    Ok(Arc::new(StructArray::try_new(fields, arrays, valid.map(NullBuffer::from))?))
+}
+
+fn valid<T>(xs: &[Option<T>]) -> Option<Vec<bool>> {
+    Some(xs.iter().map(Option::is_some).collect())
+}
+
+fn names<T: serde::Serialize>(xs: impl Iterator<Item = Option<T>>) -> ArrayRef {
+    Arc::new(xs.map(|x| x.map(|x| variant_name(&x))).collect::<StringArray>())
+}
+
+fn subject_array(ids: &[&SubjectID]) -> Result<ArrayRef, ArrowError> {
+    let strs: StringArray = ids.iter()
+        .map(|id| match id { SubjectID::IDstr(s) => Some(&**s), _ => None })
+        .collect();
+    let ints: UInt64Array = ids.iter()
+        .map(|id| match id { SubjectID::Idint(i) => Some(*i), _ => None })
+        .collect();
+    structs(vec![("str", Arc::new(strs), true), ("int", Arc::new(ints), true)], None)
+}
+
+fn time_array(times: &[Option<EventTime>], nullable: bool) -> Result<ArrayRef, ArrowError> {
+    let dates: Date32Array = times.iter().map(|t| t.and_then(|t| t.as_date()).map(Date::days)).collect();
+    let offsets: Int64Array = times.iter().map(|t| t.and_then(|t| t.as_offset())).collect();
+    structs(vec![("date", Arc::new(dates), true), ("offset", Arc::new(offsets), true)],
+            if nullable { valid(times) } else { None })
+}
+
+fn interval_array(times: &[&Interval]) -> Result<ArrayRef, ArrowError> {
+    let int = |f: fn(&Interval) -> Option<u64>| -> ArrayRef {
+        Arc::new(times.iter().map(|t| f(t)).collect::<UInt64Array>())
+    };
+    let string = |f: fn(&Interval) -> Option<&str>| -> ArrayRef {
+        Arc::new(times.iter().map(|t| f(t)).collect::<StringArray>())
+    };
+    structs(vec![
+        ("begin_int", int(|t| match t { Interval::IntervalInt { begin, .. } => Some(*begin), _ => None }), true),
+        ("end_int", int(|t| match t { Interval::IntervalInt { end, .. } => *end, _ => None }), true),
+        ("begin_str", string(|t| match t { Interval::IntervalStr { begin, .. } => Some(begin), _ => None }), true),
+        ("end_str", string(|t| match t { Interval::IntervalStr { end, .. } => end.as_deref(), _ => None }), true),
+    ], None)
+}
+
+fn code_array(codes: &[Option<&Code>]) -> Result<ArrayRef, ArrowError> {
+    let code: StringArray = codes.iter().map(|c| c.map(|c| &*c.code)).collect();
+    structs(vec![
+        ("code", Arc::new(code), false),
+        ("codebook", names(codes.iter().map(|c| c.and_then(|c| c.codebook))), true),
+    ], valid(codes))
+}
+
+fn claim_array(claims: &[Option<&Claim>]) -> Result<ArrayRef, ArrowError> {
+    let id: StringArray = claims.iter().map(|c| c.map(|c| &*c.id)).collect();
+    let r#type: StringArray = claims.iter().map(|c| c.and_then(|c| c.r#type.as_deref())).collect();
+    let index: Int32Array = claims.iter().map(|c| c.and_then(|c| c.index)).collect();
+    let procedure: StringArray = claims.iter().map(|c| c.and_then(|c| c.procedure.as_deref())).collect();
+    structs(vec![
+        ("id", Arc::new(id), false),
+        ("type", Arc::new(r#type), true),
+        ("index", Arc::new(index), true),
+        ("procedure", Arc::new(procedure), true),
+    ], valid(claims))
+}
+
+fn cost_array(costs: &[Option<&Cost>]) -> Result<ArrayRef, ArrowError> {
+    let string = |f: for<'c> fn(&'c Cost<'c>) -> Option<&'c str>| -> ArrayRef {
+        Arc::new(costs.iter().map(|c| c.and_then(f)).collect::<StringArray>())
+    };
+    structs(vec![
+        ("charge", string(|c| c.charge.as_deref()), true),
+        ("cost", string(|c| Some(&c.cost)), false),
+        ("allowed", string(|c| c.allowed.as_deref()), true),
+        ("transaction", string(|c| c.transaction.as_deref()), true),
+    ], valid(costs))
+}
+
+fn fill_array(fills: &[Option<&Fill>]) -> Result<ArrayRef, ArrowError> {
+    let days_supply: Int32Array = fills.iter().map(|f| f.and_then(|f| f.days_supply)).collect();
+    let quantity: Int32Array = fills.iter().map(|f| f.and_then(|f| f.quantity)).collect();
+    let strength: StringArray = fills.iter().map(|f| f.and_then(|f| f.strength.as_deref())).collect();
+    structs(vec![
+        ("days_supply", Arc::new(days_supply), true),
+        ("quantity", Arc::new(quantity), true),
+        ("strength", Arc::new(strength), true),
+    ], valid(fills))
+}
+
+fn lab_value_array(values: &[Option<&LabValue>]) -> Result<ArrayRef, ArrowError> {
+    let text: StringArray = values.iter().map(|v| v.and_then(|v| v.text.as_deref())).collect();
+    let number: Float64Array = values.iter().map(|v| v.and_then(|v| v.number)).collect();
+    let units: StringArray = values.iter().map(|v| v.map(|v| &*v.units)).collect();
+    structs(vec![
+        ("text", Arc::new(text), true),
+        ("number", Arc::new(number), true),
+        ("units", Arc::new(units), false),
+    ], valid(values))
+}
+
+/// The facts of one domain in every event, or `None` in events of other
+/// domains.
+fn facts<'e, 'a, T>(events: &'e [Event<'a>], f: fn(&'e Domain<'a>) -> Option<&'e T>) -> Vec<Option<&'e T>> {
+    events.iter().map(|e| f(&e.context.facts)).collect()
+}
+
+/// The events in columns.
+pub fn to_record_batch(events: &[Event]) -> Result<RecordBatch, ArrowError> {
+    let mut concepts = ListBuilder::new(StringBuilder::new());
+    for event in events {
+        for concept in &event.concepts {
+            concepts.values().append_value(concept);
+        }
+        concepts.append(true);
+    }
+    let raw = |f: for<'c> fn(&'c Context<'c>) -> Option<&'c RawValue>| -> ArrayRef {
+        Arc::new(events.iter().map(|e| f(&e.context).map(RawValue::get)).collect::<StringArray>())
+    };
+
+    let claim = facts(events, |d| match d { Domain::Claim(x) => Some(x), _ => None });
+    let demographics = facts(events, |d| match d { Domain::Demographics(x) => Some(&x.demo), _ => None });
+    let diagnosis = facts(events, |d| match d { Domain::Diagnosis(x) => Some(x), _ => None });
+    let labs = facts(events, |d| match d { Domain::Labs(x) => Some(x), _ => None });
+    let medication = facts(events, |d| match d { Domain::Medication(x) => Some(x), _ => None });
+    let procedure = facts(events, |d| match d { Domain::Procedure(x) => Some(x), _ => None });
+
+    let info: StringArray = demographics.iter()
+        .map(|d| d.and_then(|d| d.info.as_ref()).map(|info| info.to_string()))
+        .collect();
+
+    let columns: Vec<(&str, ArrayRef, bool)> = vec![
+        ("p", subject_array(&events.iter().map(|e| &e.p).collect::<Vec<_>>())?, false),
+        ("b", time_array(&events.iter().map(|e| Some(e.b)).collect::<Vec<_>>(), false)?, false),
+        ("e", time_array(&events.iter().map(|e| e.e).collect::<Vec<_>>(), true)?, true),
+        ("d", Arc::new(events.iter().map(|e| Some(&*e.d)).collect::<StringArray>()), false),
+        ("concepts", Arc::new(concepts.finish()), false),
+        ("patient_id", subject_array(&events.iter().map(|e| &e.context.patient_id).collect::<Vec<_>>())?, false),
+        ("time", interval_array(&events.iter().map(|e| &e.context.time).collect::<Vec<_>>())?, false),
+        ("domain", Arc::new(events.iter().map(|e| Some(e.context.facts.name())).collect::<StringArray>()), false),
+        ("claim", structs(vec![
+            ("claim", claim_array(&claim.iter().map(|x| x.map(|x| &x.claim)).collect::<Vec<_>>())?, false),
+            ("location", names(claim.iter().map(|x| x.and_then(|x| x.location))), true),
+            ("cost", cost_array(&claim.iter().map(|x| x.and_then(|x| x.cost.as_ref())).collect::<Vec<_>>())?, true),
+        ], valid(&claim))?, true),
+        ("demographics", structs(vec![
+            ("field", names(demographics.iter().map(|x| x.map(|x| x.field))), false),
+            ("info", Arc::new(info), true),
+        ], valid(&demographics))?, true),
+        ("diagnosis", structs(vec![
+            ("code", code_array(&diagnosis.iter().map(|x| x.map(|x| &x.code)).collect::<Vec<_>>())?, false),
+            ("claim", claim_array(&diagnosis.iter().map(|x| x.and_then(|x| x.claim.as_ref())).collect::<Vec<_>>())?, true),
+            ("location", names(diagnosis.iter().map(|x| x.and_then(|x| x.location))), true),
+        ], valid(&diagnosis))?, true),
+        ("labs", structs(vec![
+            ("code", code_array(&labs.iter().map(|x| x.map(|x| &x.code)).collect::<Vec<_>>())?, false),
+            ("value", lab_value_array(&labs.iter().map(|x| x.map(|x| &x.value)).collect::<Vec<_>>())?, false),
+            ("claim", claim_array(&labs.iter().map(|x| x.and_then(|x| x.claim.as_ref())).collect::<Vec<_>>())?, true),
+            ("location", names(labs.iter().map(|x| x.and_then(|x| x.location))), true),
+        ], valid(&labs))?, true),
+        ("medication", structs(vec![
+            ("code", code_array(&medication.iter().map(|x| x.map(|x| &x.code)).collect::<Vec<_>>())?, false),
+            ("fill", fill_array(&medication.iter().map(|x| x.and_then(|x| x.fill.as_ref())).collect::<Vec<_>>())?, true),
+            ("location", names(medication.iter().map(|x| x.and_then(|x| x.location))), true),
+            ("claim", claim_array(&medication.iter().map(|x| x.and_then(|x| x.claim.as_ref())).collect::<Vec<_>>())?, true),
+        ], valid(&medication))?, true),
+        ("procedure", structs(vec![
+            ("code", code_array(&procedure.iter().map(|x| x.map(|x| &x.code)).collect::<Vec<_>>())?, false),
+            ("claim", claim_array(&procedure.iter().map(|x| x.and_then(|x| x.claim.as_ref())).collect::<Vec<_>>())?, true),
+            ("location", names(procedure.iter().map(|x| x.and_then(|x| x.location))), true),
+        ], valid(&procedure))?, true),
+        ("source", raw(|c| c.source.as_deref()), true),
+        ("misc", raw(|c| c.misc.as_deref()), true),
+    ];
+
+    let schema = Schema::new(columns.iter()
+        .map(|(name, array, nullable)| Field::new(*name, array.data_type().clone(), *nullable))
+        .collect::<Vec<_>>());
+    RecordBatch::try_new(Arc::new(schema), columns.into_iter().map(|(_, array, _)| array).collect())
+}
+
+/// The schema of the record batches of events.
+pub fn schema() -> SchemaRef {
+    to_record_batch(&[]).expect("empty batch").schema()
+}
+
+/*----------------------------------------------------------------------------*/
+// Columns to events
+
+fn invalid(message: String) -> ArrowError {
+    ArrowError::InvalidArgumentError(message)
+}
+
+fn child<'a>(array: &'a StructArray, name: &str) -> Result<&'a dyn Array, ArrowError> {
+    array.column_by_name(name)
+        .map(|c| c.as_ref())
+        .ok_or_else(|| ArrowError::SchemaError(format!("no column {:?}", name)))
+}
+
+fn downcast<T: 'static>(array: &dyn Array) -> Result<&T, ArrowError> {
+    array.as_any().downcast_ref::<T>()
+        .ok_or_else(|| ArrowError::SchemaError(format!("unexpected type {}", array.data_type())))
+}
+
+fn as_struct(array: &dyn Array) -> Result<&StructArray, ArrowError> {
+    downcast(array)
+}
+
+/// The struct column `name` of `array`, if it is not null at row `i`.
+fn get_struct<'a>(array: &'a StructArray, name: &str, i: usize)
+                  -> Result<Option<&'a StructArray>, ArrowError> {
+    let s = as_struct(child(array, name)?)?;
+    Ok(if s.is_null(i) { None } else { Some(s) })
+}
+
+fn get_str<'a>(array: &'a StructArray, name: &str, i: usize) -> Result<Option<&'a str>, ArrowError> {
+    let s: &StringArray = downcast(child(array, name)?)?;
+    Ok(if s.is_null(i) { None } else { Some(s.value(i)) })
+}
+
+fn get_primitive<T: ArrowPrimitiveType>(array: &StructArray, name: &str, i: usize)
+                                        -> Result<Option<T::Native>, ArrowError> {
+    let a: &PrimitiveArray<T> = downcast(child(array, name)?)?;
+    Ok(if a.is_null(i) { None } else { Some(a.value(i)) })
+}
+
+fn required<T>(x: Option<T>, name: &str, i: usize) -> Result<T, ArrowError> {
+    x.ok_or_else(|| invalid(format!("null {} in row {}", name, i)))
+}
+
+fn string(x: Option<&str>) -> Option<Cow<'static, str>> {
+    x.map(|s| Cow::Owned(s.to_string()))
+}
+
+fn from_name<T: serde::de::DeserializeOwned>(x: Option<&str>) -> Result<Option<T>, ArrowError> {
+    x.map(|s| serde_json::from_value(serde_json::Value::String(s.to_string()))
+          .map_err(|_| invalid(format!("unknown name {:?}", s))))
+        .transpose()
+}
+
+fn read_subject(array: &StructArray, name: &str, i: usize) -> Result<SubjectID<'static>, ArrowError> {
+    let s = as_struct(child(array, name)?)?;
+    match (get_str(s, "str", i)?, get_primitive::<UInt64Type>(s, "int", i)?) {
+        (Some(id), _) => Ok(SubjectID::IDstr(Cow::Owned(id.to_string()))),
+        (None, Some(id)) => Ok(SubjectID::Idint(id)),
+        (None, None) => Err(invalid(format!("null {} in row {}", name, i))),
+    }
+}
+
+fn read_time(array: &StructArray, name: &str, i: usize) -> Result<Option<EventTime>, ArrowError> {
+    let t = match get_struct(array, name, i)? {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    match (get_primitive::<Date32Type>(t, "date", i)?, get_primitive::<Int64Type>(t, "offset", i)?) {
+        (Some(d), _) => Ok(Some(EventTime::Date(Date::from_days(d)))),
+        (None, Some(x)) => Ok(Some(EventTime::Offset(x))),
+        (None, None) => Err(invalid(format!("null {} in row {}", name, i))),
+    }
+}
+
+fn read_interval(array: &StructArray, i: usize) -> Result<Interval, ArrowError> {
+    let t = as_struct(child(array, "time")?)?;
+    if let Some(begin) = get_primitive::<UInt64Type>(t, "begin_int", i)? {
+        return Ok(Interval::IntervalInt { begin, end: get_primitive::<UInt64Type>(t, "end_int", i)? });
+    }
+    Ok(Interval::IntervalStr {
+        begin: required(get_str(t, "begin_str", i)?, "time", i)?.to_string(),
+        end: get_str(t, "end_str", i)?.map(str::to_string),
+    })
+}
+
+fn read_code(array: &StructArray, i: usize) -> Result<Code<'static>, ArrowError> {
+    let c = required(get_struct(array, "code", i)?, "code", i)?;
+    Ok(Code {
+        code: Cow::Owned(required(get_str(c, "code", i)?, "code", i)?.to_string()),
+        codebook: from_name(get_str(c, "codebook", i)?)?,
+    })
+}
+
+fn read_claim(array: &StructArray, i: usize) -> Result<Option<Claim<'static>>, ArrowError> {
+    let c = match get_struct(array, "claim", i)? {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    Ok(Some(Claim {
+        id: Cow::Owned(required(get_str(c, "id", i)?, "claim id", i)?.to_string()),
+        r#type: get_str(c, "type", i)?.map(str::to_string),
+        index: get_primitive::<Int32Type>(c, "index", i)?,
+        procedure: get_str(c, "procedure", i)?.map(str::to_string),
+    }))
+}
+
+fn read_location(array: &StructArray, i: usize) -> Result<Option<Location>, ArrowError> {
+    from_name(get_str(array, "location", i)?)
+}
+
+fn read_facts(batch: &StructArray, i: usize) -> Result<Domain<'static>, ArrowError> {
+    let domain = required(get_str(batch, "domain", i)?, "domain", i)?;
+    let facts = |name: &str| required(get_struct(batch, name, i)?, name, i);
+    Ok(match domain {
+        "Claim" => {
+            let x = facts("claim")?;
+            let cost = match get_struct(x, "cost", i)? {
+                None => None,
+                Some(c) => Some(Cost {
+                    charge: string(get_str(c, "charge", i)?),
+                    cost: required(string(get_str(c, "cost", i)?), "cost", i)?,
+                    allowed: string(get_str(c, "allowed", i)?),
+                    transaction: string(get_str(c, "transaction", i)?),
+                }),
+            };
+            Domain::Claim(ClaimFacts {
+                claim: required(read_claim(x, i)?, "claim", i)?,
+                location: read_location(x, i)?,
+                cost,
+            })
+        }
+        "Death" => Domain::Death(DeathFacts {}),
+        "Demographics" => {
+            let x = facts("demographics")?;
+            let info = get_str(x, "info", i)?
+                .map(serde_json::from_str)
+                .transpose()
+                .map_err(|e| invalid(format!("demographic info in row {}: {}", i, e)))?;
+            Domain::Demographics(DemographicFacts {
+                demo: DemographicInfo {
+                    field: required(from_name(get_str(x, "field", i)?)?, "field", i)?,
+                    info,
+                },
+            })
+        }
+        "Diagnosis" => {
+            let x = facts("diagnosis")?;
+            Domain::Diagnosis(DiagnosisFacts {
+                code: read_code(x, i)?,
+                claim: read_claim(x, i)?,
+                location: read_location(x, i)?,
+            })
+        }
+        "Eligibility" => Domain::Eligibility(EligibilityFacts {}),
+        "Enrollment" => Domain::Enrollment(EnrollmentFacts {}),
+        "Labs" => {
+            let x = facts("labs")?;
+            let v = required(get_struct(x, "value", i)?, "value", i)?;
+            Domain::Labs(LabsFacts {
+                code: read_code(x, i)?,
+                value: LabValue {
+                    text: string(get_str(v, "text", i)?),
+                    number: get_primitive::<Float64Type>(v, "number", i)?,
+                    units: required(string(get_str(v, "units", i)?), "units", i)?,
+                },
+                claim: read_claim(x, i)?,
+                location: read_location(x, i)?,
+            })
+        }
+        "Medication" => {
+            let x = facts("medication")?;
+            let fill = match get_struct(x, "fill", i)? {
+                None => None,
+                Some(f) => Some(Fill {
+                    days_supply: get_primitive::<Int32Type>(f, "days_supply", i)?,
+                    quantity: get_primitive::<Int32Type>(f, "quantity", i)?,
+                    strength: string(get_str(f, "strength", i)?),
+                }),
+            };
+            Domain::Medication(MedicationFacts {
+                code: read_code(x, i)?,
+                fill,
+                location: read_location(x, i)?,
+                claim: read_claim(x, i)?,
+            })
+        }
+        "Procedure" => {
+            let x = facts("procedure")?;
+            Domain::Procedure(ProcedureFacts {
+                code: read_code(x, i)?,
+                claim: read_claim(x, i)?,
+                location: read_location(x, i)?,
+            })
+        }
+        "Undefined" => Domain::Undefined(UndefinedFacts {}),
+        d => return Err(invalid(format!("unknown domain {:?} in row {}", d, i))),
+    })
+}
+
+fn read_raw(batch: &StructArray, name: &str, i: usize) -> Result<Option<Cow<'static, RawValue>>, ArrowError> {
+    get_str(batch, name, i)?
+        .map(|s| RawValue::from_string(s.to_string())
+             .map(Cow::Owned)
+             .map_err(|e| invalid(format!("{} in row {}: {}", name, i, e))))
+        .transpose()
+}
+
+/// The events of a record batch with the [`schema`] of events.
+pub fn from_record_batch(batch: &RecordBatch) -> Result<Vec<OwnedEvent>, ArrowError> {
+    let columns = StructArray::from(batch.clone());
+    let concepts: &ListArray = downcast(child(&columns, "concepts")?)?;
+    (0..batch.num_rows())
+        .map(|i| {
+            let names = concepts.value(i);
+            let names: &StringArray = downcast(names.as_ref())?;
+            Ok(Event {
+                p: read_subject(&columns, "p", i)?,
+                b: required(read_time(&columns, "b", i)?, "b", i)?,
+                e: read_time(&columns, "e", i)?,
+                d: Cow::Owned(required(get_str(&columns, "d", i)?, "d", i)?.to_string()),
+                concepts: names.iter().flatten().map(str::to_string).collect(),
+                context: Context {
+                    patient_id: read_subject(&columns, "patient_id", i)?,
+                    time: read_interval(&columns, i)?,
+                    facts: read_facts(&columns, i)?,
+                    source: read_raw(&columns, "source", i)?,
+                    misc: read_raw(&columns, "misc", i)?,
+                },
+            })
+        })
+        .collect()
+}
+
+/*----------------------------------------------------------------------------*/
+// Parquet
+
+/// The number of events in each batch written by [`ParquetOut`].
+pub const BATCH_SIZE: usize = 8192;
+
+/// Writes events to a Parquet file in batches of [`BATCH_SIZE`].
+///
+/// [`flush`](OutHandler::flush) writes the buffered events, and
+/// [`finish`](ParquetOut::finish) completes the file.
+pub struct ParquetOut<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    buffer: Vec<OwnedEvent>,
+}
+
+impl<W: Write + Send> ParquetOut<W> {
+    pub fn new(writer: W) -> Result<Self, ParquetError> {
+        Ok(ParquetOut { writer: ArrowWriter::try_new(writer, schema(), None)?, buffer: Vec::new() })
+    }
+
+    fn write_buffer(&mut self) -> Result<(), ParquetError> {
+        if !self.buffer.is_empty() {
+            self.writer.write(&to_record_batch(&self.buffer)?)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered events and the file footer, and returns the
+    /// underlying writer.
+    pub fn finish(mut self) -> Result<W, ParquetError> {
+        self.write_buffer()?;
+        self.writer.into_inner()
+    }
+}
+
+impl<'a, W: Write + Send> OutHandler<'a> for ParquetOut<W> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        self.buffer.push(event.into_owned());
+        if self.buffer.len() >= BATCH_SIZE {
+            self.write_buffer().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer().map_err(io::Error::other)
+    }
+}
+
+/// The events of a Parquet file. See [`read_parquet`].
+pub struct ParquetEvents {
+    batches: ParquetRecordBatchReader,
+    events: std::vec::IntoIter<OwnedEvent>,
+}
+
+/// Reads the events of a Parquet file written by [`ParquetOut`].
+pub fn read_parquet<R: ChunkReader + 'static>(reader: R) -> Result<ParquetEvents, ParquetError> {
+    let batches = ParquetRecordBatchReaderBuilder::try_new(reader)?.build()?;
+    Ok(ParquetEvents { batches, events: Vec::new().into_iter() })
+}
+
+impl Iterator for ParquetEvents {
+    type Item = Result<OwnedEvent, ParquetError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.next() {
+                return Some(Ok(event));
+            }
+            match self.batches.next()?.and_then(|batch| from_record_batch(&batch)) {
+                Ok(events) => self.events = events.into_iter(),
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+
+#[cfg(test)]
+mod test_columnar {
+    use crate::columnar::*;
+    use crate::test_support::EVENTS;
+
+    fn events() -> Vec<Event<'static>> {
+        EVENTS.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn json(events: &[Event]) -> Vec<String> {
+        events.iter().map(|e| serde_json::to_string(e).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_record_batch() {
+        let events = events();
+        let batch = to_record_batch(&events).unwrap();
+        assert_eq!(batch.num_rows(), events.len());
+        assert_eq!(batch.schema(), schema());
+        let back = from_record_batch(&batch).unwrap();
+        assert_eq!(json(&back), json(&events));
+        assert_eq!(back[1].context.source.as_deref().map(RawValue::get),
+                   Some("{ \"table\" : [1, 2.50,\"\\u00e9\"] }"));
+        assert!(from_record_batch(&to_record_batch(&[]).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parquet() {
+        let path = std::env::temp_dir().join(format!("eddeserus-columnar-{}.parquet", std::process::id()));
+        let mut out = ParquetOut::new(std::fs::File::create(&path).unwrap()).unwrap();
+        for event in events() {
+            out.write_event(event).unwrap();
+        }
+        out.flush().unwrap();
+        for event in events().into_iter().take(3) {
+            out.write_event(event).unwrap();
+        }
+        out.finish().unwrap();
+
+        let back = read_parquet(std::fs::File::open(&path).unwrap()).unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut expected = events();
+        expected.extend(events().into_iter().take(3));
+        assert_eq!(json(&back), json(&expected));
+    }
+}
@@ -0,0 +1,358 @@
+//! Drug eras: continuous exposure built from Medication fills.
+//!
+//! Each Medication event is an exposure that starts at `b` and lasts for the
+//! `days_supply` of its fill, or from `b` through `e` if the fill has no
+//! supply. Exposures are grouped (by code, by ingredient or by concept) and the
+//! exposures of a group are joined into a [`DrugEra`] wherever the gap between
+//! them is at most `grace` days.
+//!
+//! With `stockpile`, a fill that starts before the previous supply runs out
+//! starts when it does, so early refills extend the era rather than overlap.
+//!
+//! Example:
+//! ```
+//! use eddeserus::drugera::*;
+//! use eddeserus::types::*;
+//!
+//! let fill = |b: i64, days: i32| -> OwnedEvent {
+//!     let json = format!("\
+//!         [1,{},null,\"Medication\",[],\
+//!          {{\"patient_id\":1,\"time\":{{\"begin\":{},\"end\":null}},\
+//!            \"domain\":\"Medication\",\
+//!            \"facts\":{{\"code\":{{\"code\":\"00093-0058\",\"codebook\":\"NDC\"}},\
+//!                       \"fill\":{{\"days_supply\":{}}}}}}}]", b, b, days);
+//!     serde_json::from_str::<Event>(&json).unwrap().into_owned()
+//! };
+//! let events = vec![fill(0, 30), fill(20, 30), fill(100, 30)];
+//!
+//! let options = EraOptions { grace: 10, ..EraOptions::default() };
+//! let eras = drug_eras(&events, &options).unwrap().eras;
+//! assert_eq!(eras.len(), 2);
+//! assert_eq!((eras[0].begin, eras[0].end), (EventTime::Offset(0), EventTime::Offset(49)));
+//! assert_eq!(eras[0].events, 2);
+//!
+//! let options = EraOptions { grace: 10, stockpile: true, ..EraOptions::default() };
+//! let eras = drug_eras(&events, &options).unwrap().eras;
+//! assert_eq!(eras[0].end, EventTime::Offset(59));
+//! ```
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use serde_json::value::RawValue;
+
+use crate::intervalset::MixedTimes;
+use crate::types::*;
+use crate::valueset::ValueSet;
+
+/// How exposures are grouped into eras.
+#[derive(Debug, Clone, Copy)]
+pub enum Grouping<'v> {
+    /// By the code (and codebook) of the event.
+    Code,
+    /// By the concepts of the code in a value set that maps codes to
+    /// ingredients. A combination product counts towards each of its
+    /// ingredients.
+    Ingredient(&'v ValueSet),
+    /// By each of the event's `concepts`.
+    Concept,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EraOptions<'v> {
+    pub grouping: Grouping<'v>,
+    /// Most days between exposures that are joined into one era.
+    pub grace: i64,
+    /// Whether early refills start when the previous supply runs out.
+    pub stockpile: bool,
+    /// Days of supply of an event that has neither a `days_supply` nor an end.
+    pub default_days_supply: Option<i64>,
+}
+
+impl Default for EraOptions<'_> {
+    fn default() -> Self {
+        EraOptions { grouping: Grouping::Code, grace: 30, stockpile: false, default_days_supply: None }
+    }
+}
+
+/// A period of continuous exposure to one drug, from `begin` through `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrugEra {
+    /// The code, or with [`Grouping::Ingredient`] and [`Grouping::Concept`]
+    /// the ingredient or concept (with no codebook), of the group.
+    pub drug: Code<'static>,
+    pub begin: EventTime,
+    pub end: EventTime,
+    /// The number of events that contributed to the era.
+    pub events: usize,
+    /// The total days of supply of those events.
+    pub days_supply: i64,
+    /// The days of the era not covered by any exposure.
+    pub gap_days: i64,
+}
+
+/// The eras of a subject, with counts of the Medication events left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DrugEras {
+    /// The eras, ordered by drug and then by begin.
+    pub eras: Vec<DrugEra>,
+    /// Events whose code has no ingredient, or that have no concepts.
+    pub ungrouped: usize,
+    /// Events without a days supply, an end or a default supply.
+    pub no_supply: usize,
+}
+
+/// The days of supply of a Medication event.
+fn days_supply(event: &Event, facts: &MedicationFacts, options: &EraOptions) -> Option<i64> {
+    facts.fill.as_ref()
+        .and_then(|f| f.days_supply)
+        .filter(|n| *n > 0)
+        .map(i64::from)
+        .or_else(|| event.e.and_then(|e| event.b.days_until(&e)).map(|n| n.saturating_add(1)))
+        .or(options.default_days_supply)
+        .filter(|n| *n > 0)
+}
+
+/// Builds the drug eras from the Medication events among `events`, which
+/// should be those of one subject. Fails if the events of a group mix dates and
+/// offsets.
+pub fn drug_eras(events: &[Event], options: &EraOptions) -> Result<DrugEras, MixedTimes> {
+    let mut result = DrugEras::default();
+    let mut groups: BTreeMap<Code<'static>, Vec<(EventTime, i64)>> = BTreeMap::new();
+
+    for event in events {
+        let facts = match &event.context.facts {
+            Domain::Medication(facts) => facts,
+            _ => continue,
+        };
+        let days = match days_supply(event, facts, options) {
+            Some(days) => days,
+            None => {
+                result.no_supply += 1;
+                continue;
+            }
+        };
+        let concept = |name: &str| Code { code: Cow::Owned(name.to_string()), codebook: None };
+        let keys: Vec<Code<'static>> = match options.grouping {
+            Grouping::Code => vec![facts.code.as_borrowed().into_owned()],
+            Grouping::Ingredient(ingredients) =>
+                ingredients.concepts(&facts.code).into_iter().map(concept).collect(),
+            Grouping::Concept => event.concepts.iter().map(|c| concept(c)).collect(),
+        };
+        if keys.is_empty() {
+            result.ungrouped += 1;
+        }
+        for key in keys {
+            groups.entry(key).or_default().push((event.b, days));
+        }
+    }
+
+    for (drug, mut exposures) in groups {
+        exposures.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut era: Option<DrugEra> = None;
+        for (b, days) in exposures {
+            let mut start = b;
+            if let Some(era) = &era {
+                if options.stockpile && era.end.days_until(&b).ok_or(MixedTimes)? <= 0 {
+                    start = era.end.add_days(1);
+                }
+            }
+            let end = start.add_days(days - 1);
+            match &mut era {
+                Some(era) if era.end.days_until(&start).ok_or(MixedTimes)?.saturating_sub(1) <= options.grace => {
+                    let gap = era.end.days_until(&start).unwrap().saturating_sub(1);
+                    let added = era.end.days_until(&end).unwrap();
+                    era.gap_days = era.gap_days.saturating_add(gap.max(0));
+                    if added > 0 {
+                        era.end = end;
+                    }
+                    era.events += 1;
+                    era.days_supply = era.days_supply.saturating_add(days);
+                }
+                _ => {
+                    result.eras.extend(era.take());
+                    era = Some(DrugEra {
+                        drug: drug.clone(), begin: start, end, events: 1, days_supply: days, gap_days: 0,
+                    });
+                }
+            }
+        }
+        result.eras.extend(era);
+    }
+    Ok(result)
+}
+
+impl DrugEra {
+    /// The era as a Medication event of `patient` with a fill of the era's
+    /// length. The event's `concepts` hold the ingredient or concept of the
+    /// group, and `misc` holds the era's counts as
+    /// `{"drug_era":{"events":..,"days_supply":..,"gap_days":..}}`. `None`
+    /// if the era begins at a negative offset, which the event's
+    /// `context.time` cannot hold.
+    pub fn to_event(&self, patient: &SubjectID) -> Option<OwnedEvent> {
+        let length = self.begin.days_until(&self.end).unwrap_or(0).saturating_add(1);
+        let misc = format!(
+            "{{\"drug_era\":{{\"events\":{},\"days_supply\":{},\"gap_days\":{}}}}}",
+            self.events, self.days_supply, self.gap_days);
+        let concepts = match self.drug.codebook {
+            Some(_) => vec![],
+            None => vec![self.drug.code.to_string()],
+        };
+        let time = Interval::from_times(self.begin, Some(self.end))?;
+        Some(Event {
+            p: patient.as_borrowed().into_owned(),
+            b: self.begin,
+            e: Some(self.end),
+            d: Cow::Borrowed("Medication"),
+            concepts,
+            context: Context {
+                patient_id: patient.as_borrowed().into_owned(),
+                time,
+                facts: Domain::Medication(MedicationFacts {
+                    code: self.drug.clone(),
+                    fill: Some(Fill {
+                        days_supply: Some(i32::try_from(length).unwrap_or(i32::MAX)),
+                        quantity: None,
+                        strength: None,
+                    }),
+                    location: None,
+                    claim: None,
+                }),
+                source: None,
+                misc: Some(Cow::Owned(RawValue::from_string(misc).expect("valid JSON"))),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test_drugera {
+    use crate::drugera::*;
+    use crate::test_support::event;
+
+    fn fill(code: &str, b: i64, days: Option<i32>, concepts: &[&str]) -> OwnedEvent {
+        let fill = days.map_or("".to_string(), |d| format!(",\"fill\":{{\"days_supply\":{}}}", d));
+        let facts = format!("{{\"code\":{{\"code\":\"{}\",\"codebook\":\"NDC\"}}{}}}", code, fill);
+        event(1, b, None, "Medication", concepts, &facts)
+    }
+
+    fn spans(eras: &DrugEras) -> Vec<(&str, i64, i64, usize)> {
+        eras.eras.iter()
+            .map(|e| match (e.begin, e.end) {
+                (EventTime::Offset(b), EventTime::Offset(e_)) => (&*e.drug.code, b, e_, e.events),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_grace() {
+        let events = vec![
+            fill("a", 0, Some(30), &[]),
+            fill("b", 5, Some(10), &[]),
+            fill("a", 40, Some(30), &[]),
+            fill("a", 81, Some(30), &[]),
+        ];
+        let options = EraOptions { grace: 10, ..EraOptions::default() };
+        let eras = drug_eras(&events, &options).unwrap();
+        assert_eq!(spans(&eras), vec![("a", 0, 69, 2), ("a", 81, 110, 1), ("b", 5, 14, 1)]);
+        assert_eq!(eras.eras[0].gap_days, 10);
+        assert_eq!(eras.eras[0].days_supply, 60);
+
+        let options = EraOptions { grace: 11, ..EraOptions::default() };
+        let eras = drug_eras(&events, &options).unwrap();
+        assert_eq!(spans(&eras), vec![("a", 0, 110, 3), ("b", 5, 14, 1)]);
+        assert_eq!(eras.eras[0].gap_days, 21);
+    }
+
+    #[test]
+    fn test_stockpile() {
+        let events = vec![
+            fill("a", 0, Some(30), &[]),
+            fill("a", 10, Some(30), &[]),
+            fill("a", 20, Some(30), &[]),
+            fill("a", 100, Some(30), &[]),
+        ];
+        let options = EraOptions { grace: 0, ..EraOptions::default() };
+        assert_eq!(spans(&drug_eras(&events, &options).unwrap()),
+                   vec![("a", 0, 49, 3), ("a", 100, 129, 1)]);
+
+        let options = EraOptions { grace: 0, stockpile: true, ..EraOptions::default() };
+        assert_eq!(spans(&drug_eras(&events, &options).unwrap()),
+                   vec![("a", 0, 89, 3), ("a", 100, 129, 1)]);
+
+        let options = EraOptions { grace: 10, stockpile: true, ..EraOptions::default() };
+        let eras = drug_eras(&events, &options).unwrap();
+        assert_eq!(spans(&eras), vec![("a", 0, 129, 4)]);
+        assert_eq!(eras.eras[0].gap_days, 10);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let events = vec![
+            fill("single", 0, Some(30), &["statin"]),
+            fill("combo", 20, Some(30), &["statin", "other"]),
+            fill("unmapped", 0, Some(30), &[]),
+            fill("single", 100, None, &["statin"]),
+        ];
+
+        let mut ingredients = ValueSet::new();
+        ingredients.insert("atorvastatin", Some(Codebook::NDC), "single");
+        ingredients.insert("atorvastatin", Some(Codebook::NDC), "combo");
+        ingredients.insert("amlodipine", Some(Codebook::NDC), "combo");
+        let options = EraOptions { grouping: Grouping::Ingredient(&ingredients), ..EraOptions::default() };
+        let eras = drug_eras(&events, &options).unwrap();
+        assert_eq!(spans(&eras), vec![("amlodipine", 20, 49, 1), ("atorvastatin", 0, 49, 2)]);
+        assert_eq!(eras.eras[0].drug.codebook, None);
+        assert_eq!((eras.ungrouped, eras.no_supply), (1, 1));
+
+        let options = EraOptions { grouping: Grouping::Concept, default_days_supply: Some(30),
+                                   ..EraOptions::default() };
+        let eras = drug_eras(&events, &options).unwrap();
+        assert_eq!(spans(&eras), vec![("other", 20, 49, 1), ("statin", 0, 49, 2), ("statin", 100, 129, 1)]);
+        assert_eq!((eras.ungrouped, eras.no_supply), (1, 0));
+    }
+
+    #[test]
+    fn test_to_event() {
+        let events = vec![fill("a", 0, Some(30), &[]), fill("a", 35, Some(30), &[])];
+        let eras = drug_eras(&events, &EraOptions::default()).unwrap();
+        let event = eras.eras[0].to_event(&SubjectID::Idint(1)).unwrap();
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, "\
+            [1,0,64,\"Medication\",[],\
+             {\"patient_id\":1,\"time\":{\"begin\":0,\"end\":64},\
+              \"domain\":\"Medication\",\
+              \"facts\":{\"code\":{\"code\":\"a\",\"codebook\":\"NDC\"},\
+                         \"fill\":{\"days_supply\":65}},\
+              \"misc\":{\"drug_era\":{\"events\":2,\"days_supply\":60,\"gap_days\":5}}}]"
+            .replace(|c: char| c.is_whitespace(), ""));
+    }
+
+    #[test]
+    fn test_extreme_offsets() {
+        let facts = "{\"code\":{\"code\":\"a\",\"codebook\":\"NDC\"}}";
+        let events = vec![event(1, 0, Some(i64::MAX), "Medication", &[], facts),
+                          fill("a", 0, Some(30), &[])];
+        let eras = drug_eras(&events, &EraOptions::default()).unwrap();
+        assert_eq!(spans(&eras), vec![("a", 0, i64::MAX - 1, 2)]);
+        assert_eq!(eras.eras[0].days_supply, i64::MAX);
+        let event = eras.eras[0].to_event(&SubjectID::Idint(1)).unwrap();
+        assert!(matches!(event.context.facts, Domain::Medication(MedicationFacts {
+            fill: Some(Fill { days_supply: Some(i32::MAX), .. }), ..
+        })));
+    }
+
+    #[test]
+    fn test_mixed() {
+        let mut events = vec![fill("a", 0, Some(30), &[])];
+        let json = "\
+            [1,\"2010-01-01\",null,\"Medication\",[],\
+             {\"patient_id\":1,\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+              \"domain\":\"Medication\",\
+              \"facts\":{\"code\":{\"code\":\"a\",\"codebook\":\"NDC\"},\"fill\":{\"days_supply\":30}}}]";
+        events.push(serde_json::from_str::<Event>(json).unwrap().into_owned());
+        assert_eq!(drug_eras(&events, &EraOptions::default()), Err(MixedTimes));
+    }
+}
@@ -0,0 +1,211 @@
+//! Continuous enrollment: was a subject covered over a lookback window?
+//!
+//! A [`ContinuousEnrollment`] criterion holds at an index date `t0` if the
+//! window `[t0 - lookback, t0]` has no stretch of more than `max_gap`
+//! consecutive days without coverage. Gaps at either edge of the window count
+//! too, so coverage may start up to `max_gap` days after the window begins.
+//!
+//! Coverage is an [`IntervalSet`], usually built from a timeline's
+//! `Enrollment` (or `Eligibility`) events, whose days run from `b` through `e`
+//! inclusive. [`ContinuousEnrollment::check`] tests one index date and reports
+//! the first disqualifying gap; [`ContinuousEnrollment::qualifying`] finds
+//! every index date at which the criterion holds.
+//!
+//! Example:
+//! ```
+//! use eddeserus::enrollment::*;
+//! use eddeserus::intervalset::IntervalSet;
+//! use eddeserus::types::EventTime;
+//!
+//! let t = EventTime::Offset;
+//! let mut coverage = IntervalSet::new();
+//! coverage.insert(t(0), Some(t(99))).unwrap();
+//! coverage.insert(t(130), Some(t(365))).unwrap();
+//!
+//! let criterion = ContinuousEnrollment::new(180, 20);
+//! assert!(criterion.check(&coverage, t(300)).unwrap().enrolled);
+//!
+//! let check = criterion.check(&coverage, t(260)).unwrap();
+//! assert!(!check.enrolled);
+//! assert_eq!(check.first_gap, Some((t(100), t(129))));
+//!
+//! let qualifying = criterion.qualifying(&coverage);
+//! assert_eq!(qualifying.iter().collect::<Vec<_>>(), vec![(t(290), Some(t(385)))]);
+//! ```
+
+use crate::intervalset::{IntervalSet, MixedTimes};
+use crate::timeline::Timeline;
+use crate::types::EventTime;
+
+/// Requires coverage over `[t0 - lookback, t0]` with gaps of at most
+/// `max_gap` days.
+///
+/// The results of [`qualifying`](ContinuousEnrollment::qualifying) assume
+/// that `max_gap` is at most `lookback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContinuousEnrollment {
+    /// Days before the index date that must be covered.
+    pub lookback: i64,
+    /// Most consecutive uncovered days allowed.
+    pub max_gap: i64,
+}
+
+/// The result of checking one index date.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    /// Whether the criterion holds.
+    pub enrolled: bool,
+    /// The window `[t0 - lookback, t0]`.
+    pub window: (EventTime, EventTime),
+    /// The covered periods within the window, in order.
+    pub covered: Vec<(EventTime, EventTime)>,
+    /// The first uncovered stretch in the window longer than `max_gap` days.
+    pub first_gap: Option<(EventTime, EventTime)>,
+}
+
+/// The number of days from `b` through `e`.
+fn length(b: EventTime, e: EventTime) -> i64 {
+    b.days_until(&e).map_or(0, |n| n.saturating_add(1))
+}
+
+impl ContinuousEnrollment {
+    pub fn new(lookback: i64, max_gap: i64) -> Self {
+        ContinuousEnrollment { lookback, max_gap }
+    }
+
+    /// Checks the criterion at `t0`. Fails if `t0` is not of the same kind
+    /// (date or offset) as the coverage.
+    pub fn check(&self, coverage: &IntervalSet, t0: EventTime) -> Result<Check, MixedTimes> {
+        let begin = t0.add_days(self.lookback.saturating_neg());
+        let missing = coverage.complement(begin, Some(t0))?;
+        let first_gap = missing.iter()
+            .filter_map(|(b, e)| e.map(|e| (b, e)))
+            .find(|(b, e)| length(*b, *e) > self.max_gap);
+
+        let mut window = IntervalSet::new();
+        window.insert(begin, Some(t0))?;
+        let covered = window.intersection(coverage)?
+            .iter()
+            .filter_map(|(b, e)| e.map(|e| (b, e)))
+            .collect();
+
+        Ok(Check { enrolled: first_gap.is_none(), window: (begin, t0), covered, first_gap })
+    }
+
+    /// Checks the criterion at `t0` against the `Enrollment` events of a
+    /// timeline.
+    pub fn check_timeline(&self, timeline: &Timeline, t0: EventTime) -> Result<Check, MixedTimes> {
+        self.check(&IntervalSet::from_enrollment(&timeline.events)?, t0)
+    }
+
+    /// The index dates at which the criterion holds.
+    pub fn qualifying(&self, coverage: &IntervalSet) -> IntervalSet {
+        let spans : Vec<_> = coverage.iter().collect();
+        let (first, last) = match (spans.first(), spans.last()) {
+            (Some(first), Some(last)) => (first.0, last.1),
+            _ => return IntervalSet::new(),
+        };
+        let (k, g) = (self.lookback, self.max_gap);
+
+        // Coverage must begin at most `g` days into the window, and the window
+        // must end at most `g` days after coverage ends.
+        let mut result = IntervalSet::new();
+        result.insert(first.add_days(k.saturating_sub(g)), last.map(|e| e.add_days(g)))
+            .expect("times of one kind");
+
+        // A gap [x, y] longer than `g` days disqualifies every `t0` whose
+        // window holds more than `g` of its days: those in [x + g, y + k - g].
+        let mut disqualified = IntervalSet::new();
+        for (x, y) in coverage.gaps().iter() {
+            let y = y.expect("gaps are closed");
+            if length(x, y) > g {
+                disqualified.insert(x.add_days(g), Some(y.add_days(k.saturating_sub(g))))
+                    .expect("times of one kind");
+            }
+        }
+        result.difference(&disqualified).expect("times of one kind")
+    }
+}
+
+#[cfg(test)]
+mod test_enrollment {
+    use crate::enrollment::*;
+    use crate::timeline::Timeline;
+    use crate::types::*;
+
+    fn t(i: i64) -> EventTime {
+        EventTime::Offset(i)
+    }
+
+    fn coverage(spans: &[(i64, Option<i64>)]) -> IntervalSet {
+        let mut s = IntervalSet::new();
+        for (b, e) in spans {
+            s.insert(t(*b), e.map(t)).unwrap();
+        }
+        s
+    }
+
+    #[test]
+    fn test_check() {
+        let cov = coverage(&[(0, Some(99)), (110, Some(199)), (250, Some(400))]);
+        let ce = ContinuousEnrollment::new(180, 10);
+
+        let c = ce.check(&cov, t(199)).unwrap();
+        assert!(c.enrolled);
+        assert_eq!(c.window, (t(19), t(199)));
+        assert_eq!(c.covered, vec![(t(19), t(99)), (t(110), t(199))]);
+        assert_eq!(c.first_gap, None);
+
+        let c = ce.check(&cov, t(300)).unwrap();
+        assert!(!c.enrolled);
+        assert_eq!(c.first_gap, Some((t(200), t(249))));
+
+        // Gaps of up to `max_gap` days are allowed at the edges of the window.
+        assert!(ce.check(&cov, t(170)).unwrap().enrolled);
+        assert_eq!(ce.check(&cov, t(169)).unwrap().first_gap, Some((t(-11), t(-1))));
+        assert!(ce.check(&cov, t(209)).unwrap().enrolled);
+        assert_eq!(ce.check(&cov, t(210)).unwrap().first_gap, Some((t(200), t(210))));
+
+        let date = EventTime::Date(Date::from_ymd(2010, 1, 1).unwrap());
+        assert_eq!(ce.check(&cov, date), Err(MixedTimes));
+
+        // A gap too long to count in an `i64` still disqualifies.
+        let c = ContinuousEnrollment::new(i64::MAX, 10).check(&cov, t(199)).unwrap();
+        assert_eq!(c.first_gap, Some((t(199 - i64::MAX), t(-1))));
+    }
+
+    #[test]
+    fn test_qualifying() {
+        let ce = ContinuousEnrollment::new(180, 10);
+        let cov = coverage(&[(0, Some(99)), (110, Some(199)), (250, Some(400))]);
+        let q = ce.qualifying(&cov);
+        assert_eq!(q.iter().collect::<Vec<_>>(), vec![(t(170), Some(t(209)))]);
+        for t0 in -50..500 {
+            assert_eq!(q.contains(t(t0)), ce.check(&cov, t(t0)).unwrap().enrolled, "{}", t0);
+        }
+
+        let open = coverage(&[(0, Some(99)), (105, None)]);
+        let q = ce.qualifying(&open);
+        assert_eq!(q.iter().collect::<Vec<_>>(), vec![(t(170), None)]);
+        assert!(ce.qualifying(&IntervalSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let json = "\
+            [\"a\",\"2010-01-01\",\"2010-06-30\",\"Enrollment\",[],\
+             {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-01-01\",\"end\":\"2010-06-30\"},\
+              \"domain\":\"Enrollment\",\"facts\":{}}]\n\
+            [\"a\",\"2010-07-15\",\"2010-12-31\",\"Enrollment\",[],\
+             {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-07-15\",\"end\":\"2010-12-31\"},\
+              \"domain\":\"Enrollment\",\"facts\":{}}]\n";
+        let mut timeline = Timeline::new(SubjectID::IDstr("a".into()));
+        timeline.events = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        let t0 = EventTime::Date(Date::from_ymd(2010, 12, 31).unwrap());
+        assert!(ContinuousEnrollment::new(365, 14).check_timeline(&timeline, t0).unwrap().enrolled);
+        let c = ContinuousEnrollment::new(365, 13).check_timeline(&timeline, t0).unwrap();
+        assert_eq!(c.first_gap.map(|(b, e)| (b.to_string(), e.to_string())),
+                   Some(("2010-07-01".to_string(), "2010-07-14".to_string())));
+    }
+}
@@ -0,0 +1,512 @@
+//! Features: a subject-by-feature matrix of covariates at index dates.
+//!
+//! A [`FeatureSpec`] lists the features to extract, usually read from JSON:
+//!
+//! ```json
+//! [
+//!   {"type": "count", "events": {"concept": "diabetes"}, "window": [-365, -1]},
+//!   {"type": "any", "events": {"domain": "Procedure"}, "window": [null, -1],
+//!    "name": "any_procedure"},
+//!   {"type": "demographic", "field": "Gender"},
+//!   {"type": "age"}
+//! ]
+//! ```
+//!
+//! * `count` is the number of events matching a
+//!   [`Selector`](crate::cohort::Selector) with begins in a
+//!   [`Window`](crate::cohort::Window) around the index date, and `any` is 1
+//!   if there is one. Their columns are named `name`, or else after the
+//!   feature, as in `count:concept=diabetes:-365:-1`.
+//! * `demographic` is a column per value of a field of `DemographicFacts`,
+//!   as in `Gender=F`, which is 1 if the subject has the value.
+//! * `age` is the age in whole years at the index date, from `BirthDate` or
+//!   `BirthYear`. Index dates must be dates.
+//!
+//! An [`Extractor`] adds a row per subject and index date, and gives a sparse
+//! [`Matrix`] in which missing entries are 0, except in `age` columns, where
+//! they are unknown: `NaN` in [`Matrix::to_dense`] and an empty cell in
+//! [`Matrix::write_csv`]. Its columns are in the order of the features, and
+//! those of a `demographic` feature are sorted by value. Column names must be
+//! unique, so [`FeatureSpec::extractor`] fails with [`DuplicateColumn`] if two
+//! features share a name, two `demographic` features share a field, or a name
+//! could be that of a demographic value, as `Gender=F` could.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cohort::{Selector, Window};
+use crate::timeline::Timeline;
+use crate::types::*;
+
+/// A feature, or with `demographic` a group of features.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Feature {
+    Count {
+        events: Selector,
+        window: Window,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    Any {
+        events: Selector,
+        window: Window,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    Demographic {
+        field: DemographicField,
+    },
+    Age,
+}
+
+struct SelectorName<'a>(&'a Selector);
+
+impl fmt::Display for SelectorName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Selector::Concept(x) => write!(f, "concept={}", x),
+            Selector::Code(x) => write!(f, "code={}", x),
+            Selector::Domain(x) => write!(f, "domain={}", x),
+        }
+    }
+}
+
+fn window_name(window: &Window) -> String {
+    let bound = |x: Option<i64>| x.map_or(String::new(), |x| x.to_string());
+    format!("{}:{}", bound(window.0), bound(window.1))
+}
+
+impl Feature {
+    /// The name of the column of a `count`, `any` or `age` feature.
+    pub fn name(&self) -> Option<String> {
+        match self {
+            Feature::Count { name: Some(name), .. } | Feature::Any { name: Some(name), .. } =>
+                Some(name.clone()),
+            Feature::Count { events, window, .. } =>
+                Some(format!("count:{}:{}", SelectorName(events), window_name(window))),
+            Feature::Any { events, window, .. } =>
+                Some(format!("any:{}:{}", SelectorName(events), window_name(window))),
+            Feature::Demographic { .. } => None,
+            Feature::Age => Some("age".to_string()),
+        }
+    }
+}
+
+/// Two features with the same column, named by the column or, for
+/// `demographic` features, by the `Field=` prefix of their columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateColumn(pub String);
+
+impl fmt::Display for DuplicateColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "more than one feature has the column {:?}", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateColumn {}
+
+/// The features to extract.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct FeatureSpec {
+    pub features: Vec<Feature>,
+}
+
+impl FeatureSpec {
+    pub fn from_json<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(io::BufReader::new(reader))
+    }
+
+    /// An extractor of the features. Fails if two features could give
+    /// columns of the same name.
+    pub fn extractor(&self) -> Result<Extractor<'_>, DuplicateColumn> {
+        let prefixes: Vec<String> = self.features.iter()
+            .filter_map(|feature| match feature {
+                Feature::Demographic { field } => Some(format!("{:?}=", field)),
+                _ => None,
+            })
+            .collect();
+        for (k, prefix) in prefixes.iter().enumerate() {
+            if prefixes[..k].contains(prefix) {
+                return Err(DuplicateColumn(prefix.clone()));
+            }
+        }
+
+        let mut extractor = Extractor {
+            spec: self, matrix: Matrix::default(), columns: HashMap::new(), order: Vec::new(),
+        };
+        for (k, feature) in self.features.iter().enumerate() {
+            if let Some(name) = feature.name() {
+                if extractor.columns.contains_key(&name) || prefixes.iter().any(|p| name.starts_with(p)) {
+                    return Err(DuplicateColumn(name));
+                }
+                extractor.column(k, name);
+            }
+        }
+        Ok(extractor)
+    }
+}
+
+/// A sparse matrix with labelled rows and columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Matrix {
+    /// The subject and index date of each row.
+    pub rows: Vec<(SubjectID<'static>, EventTime)>,
+    pub columns: Vec<String>,
+    /// The known entries as `(row, column, value)`, ordered by row and then
+    /// by column. Entries of 0 are left out, except in `unknown` columns.
+    pub entries: Vec<(usize, usize, f64)>,
+    /// The columns whose missing entries are unknown rather than 0 (those of
+    /// `age` features).
+    pub unknown: Vec<usize>,
+}
+
+impl Matrix {
+    /// The matrix as dense rows, with `NaN` for missing entries of `unknown`
+    /// columns.
+    pub fn to_dense(&self) -> Vec<Vec<f64>> {
+        let mut row = vec![0.0; self.columns.len()];
+        for j in &self.unknown {
+            row[*j] = f64::NAN;
+        }
+        let mut dense = vec![row; self.rows.len()];
+        for (i, j, x) in &self.entries {
+            dense[*i][*j] = *x;
+        }
+        dense
+    }
+
+    /// Writes the dense matrix as CSV with the columns `patient_id`, `index`
+    /// and one per feature. Unknown entries are empty.
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let mut header = vec!["patient_id", "index"];
+        header.extend(self.columns.iter().map(String::as_str));
+        writer.write_record(&header)?;
+        for ((subject, index), row) in self.rows.iter().zip(self.to_dense()) {
+            let subject = match subject {
+                SubjectID::IDstr(s) => s.to_string(),
+                SubjectID::Idint(i) => i.to_string(),
+            };
+            let mut record = vec![subject, index.to_string()];
+            record.extend(row.iter().map(|x| if x.is_nan() { String::new() } else { x.to_string() }));
+            writer.write_record(&record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the entries as CSV triplets `row,column,value` of zero-based
+    /// indices into [`rows`](Matrix::rows) and [`columns`](Matrix::columns).
+    pub fn write_triplets<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["row", "column", "value"])?;
+        for (i, j, x) in &self.entries {
+            writer.write_record([i.to_string(), j.to_string(), x.to_string()])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the row labels as CSV with the columns `row`, `patient_id` and
+    /// `index`.
+    pub fn write_row_labels<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["row", "patient_id", "index"])?;
+        for (i, (subject, index)) in self.rows.iter().enumerate() {
+            writer.serialize((i, subject, index.to_string()))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the column labels as CSV with the columns `column` and `name`.
+    pub fn write_column_labels<W: io::Write>(&self, writer: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["column", "name"])?;
+        for (j, name) in self.columns.iter().enumerate() {
+            writer.serialize((j, name))?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Builds a [`Matrix`] from the features of a [`FeatureSpec`].
+pub struct Extractor<'s> {
+    spec: &'s FeatureSpec,
+    matrix: Matrix,
+    columns: HashMap<String, usize>,
+    /// The feature of each column, by which columns are ordered.
+    order: Vec<usize>,
+}
+
+/// A demographic value as a string: strings as they are, other JSON as JSON.
+fn demographic_value(info: &serde_json::Value) -> String {
+    match info {
+        serde_json::Value::String(s) => s.clone(),
+        x => x.to_string(),
+    }
+}
+
+/// The demographic values of a timeline for `field`.
+fn demographics<'t>(timeline: &'t Timeline, field: DemographicField)
+                    -> impl Iterator<Item = &'t serde_json::Value> {
+    timeline.events.iter().filter_map(move |e| match &e.context.facts {
+        Domain::Demographics(x) if x.demo.field == field => x.demo.info.as_ref(),
+        _ => None,
+    })
+}
+
+/// The age in whole years at `index`.
+fn age(timeline: &Timeline, index: EventTime) -> Option<f64> {
+    let index = index.as_date()?;
+    let birth_date = demographics(timeline, DemographicField::BirthDate)
+        .find_map(|x| demographic_value(x).parse::<EventTime>().ok()?.as_date());
+    if let Some(birth) = birth_date {
+        let before_birthday = (index.month(), index.day()) < (birth.month(), birth.day());
+        return Some(f64::from(index.year() - birth.year() - i32::from(before_birthday)));
+    }
+    demographics(timeline, DemographicField::BirthYear)
+        .find_map(|x| demographic_value(x).parse::<i32>().ok())
+        .map(|year| f64::from(index.year() - year))
+}
+
+impl Extractor<'_> {
+    fn column(&mut self, feature: usize, name: String) -> usize {
+        let matrix = &mut self.matrix;
+        let order = &mut self.order;
+        *self.columns.entry(name).or_insert_with_key(|name| {
+            matrix.columns.push(name.clone());
+            order.push(feature);
+            matrix.columns.len() - 1
+        })
+    }
+
+    /// Adds the row of a subject at an index date.
+    pub fn add(&mut self, timeline: &Timeline, index: EventTime) {
+        let row = self.matrix.rows.len();
+        self.matrix.rows.push((timeline.subject.as_borrowed().into_owned(), index));
+        let mut entries = Vec::new();
+
+        for (k, feature) in self.spec.features.iter().enumerate() {
+            match feature {
+                Feature::Count { events, window, .. } | Feature::Any { events, window, .. } => {
+                    let column = self.column(k, feature.name().unwrap());
+                    let n = timeline.events.iter()
+                        .filter(|e| events.matches(e) && window.contains(index, e.b))
+                        .count();
+                    let x = match feature {
+                        Feature::Any { .. } => (n > 0) as usize,
+                        _ => n,
+                    };
+                    if x > 0 {
+                        entries.push((column, x as f64));
+                    }
+                }
+                Feature::Demographic { field } => {
+                    let names: Vec<String> = demographics(timeline, *field)
+                        .map(|x| format!("{:?}={}", field, demographic_value(x)))
+                        .collect();
+                    for name in names {
+                        let column = self.column(k, name);
+                        entries.push((column, 1.0));
+                    }
+                }
+                Feature::Age => {
+                    let column = self.column(k, feature.name().unwrap());
+                    if let Some(x) = age(timeline, index) {
+                        entries.push((column, x));
+                    }
+                }
+            }
+        }
+
+        entries.sort_by_key(|(j, _)| *j);
+        entries.dedup_by_key(|(j, _)| *j);
+        self.matrix.entries.extend(entries.into_iter().map(|(j, x)| (row, j, x)));
+    }
+
+    /// The matrix of the rows added.
+    pub fn finish(self) -> Matrix {
+        let Extractor { spec, mut matrix, order, .. } = self;
+        let mut by_order: Vec<usize> = (0..matrix.columns.len()).collect();
+        by_order.sort_by(|a, b| order[*a].cmp(&order[*b])
+                         .then_with(|| matrix.columns[*a].cmp(&matrix.columns[*b])));
+        // Columns of count, any and age features are created in feature order
+        // by `FeatureSpec::extractor`, so only those of demographic features move.
+        let mut new_index = vec![0; by_order.len()];
+        for (new, old) in by_order.iter().enumerate() {
+            new_index[*old] = new;
+        }
+        matrix.columns = by_order.iter().map(|j| matrix.columns[*j].clone()).collect();
+        for entry in &mut matrix.entries {
+            entry.1 = new_index[entry.1];
+        }
+        matrix.entries.sort_by_key(|(i, j, _)| (*i, *j));
+        matrix.unknown = by_order.iter().enumerate()
+            .filter(|(_, old)| matches!(spec.features[order[**old]], Feature::Age))
+            .map(|(new, _)| new)
+            .collect();
+        matrix
+    }
+}
+
+#[cfg(test)]
+mod test_features {
+    use crate::features::*;
+
+    fn event(p: &str, b: &str, d: &str, concepts: &[&str], facts: &str) -> OwnedEvent {
+        crate::test_support::event(p, b, None, d, concepts, facts)
+    }
+
+    fn demo(p: &str, field: &str, info: &str) -> OwnedEvent {
+        let facts = format!("{{\"demo\":{{\"field\":\"{}\",\"info\":\"{}\"}}}}", field, info);
+        event(p, "2000-01-01", "Demographics", &[], &facts)
+    }
+
+    fn timeline(p: &str, events: Vec<OwnedEvent>) -> Timeline<'static> {
+        let mut t = Timeline::new(SubjectID::IDstr(p.to_string().into()));
+        t.events = events;
+        t
+    }
+
+    fn date(s: &str) -> EventTime {
+        s.parse().unwrap()
+    }
+
+    fn spec() -> FeatureSpec {
+        FeatureSpec::from_json(r#"[
+            {"type": "count", "events": {"concept": "dm"}, "window": [-365, -1]},
+            {"type": "any", "events": {"domain": "Death"}, "window": [null, null], "name": "died"},
+            {"type": "demographic", "field": "Gender"},
+            {"type": "age"}
+        ]"#.as_bytes()).unwrap()
+    }
+
+    fn matrix() -> Matrix {
+        let a = timeline("a", vec![
+            demo("a", "Gender", "M"),
+            demo("a", "BirthDate", "1980-06-15"),
+            event("a", "2009-06-01", "Undefined", &["dm"], "{}"),
+            event("a", "2009-12-01", "Undefined", &["dm"], "{}"),
+            event("a", "2010-06-01", "Undefined", &["dm"], "{}"),
+        ]);
+        let b = timeline("b", vec![
+            demo("b", "Gender", "F"),
+            demo("b", "BirthYear", "1990"),
+            event("b", "2011-01-01", "Death", &[], "{}"),
+        ]);
+        // No birth data, and born at the index date.
+        let c = timeline("c", vec![]);
+        let d = timeline("d", vec![demo("d", "BirthDate", "2010-06-01")]);
+        let spec = spec();
+        let mut extractor = spec.extractor().unwrap();
+        extractor.add(&a, date("2010-06-01"));
+        extractor.add(&a, date("2010-06-15"));
+        extractor.add(&b, date("2010-06-01"));
+        extractor.add(&c, date("2010-06-01"));
+        extractor.add(&d, date("2010-06-01"));
+        extractor.finish()
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = spec();
+        let names: Vec<_> = spec.features.iter().map(Feature::name).collect();
+        assert_eq!(names, vec![Some("count:concept=dm:-365:-1".to_string()), Some("died".to_string()),
+                               None, Some("age".to_string())]);
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(FeatureSpec::from_json(json.as_bytes()).unwrap(), spec);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let m = matrix();
+        assert_eq!(m.columns, vec!["count:concept=dm:-365:-1", "died", "Gender=F", "Gender=M", "age"]);
+        assert_eq!(m.rows[2], (SubjectID::IDstr("b".into()), date("2010-06-01")));
+        assert_eq!(m.unknown, vec![4]);
+        let dense = m.to_dense();
+        assert_eq!(dense[..3], vec![
+            vec![2.0, 0.0, 0.0, 1.0, 29.0],
+            vec![2.0, 0.0, 0.0, 1.0, 30.0],
+            vec![0.0, 1.0, 1.0, 0.0, 20.0],
+        ]);
+        assert_eq!(dense[3][..4], [0.0; 4]);
+        assert!(dense[3][4].is_nan());
+        assert_eq!(dense[4], vec![0.0; 5]);
+        assert_eq!(m.entries.len(), 10);
+        assert!(m.entries.windows(2).all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+
+        let spec = spec();
+        let empty = spec.extractor().unwrap().finish();
+        assert_eq!(empty.columns, vec!["count:concept=dm:-365:-1", "died", "age"]);
+        assert_eq!(empty.unknown, vec![2]);
+        assert!(empty.rows.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_columns() {
+        let duplicate = |json: &str| {
+            FeatureSpec::from_json(json.as_bytes()).unwrap().extractor().err()
+        };
+        let column = |name: &str| Some(DuplicateColumn(name.to_string()));
+        assert_eq!(duplicate(r#"[
+            {"type": "any", "events": {"domain": "Death"}, "window": [null, null], "name": "age"},
+            {"type": "age"}
+        ]"#), column("age"));
+        assert_eq!(duplicate(r#"[
+            {"type": "count", "events": {"concept": "dm"}, "window": [-365, -1]},
+            {"type": "count", "events": {"concept": "dm"}, "window": [-365, -1]}
+        ]"#), column("count:concept=dm:-365:-1"));
+        assert_eq!(duplicate(r#"[
+            {"type": "demographic", "field": "Gender"},
+            {"type": "demographic", "field": "Race"},
+            {"type": "demographic", "field": "Gender"}
+        ]"#), column("Gender="));
+        assert_eq!(duplicate(r#"[
+            {"type": "demographic", "field": "Gender"},
+            {"type": "any", "events": {"domain": "Death"}, "window": [null, null], "name": "Gender=F"}
+        ]"#), column("Gender=F"));
+        assert_eq!(duplicate(r#"[
+            {"type": "demographic", "field": "Gender"},
+            {"type": "any", "events": {"domain": "Death"}, "window": [null, null], "name": "Race=x"}
+        ]"#), None);
+        assert_eq!(column("age").unwrap().to_string(), "more than one feature has the column \"age\"");
+    }
+
+    #[test]
+    fn test_export() {
+        let m = matrix();
+        let mut csv = Vec::new();
+        m.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+            patient_id,index,count:concept=dm:-365:-1,died,Gender=F,Gender=M,age\n\
+            a,2010-06-01,2,0,0,1,29\n\
+            a,2010-06-15,2,0,0,1,30\n\
+            b,2010-06-01,0,1,1,0,20\n\
+            c,2010-06-01,0,0,0,0,\n\
+            d,2010-06-01,0,0,0,0,0\n");
+
+        let mut triplets = Vec::new();
+        m.write_triplets(&mut triplets).unwrap();
+        let triplets = String::from_utf8(triplets).unwrap();
+        assert!(triplets.starts_with("row,column,value\n0,0,2\n0,3,1\n0,4,29\n1,0,2\n"));
+        assert_eq!(triplets.lines().count(), 11);
+        assert!(triplets.ends_with("2,4,20\n4,4,0\n"));
+
+        let mut rows = Vec::new();
+        m.write_row_labels(&mut rows).unwrap();
+        assert_eq!(String::from_utf8(rows).unwrap(), "\
+            row,patient_id,index\n0,a,2010-06-01\n1,a,2010-06-15\n2,b,2010-06-01\n\
+            3,c,2010-06-01\n4,d,2010-06-01\n");
+
+        let mut columns = Vec::new();
+        m.write_column_labels(&mut columns).unwrap();
+        assert!(String::from_utf8(columns).unwrap().starts_with("column,name\n0,count:concept=dm:-365:-1\n"));
+    }
+}
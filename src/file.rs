@@ -0,0 +1,137 @@
+//! Memory-mapped files of newline-delimited JSON events.
+//!
+//! An [`EventFile`] maps a file into memory, so events can borrow their
+//! strings and raw values straight from the file without reading it into a
+//! `String` or copying each line. [`EventFile::events`] iterates over the
+//! events with their line numbers; to run a pipeline, pass
+//! [`EventFile::as_bytes`] to [`process_bytes`](crate::process::process_bytes).
+//!
+//! Example:
+//! ```no_run
+//! use eddeserus::file::EventFile;
+//!
+//! let file = unsafe { EventFile::open("events.json") }.unwrap();
+//! for event in file.events() {
+//!     let (line, event) = event.unwrap();
+//!     println!("{}: {}", line, event.d);
+//! }
+//! ```
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::process::{LineError, Lines};
+use crate::types::Event;
+
+/// A memory-mapped file of newline-delimited JSON events.
+pub struct EventFile {
+    map: Mmap,
+}
+
+impl EventFile {
+    /// Maps the file at `path` into memory.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated, by this or any other
+    /// process, while the `EventFile` or any event borrowed from it is alive.
+    /// Otherwise events may change under their borrows, or reading them may
+    /// crash the process.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(EventFile { map: Mmap::map(&file)? })
+    }
+
+    /// The contents of the file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// Iterates over the events in the file, skipping blank lines.
+    pub fn events(&self) -> Events<'_> {
+        Events {
+            lines: self.as_bytes().split_inclusive(|b| *b == b'\n'),
+            state: Lines::default(),
+        }
+    }
+}
+
+/// An iterator over the events of an [`EventFile`], with their 1-based line
+/// numbers. Lines that fail to deserialize are yielded as errors, and
+/// iteration continues after them.
+pub struct Events<'a> {
+    lines: std::slice::SplitInclusive<'a, u8, fn(&u8) -> bool>,
+    state: Lines,
+}
+
+impl<'a> Iterator for Events<'a> {
+    type Item = Result<(usize, Event<'a>), LineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for raw in &mut self.lines {
+            if let Some(result) = self.state.parse(raw) {
+                return Some(result.map(|event| (self.state.line, event)));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test_file {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::file::*;
+    use crate::process::*;
+    use crate::types::*;
+
+    /// Writes `contents` to a file in the temporary directory.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("eddeserus-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_events() {
+        let mut json = include_str!("../resources/50events.json").to_string();
+        json.push_str("\nnot an event\n");
+        let path = temp_file("events.json", &json);
+
+        let file = unsafe { EventFile::open(&path) }.unwrap();
+        let events : Vec<_> = file.events().collect();
+        assert_eq!(events.len(), 51);
+        assert!(events[..50].iter().enumerate()
+                .all(|(i, e)| matches!(e, Ok((line, _)) if *line == i + 1)));
+        assert_eq!(events[50].as_ref().unwrap_err().line, 52);
+
+        // Events borrow from the mapped file.
+        let (_, first) = events[0].as_ref().unwrap();
+        assert!(matches!(first.d, std::borrow::Cow::Borrowed(_)));
+
+        let mut out : Vec<Event> = Vec::new();
+        let mut errors : Vec<LineError> = Vec::new();
+        let summary = process_bytes_with_errors(file.as_bytes(), &mut map(|e| e),
+                                                &mut out, &mut errors).unwrap();
+        assert_eq!(summary, Summary { ok: 50, failed: 1 });
+        assert_eq!(out.len(), 50);
+
+        drop(events);
+        drop(file);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_empty() {
+        let path = temp_file("empty.json", "");
+        let file = unsafe { EventFile::open(&path) }.unwrap();
+        assert_eq!(file.events().count(), 0);
+        drop(file);
+        fs::remove_file(path).unwrap();
+    }
+}
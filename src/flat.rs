@@ -0,0 +1,404 @@
+//! Flat CSV export of events.
+//!
+//! Each event becomes one row of a fixed set of [`Column`]s, with empty cells
+//! where a field is missing or does not apply to the event's domain:
+//!
+//! | columns | from |
+//! |---------|------|
+//! | `patient_id`, `begin`, `end`, `domain` | the header |
+//! | `concepts` | the concepts, joined by `;` |
+//! | `code`, `codebook`, `location` | the facts |
+//! | `claim_id`, `claim_type`, `claim_index`, `claim_procedure` | the claim |
+//! | `charge`, `cost`, `allowed`, `transaction` | the cost of a Claim |
+//! | `days_supply`, `quantity`, `strength` | the fill of a Medication |
+//! | `lab_text`, `lab_number`, `lab_units` | the value of a Labs event |
+//! | `demo_field`, `demo_info` | the facts of a Demographics event |
+//! | `source`, `misc` | the raw JSON |
+//!
+//! [`CsvOut`] writes every event to one file with a chosen list of columns;
+//! [`CsvByDomain`] writes the events of each domain to its own file in a
+//! directory, by default with only the columns that apply to the domain.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::process::OutHandler;
+use crate::types::*;
+
+/// A column of the flat schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Column {
+    Patient,
+    Begin,
+    End,
+    Domain,
+    Concepts,
+    Code,
+    Codebook,
+    Location,
+    ClaimId,
+    ClaimType,
+    ClaimIndex,
+    ClaimProcedure,
+    Charge,
+    Cost,
+    Allowed,
+    Transaction,
+    DaysSupply,
+    Quantity,
+    Strength,
+    LabText,
+    LabNumber,
+    LabUnits,
+    DemoField,
+    DemoInfo,
+    Source,
+    Misc,
+}
+
+impl Column {
+    /// Every column, in schema order.
+    pub const ALL: [Column; 26] = [
+        Column::Patient, Column::Begin, Column::End, Column::Domain, Column::Concepts,
+        Column::Code, Column::Codebook, Column::Location,
+        Column::ClaimId, Column::ClaimType, Column::ClaimIndex, Column::ClaimProcedure,
+        Column::Charge, Column::Cost, Column::Allowed, Column::Transaction,
+        Column::DaysSupply, Column::Quantity, Column::Strength,
+        Column::LabText, Column::LabNumber, Column::LabUnits,
+        Column::DemoField, Column::DemoInfo,
+        Column::Source, Column::Misc,
+    ];
+
+    /// The name of the column, as written in the header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Column::Patient        => "patient_id",
+            Column::Begin          => "begin",
+            Column::End            => "end",
+            Column::Domain         => "domain",
+            Column::Concepts       => "concepts",
+            Column::Code           => "code",
+            Column::Codebook       => "codebook",
+            Column::Location       => "location",
+            Column::ClaimId        => "claim_id",
+            Column::ClaimType      => "claim_type",
+            Column::ClaimIndex     => "claim_index",
+            Column::ClaimProcedure => "claim_procedure",
+            Column::Charge         => "charge",
+            Column::Cost           => "cost",
+            Column::Allowed        => "allowed",
+            Column::Transaction    => "transaction",
+            Column::DaysSupply     => "days_supply",
+            Column::Quantity       => "quantity",
+            Column::Strength       => "strength",
+            Column::LabText        => "lab_text",
+            Column::LabNumber      => "lab_number",
+            Column::LabUnits       => "lab_units",
+            Column::DemoField      => "demo_field",
+            Column::DemoInfo       => "demo_info",
+            Column::Source         => "source",
+            Column::Misc           => "misc",
+        }
+    }
+
+    /// Whether the column can have a value for events of a domain.
+    pub fn applies_to(&self, domain: &str) -> bool {
+        let coded = matches!(domain, "Diagnosis" | "Labs" | "Medication" | "Procedure");
+        match self {
+            Column::Code | Column::Codebook => coded,
+            Column::Location | Column::ClaimId | Column::ClaimType | Column::ClaimIndex
+                | Column::ClaimProcedure => coded || domain == "Claim",
+            Column::Charge | Column::Cost | Column::Allowed | Column::Transaction =>
+                domain == "Claim",
+            Column::DaysSupply | Column::Quantity | Column::Strength => domain == "Medication",
+            Column::LabText | Column::LabNumber | Column::LabUnits => domain == "Labs",
+            Column::DemoField | Column::DemoInfo => domain == "Demographics",
+            _ => true,
+        }
+    }
+
+    /// The columns that apply to a domain, in schema order.
+    pub fn for_domain(domain: &str) -> Vec<Column> {
+        Column::ALL.iter().copied().filter(|c| c.applies_to(domain)).collect()
+    }
+
+    /// The value of the column for an event, or `None` if it has none.
+    pub fn value<'e>(&self, event: &'e Event) -> Option<Cow<'e, str>> {
+        let facts = &event.context.facts;
+        let claim = match facts {
+            Domain::Claim(x) => Some(&x.claim),
+            Domain::Diagnosis(x) => x.claim.as_ref(),
+            Domain::Labs(x) => x.claim.as_ref(),
+            Domain::Medication(x) => x.claim.as_ref(),
+            Domain::Procedure(x) => x.claim.as_ref(),
+            _ => None,
+        };
+        let cost = match facts {
+            Domain::Claim(x) => x.cost.as_ref(),
+            _ => None,
+        };
+        let fill = match facts {
+            Domain::Medication(x) => x.fill.as_ref(),
+            _ => None,
+        };
+        let lab = match facts {
+            Domain::Labs(x) => Some(&x.value),
+            _ => None,
+        };
+        let demo = match facts {
+            Domain::Demographics(x) => Some(&x.demo),
+            _ => None,
+        };
+        let borrowed = |x: &'e Cow<'e, str>| Cow::Borrowed(&**x);
+        let owned = |x: &dyn fmt::Display| Cow::Owned(x.to_string());
+
+        match self {
+            Column::Patient => Some(owned(&event.p)),
+            Column::Begin => Some(owned(&event.b)),
+            Column::End => event.e.as_ref().map(|e| owned(e)),
+            Column::Domain => Some(borrowed(&event.d)),
+            Column::Concepts => Some(event.concepts.join(";")).filter(|s| !s.is_empty()).map(Cow::Owned),
+            Column::Code => facts.code().map(|c| borrowed(&c.code)),
+            Column::Codebook => facts.code().and_then(|c| c.codebook).map(|c| Cow::Owned(variant_name(&c))),
+            Column::Location => match facts {
+                Domain::Claim(x) => x.location,
+                Domain::Diagnosis(x) => x.location,
+                Domain::Labs(x) => x.location,
+                Domain::Medication(x) => x.location,
+                Domain::Procedure(x) => x.location,
+                _ => None,
+            }.map(|l| Cow::Owned(variant_name(&l))),
+            Column::ClaimId => claim.map(|c| borrowed(&c.id)),
+            Column::ClaimType => claim.and_then(|c| c.r#type.as_deref()).map(Cow::Borrowed),
+            Column::ClaimIndex => claim.and_then(|c| c.index).map(|i| owned(&i)),
+            Column::ClaimProcedure => claim.and_then(|c| c.procedure.as_deref()).map(Cow::Borrowed),
+            Column::Charge => cost.and_then(|c| c.charge.as_ref()).map(borrowed),
+            Column::Cost => cost.map(|c| borrowed(&c.cost)),
+            Column::Allowed => cost.and_then(|c| c.allowed.as_ref()).map(borrowed),
+            Column::Transaction => cost.and_then(|c| c.transaction.as_ref()).map(borrowed),
+            Column::DaysSupply => fill.and_then(|f| f.days_supply).map(|x| owned(&x)),
+            Column::Quantity => fill.and_then(|f| f.quantity).map(|x| owned(&x)),
+            Column::Strength => fill.and_then(|f| f.strength.as_ref()).map(borrowed),
+            Column::LabText => lab.and_then(|l| l.text.as_ref()).map(borrowed),
+            Column::LabNumber => lab.and_then(|l| l.number).map(|x| owned(&x)),
+            Column::LabUnits => lab.map(|l| borrowed(&l.units)),
+            Column::DemoField => demo.map(|d| Cow::Owned(variant_name(&d.field))),
+            Column::DemoInfo => demo.and_then(|d| d.info.as_ref()).map(|info| match info {
+                serde_json::Value::String(s) => Cow::Borrowed(s.as_str()),
+                x => Cow::Owned(x.to_string()),
+            }),
+            Column::Source => event.context.source.as_ref().map(|x| Cow::Borrowed(x.get())),
+            Column::Misc => event.context.misc.as_ref().map(|x| Cow::Borrowed(x.get())),
+        }
+    }
+}
+
+/// The serialized name of a unit variant, e.g. `medicaid_cat`.
+pub(crate) fn variant_name<T: serde::Serialize>(x: &T) -> String {
+    match serde_json::to_value(x) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+/// A name that is not a column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownColumn(pub String);
+
+impl fmt::Display for UnknownColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown column {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownColumn {}
+
+impl FromStr for Column {
+    type Err = UnknownColumn;
+
+    fn from_str(s: &str) -> Result<Column, UnknownColumn> {
+        Column::ALL.iter().copied()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| UnknownColumn(s.to_string()))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Writers
+
+fn write_header<W: Write>(writer: &mut csv::Writer<W>, columns: &[Column]) -> io::Result<()> {
+    writer.write_record(columns.iter().map(Column::name))?;
+    Ok(())
+}
+
+fn write_row<W: Write>(writer: &mut csv::Writer<W>, columns: &[Column], event: &Event)
+                       -> io::Result<()> {
+    for column in columns {
+        writer.write_field(column.value(event).as_deref().unwrap_or(""))?;
+    }
+    writer.write_record(None::<&[u8]>)?;
+    Ok(())
+}
+
+/// Writes events as CSV rows of the given columns, after a header.
+pub struct CsvOut<W: Write> {
+    writer: csv::Writer<W>,
+    columns: Vec<Column>,
+}
+
+impl<W: Write> CsvOut<W> {
+    /// Writes the header.
+    pub fn new(writer: W, columns: Vec<Column>) -> io::Result<Self> {
+        let mut writer = csv::Writer::from_writer(writer);
+        write_header(&mut writer, &columns)?;
+        Ok(CsvOut { writer, columns })
+    }
+
+    /// Flushes the buffer and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<'a, W: Write> OutHandler<'a> for CsvOut<W> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        write_row(&mut self.writer, &self.columns, &event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes the events of each domain to `<dir>/<domain>.csv`, creating each
+/// file when its first event arrives.
+pub struct CsvByDomain {
+    dir: PathBuf,
+    columns: Option<Vec<Column>>,
+    files: HashMap<String, (csv::Writer<File>, Vec<Column>)>,
+}
+
+impl CsvByDomain {
+    /// Writes the given columns to every file, or with `None` the columns
+    /// that apply to each file's domain.
+    pub fn new<P: Into<PathBuf>>(dir: P, columns: Option<Vec<Column>>) -> Self {
+        CsvByDomain { dir: dir.into(), columns, files: HashMap::new() }
+    }
+
+    /// The paths of the files written so far, by domain.
+    pub fn paths(&self) -> Vec<(String, PathBuf)> {
+        let mut paths: Vec<_> = self.files.keys()
+            .map(|d| (d.clone(), self.dir.join(format!("{}.csv", d))))
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl<'a> OutHandler<'a> for CsvByDomain {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        if !self.files.contains_key(&*event.d) {
+            let domain = event.d.to_string();
+            if domain.is_empty() || domain.contains(['/', '\\', '.']) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("domain {:?} cannot name a file", domain)));
+            }
+            let columns = self.columns.clone().unwrap_or_else(|| Column::for_domain(&domain));
+            let mut writer = csv::Writer::from_writer(File::create(self.dir.join(format!("{}.csv", domain)))?);
+            write_header(&mut writer, &columns)?;
+            self.files.insert(domain, (writer, columns));
+        }
+        let (writer, columns) = self.files.get_mut(&*event.d).unwrap();
+        write_row(writer, columns, &event)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.files.values_mut().try_for_each(|(w, _)| w.flush())
+    }
+}
+
+#[cfg(test)]
+mod test_flat {
+    use crate::flat::*;
+    use crate::process::*;
+    use crate::test_support::EVENTS;
+
+    fn write_all<O: for<'a> OutHandler<'a>>(out: &mut O) {
+        process_str(EVENTS, &mut map(|e| e), out).unwrap();
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!("claim_index".parse(), Ok(Column::ClaimIndex));
+        assert_eq!("dx".parse::<Column>(), Err(UnknownColumn("dx".to_string())));
+        assert!(Column::ALL.iter().all(|c| c.name().parse() == Ok(*c)));
+        assert_eq!(Column::for_domain("Death"), vec![
+            Column::Patient, Column::Begin, Column::End, Column::Domain, Column::Concepts,
+            Column::Source, Column::Misc,
+        ]);
+        assert_eq!(Column::for_domain("Claim").len(), 16);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut out = CsvOut::new(Vec::new(), Column::ALL.to_vec()).unwrap();
+        write_all(&mut out);
+        let csv = String::from_utf8(out.into_inner().unwrap()).unwrap();
+        assert_eq!(csv, "\
+            patient_id,begin,end,domain,concepts,code,codebook,location,\
+            claim_id,claim_type,claim_index,claim_procedure,charge,cost,allowed,transaction,\
+            days_supply,quantity,strength,lab_text,lab_number,lab_units,demo_field,demo_info,source,misc\n\
+            a,2010-01-01,2010-01-02,Claim,,,,Inpatient,c1,IP,2,x,20,12.5,15,t,,,,,,,,,,\n\
+            a,2011-05-06,,Death,,,,,,,,,,,,,,,,,,,,,\"{ \"\"table\"\" : [1, 2.50,\"\"\\u00e9\"\"] }\",\n\
+            7,0,,Demographics,,,,,,,,,,,,,,,,,,,RaceCodes,\"[\"\"a\"\",{\"\"b\"\":1}]\",,\n\
+            7,1,,Demographics,,,,,,,,,,,,,,,,,,,BirthYear,1980,,\n\
+            7,2,5,Diagnosis,dm,E11.9,ICD10,Outpatient,c2,,,,,,,,,,,,,,,,,\n\
+            7,2,,Eligibility,,,,,,,,,,,,,,,,,,,,,,\n\
+            b,2009-01-01,2009-12-31,Enrollment,,,,,,,,,,,,,,,,,,,,,,\n\
+            7,3,,Labs,a1c;lab,4548-4,LOINC,,,,,,,,,,,,,high,6.5,%,,,,\"{\"\"k\"\" :[1,2]}\"\n\
+            a,2010-01-01,,Medication,statin,00093005801,NDC,Unknown,c3,,,,,,,,30,60,5mg,,,,,,,\n\
+            a,2010-02-01,,Medication,,statin,,,,,,,,,,,,,,,,,,,\
+            \"{\"\"table\"\":\"\"rx, \\\"\"fills\\\"\"\"\"}\",\n\
+            7,-4,,Procedure,,99213,CPT,,,,,,,,,,,,,,,,,,,\n\
+            c,1969-12-31,,Undefined,,,,,,,,,,,,,,,,,,,,,,\n");
+
+        let columns = vec![Column::Patient, Column::Code, Column::Misc];
+        let mut out = CsvOut::new(Vec::new(), columns).unwrap();
+        write_all(&mut out);
+        let csv = String::from_utf8(out.into_inner().unwrap()).unwrap();
+        assert_eq!(csv, "patient_id,code,misc\na,,\na,,\n7,,\n7,,\n7,E11.9,\n7,,\nb,,\n\
+                         7,4548-4,\"{\"\"k\"\" :[1,2]}\"\na,00093005801,\na,statin,\n7,99213,\nc,,\n");
+    }
+
+    #[test]
+    fn test_by_domain() {
+        let dir = std::env::temp_dir().join(format!("eddeserus-flat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut out = CsvByDomain::new(&dir, None);
+        write_all(&mut out);
+        OutHandler::flush(&mut out).unwrap();
+        let paths = out.paths();
+        assert_eq!(paths.iter().map(|(d, _)| d.as_str()).collect::<Vec<_>>(),
+                   vec!["Claim", "Death", "Demographics", "Diagnosis", "Eligibility", "Enrollment",
+                        "Labs", "Medication", "Procedure", "Undefined"]);
+        let labs = std::fs::read_to_string(dir.join("Labs.csv")).unwrap();
+        assert_eq!(labs, "\
+            patient_id,begin,end,domain,concepts,code,codebook,location,\
+            claim_id,claim_type,claim_index,claim_procedure,lab_text,lab_number,lab_units,source,misc\n\
+            7,3,,Labs,a1c;lab,4548-4,LOINC,,,,,,high,6.5,%,,\"{\"\"k\"\" :[1,2]}\"\n");
+
+        let mut out = CsvByDomain::new(&dir, Some(vec![Column::Patient, Column::Domain]));
+        write_all(&mut out);
+        drop(out);
+        let claims = std::fs::read_to_string(dir.join("Claim.csv")).unwrap();
+        assert_eq!(claims, "patient_id,domain\na,Claim\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
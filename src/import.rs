@@ -0,0 +1,892 @@
+//! Import of events from CSV or TSV tables.
+//!
+//! An [`ImportConfig`], usually read from JSON, names the columns from which
+//! each row of a table builds events of one or more domains:
+//!
+//! ```json
+//! {
+//!   "delimiter": ",",
+//!   "date_format": "yyyymmdd",
+//!   "mappings": [
+//!     {"domain": "Claim", "patient": "PATID", "begin": "FST_DT", "end": "LST_DT",
+//!      "claim_id": "CLMID", "cost": "STD_COST"},
+//!     {"domain": "Diagnosis", "patient": "PATID", "begin": "FST_DT",
+//!      "code": ["DIAG1", "DIAG2", "DIAG3"],
+//!      "codebook": {"column": "ICD_FLAG", "values": {"9": "ICD9", "10": "ICD10"}},
+//!      "claim_id": "CLMID"}
+//!   ]
+//! }
+//! ```
+//!
+//! A mapping has a `domain`, the columns `patient` and `begin`, and optionally
+//! `end`. Which other columns it takes depends on the domain:
+//!
+//! | domain | columns |
+//! |--------|---------|
+//! | `Claim` | `claim_id` (required), `claim_type`, `location`, `cost`, `charge`, `allowed`, `transaction` |
+//! | `Diagnosis`, `Procedure` | `code` (required), `codebook`, `claim_*`, `location` |
+//! | `Medication` | as `Diagnosis`, and `days_supply`, `quantity`, `strength` |
+//! | `Labs` | as `Diagnosis`, and `lab_text`, `lab_number`, `lab_units` |
+//! | `Demographics` | `field` (a `DemographicField`, not a column) and `value` (required) |
+//! | `Death`, `Enrollment`, `Eligibility` | none |
+//!
+//! `claim_*` are `claim_id`, `claim_type`, `claim_index` and
+//! `claim_procedure`. `code` may be a list of columns, as in a wide table of
+//! diagnoses: each nonempty one gives an event, whose claim index is its
+//! position in the list (from 1) unless `claim_index` is mapped. `codebook` is
+//! either a codebook or a column with an optional map of its values to
+//! codebooks; values not in the map are read as codebook names.
+//!
+//! Empty cells are missing values. Rows that fail to map (a missing required
+//! value, or an unreadable time, number or codebook) are passed, with their
+//! line number, to a callback and build no events.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::process::OutHandler;
+use crate::types::*;
+
+/// How dates are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum DateFormat {
+    /// `YYYY-MM-DD`, or a day offset.
+    #[default]
+    #[serde(rename = "iso")]
+    Iso,
+    #[serde(rename = "yyyymmdd")]
+    Compact,
+    #[serde(rename = "mm/dd/yyyy")]
+    Us,
+}
+
+impl DateFormat {
+    pub fn parse(&self, s: &str) -> Option<EventTime> {
+        let ymd = |y: &str, m: &str, d: &str| {
+            Date::from_ymd(y.parse().ok()?, m.parse().ok()?, d.parse().ok()?).map(EventTime::Date)
+        };
+        match self {
+            DateFormat::Iso => s.parse().ok(),
+            DateFormat::Compact if s.len() == 8 && s.bytes().all(|c| c.is_ascii_digit()) =>
+                ymd(&s[..4], &s[4..6], &s[6..]),
+            DateFormat::Us => {
+                let mut parts = s.splitn(3, '/');
+                let (m, d, y) = (parts.next()?, parts.next()?, parts.next()?);
+                if y.len() != 4 {
+                    return None;
+                }
+                ymd(y, m, d)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The codebook of a mapping's codes.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum CodebookSource {
+    Fixed(Codebook),
+    Column {
+        column: String,
+        #[serde(default)]
+        values: HashMap<String, Codebook>,
+    },
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(x) => vec![x],
+        OneOrMany::Many(x) => x,
+    })
+}
+
+/// The columns from which a row builds events of one domain.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mapping {
+    pub domain: String,
+    pub patient: String,
+    pub begin: String,
+    pub end: Option<String>,
+
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub code: Vec<String>,
+    pub codebook: Option<CodebookSource>,
+    pub location: Option<String>,
+
+    pub claim_id: Option<String>,
+    pub claim_type: Option<String>,
+    pub claim_index: Option<String>,
+    pub claim_procedure: Option<String>,
+
+    pub cost: Option<String>,
+    pub charge: Option<String>,
+    pub allowed: Option<String>,
+    pub transaction: Option<String>,
+
+    pub days_supply: Option<String>,
+    pub quantity: Option<String>,
+    pub strength: Option<String>,
+
+    pub lab_text: Option<String>,
+    pub lab_number: Option<String>,
+    pub lab_units: Option<String>,
+
+    pub field: Option<DemographicField>,
+    pub value: Option<String>,
+}
+
+fn comma() -> char {
+    ','
+}
+
+/// How to import a table.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportConfig {
+    /// The field delimiter, by default `,`.
+    #[serde(default = "comma")]
+    pub delimiter: char,
+    #[serde(default)]
+    pub date_format: DateFormat,
+    /// Whether patient IDs are integers rather than strings.
+    #[serde(default)]
+    pub integer_ids: bool,
+    pub mappings: Vec<Mapping>,
+}
+
+impl ImportConfig {
+    pub fn from_json<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader(io::BufReader::new(reader))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Errors
+
+/// Why an import could not proceed.
+#[derive(Debug)]
+pub enum ImportError {
+    /// The delimiter is not a single byte.
+    Delimiter(char),
+    /// A mapping's domain is unknown.
+    Domain(String),
+    /// A mapping lacks a column required by its domain.
+    Required { domain: String, column: &'static str },
+    /// A mapped column is not in the header.
+    MissingColumn(String),
+    Csv(csv::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportError::Delimiter(c) => write!(f, "delimiter {:?} is not a single byte", c),
+            ImportError::Domain(d) => write!(f, "unknown domain {:?}", d),
+            ImportError::Required { domain, column } =>
+                write!(f, "{} mapping needs `{}`", domain, column),
+            ImportError::MissingColumn(c) => write!(f, "no column {:?} in header", c),
+            ImportError::Csv(e) => e.fmt(f),
+            ImportError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<csv::Error> for ImportError {
+    fn from(e: csv::Error) -> Self {
+        ImportError::Csv(e)
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        ImportError::Io(e)
+    }
+}
+
+/// Why a row failed to map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapError {
+    /// The row could not be read (e.g. invalid UTF-8).
+    Unreadable(String),
+    /// A required value is empty or missing.
+    Missing(String),
+    InvalidTime { column: String, value: String },
+    InvalidNumber { column: String, value: String },
+    InvalidCodebook { column: String, value: String },
+    InvalidLocation { column: String, value: String },
+    /// The end of the event is before its begin.
+    EndBeforeBegin { begin: String, end: String },
+    /// The times have no `context.time` interval: an offset is negative, or
+    /// dates are mixed with offsets.
+    NoInterval { begin: String, end: Option<String> },
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapError::Unreadable(e) => write!(f, "unreadable row: {}", e),
+            MapError::Missing(c) => write!(f, "missing value in column {:?}", c),
+            MapError::InvalidTime { column, value } =>
+                write!(f, "invalid time {:?} in column {:?}", value, column),
+            MapError::InvalidNumber { column, value } =>
+                write!(f, "invalid number {:?} in column {:?}", value, column),
+            MapError::InvalidCodebook { column, value } =>
+                write!(f, "invalid codebook {:?} in column {:?}", value, column),
+            MapError::InvalidLocation { column, value } =>
+                write!(f, "invalid location {:?} in column {:?}", value, column),
+            MapError::EndBeforeBegin { begin, end } =>
+                write!(f, "end {} is before begin {}", end, begin),
+            MapError::NoInterval { begin, end: Some(end) } =>
+                write!(f, "no interval from {} to {} (negative offset or dates mixed with offsets)",
+                       begin, end),
+            MapError::NoInterval { begin, end: None } =>
+                write!(f, "no interval from {} (negative offset)", begin),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+/// A row that failed to map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// 1-based line number.
+    pub line: u64,
+    /// The domain of the mapping that failed, if the row could be read.
+    pub domain: Option<&'static str>,
+    pub error: MapError,
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.domain {
+            Some(d) => write!(f, "line {} ({}): {}", self.line, d, self.error),
+            None => write!(f, "line {}: {}", self.line, self.error),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Counts of an import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Rows read, excluding the header.
+    pub rows: usize,
+    /// Events written.
+    pub events: usize,
+    /// Rows that failed to map.
+    pub failed: usize,
+}
+
+/*----------------------------------------------------------------------------*/
+// Resolved mappings
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Claim,
+    Death,
+    Demographics,
+    Diagnosis,
+    Eligibility,
+    Enrollment,
+    Labs,
+    Medication,
+    Procedure,
+}
+
+impl Kind {
+    fn from_name(name: &str) -> Option<Kind> {
+        Some(match name {
+            "Claim" => Kind::Claim,
+            "Death" => Kind::Death,
+            "Demographics" => Kind::Demographics,
+            "Diagnosis" => Kind::Diagnosis,
+            "Eligibility" => Kind::Eligibility,
+            "Enrollment" => Kind::Enrollment,
+            "Labs" => Kind::Labs,
+            "Medication" => Kind::Medication,
+            "Procedure" => Kind::Procedure,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Claim => "Claim",
+            Kind::Death => "Death",
+            Kind::Demographics => "Demographics",
+            Kind::Diagnosis => "Diagnosis",
+            Kind::Eligibility => "Eligibility",
+            Kind::Enrollment => "Enrollment",
+            Kind::Labs => "Labs",
+            Kind::Medication => "Medication",
+            Kind::Procedure => "Procedure",
+        }
+    }
+
+    fn has_code(self) -> bool {
+        matches!(self, Kind::Diagnosis | Kind::Labs | Kind::Medication | Kind::Procedure)
+    }
+}
+
+/// A column by name and position.
+#[derive(Debug, Clone)]
+struct Col {
+    name: String,
+    index: usize,
+}
+
+#[derive(Debug, Clone)]
+enum ResolvedCodebook {
+    None,
+    Fixed(Codebook),
+    Column(Col, HashMap<String, Codebook>),
+}
+
+/// A mapping with its columns found in the header.
+#[derive(Debug, Clone)]
+struct Resolved {
+    kind: Kind,
+    patient: Col,
+    begin: Col,
+    end: Option<Col>,
+    code: Vec<Col>,
+    codebook: ResolvedCodebook,
+    location: Option<Col>,
+    claim_id: Option<Col>,
+    claim_type: Option<Col>,
+    claim_index: Option<Col>,
+    claim_procedure: Option<Col>,
+    cost: Option<Col>,
+    charge: Option<Col>,
+    allowed: Option<Col>,
+    transaction: Option<Col>,
+    days_supply: Option<Col>,
+    quantity: Option<Col>,
+    strength: Option<Col>,
+    lab_text: Option<Col>,
+    lab_number: Option<Col>,
+    lab_units: Option<Col>,
+    field: Option<DemographicField>,
+    value: Option<Col>,
+}
+
+impl Resolved {
+    fn new(mapping: &Mapping, header: &csv::StringRecord) -> Result<Resolved, ImportError> {
+        let kind = Kind::from_name(&mapping.domain)
+            .ok_or_else(|| ImportError::Domain(mapping.domain.clone()))?;
+        let col = |name: &str| match header.iter().position(|h| h == name) {
+            Some(index) => Ok(Col { name: name.to_string(), index }),
+            None => Err(ImportError::MissingColumn(name.to_string())),
+        };
+        let opt = |name: &Option<String>| name.as_deref().map(col).transpose();
+        let required = |column: &'static str| ImportError::Required { domain: mapping.domain.clone(), column };
+
+        if kind.has_code() && mapping.code.is_empty() {
+            return Err(required("code"));
+        }
+        if kind == Kind::Claim && mapping.claim_id.is_none() {
+            return Err(required("claim_id"));
+        }
+        if kind == Kind::Claim && mapping.cost.is_none()
+            && (mapping.charge.is_some() || mapping.allowed.is_some() || mapping.transaction.is_some()) {
+            return Err(required("cost"));
+        }
+        if kind == Kind::Demographics && mapping.field.is_none() {
+            return Err(required("field"));
+        }
+        if kind == Kind::Demographics && mapping.value.is_none() {
+            return Err(required("value"));
+        }
+
+        Ok(Resolved {
+            kind,
+            patient: col(&mapping.patient)?,
+            begin: col(&mapping.begin)?,
+            end: opt(&mapping.end)?,
+            code: mapping.code.iter().map(|c| col(c)).collect::<Result<_, _>>()?,
+            codebook: match &mapping.codebook {
+                None => ResolvedCodebook::None,
+                Some(CodebookSource::Fixed(cb)) => ResolvedCodebook::Fixed(*cb),
+                Some(CodebookSource::Column { column, values }) =>
+                    ResolvedCodebook::Column(col(column)?, values.clone()),
+            },
+            location: opt(&mapping.location)?,
+            claim_id: opt(&mapping.claim_id)?,
+            claim_type: opt(&mapping.claim_type)?,
+            claim_index: opt(&mapping.claim_index)?,
+            claim_procedure: opt(&mapping.claim_procedure)?,
+            cost: opt(&mapping.cost)?,
+            charge: opt(&mapping.charge)?,
+            allowed: opt(&mapping.allowed)?,
+            transaction: opt(&mapping.transaction)?,
+            days_supply: opt(&mapping.days_supply)?,
+            quantity: opt(&mapping.quantity)?,
+            strength: opt(&mapping.strength)?,
+            lab_text: opt(&mapping.lab_text)?,
+            lab_number: opt(&mapping.lab_number)?,
+            lab_units: opt(&mapping.lab_units)?,
+            field: mapping.field,
+            value: opt(&mapping.value)?,
+        })
+    }
+}
+
+/// The values of one row.
+struct Row<'r> {
+    record: &'r csv::StringRecord,
+    date_format: DateFormat,
+}
+
+impl Row<'_> {
+    /// The value of a column, or `None` if it is empty.
+    fn get(&self, col: &Col) -> Option<&str> {
+        self.record.get(col.index).map(str::trim).filter(|s| !s.is_empty())
+    }
+
+    fn opt(&self, col: &Option<Col>) -> Option<&str> {
+        col.as_ref().and_then(|c| self.get(c))
+    }
+
+    fn required(&self, col: &Col) -> Result<&str, MapError> {
+        self.get(col).ok_or_else(|| MapError::Missing(col.name.clone()))
+    }
+
+    fn string(&self, col: &Option<Col>) -> Option<String> {
+        self.opt(col).map(str::to_string)
+    }
+
+    fn time(&self, col: &Col) -> Result<EventTime, MapError> {
+        let value = self.required(col)?;
+        self.date_format.parse(value)
+            .ok_or_else(|| MapError::InvalidTime { column: col.name.clone(), value: value.to_string() })
+    }
+
+    fn number<T: std::str::FromStr>(&self, col: &Option<Col>) -> Result<Option<T>, MapError> {
+        let col = match col {
+            Some(col) => col,
+            None => return Ok(None),
+        };
+        match self.get(col) {
+            None => Ok(None),
+            Some(value) => value.parse().map(Some).map_err(|_| MapError::InvalidNumber {
+                column: col.name.clone(), value: value.to_string(),
+            }),
+        }
+    }
+
+    /// An integer, which may be written with a zero fraction, e.g. `30.0`.
+    fn integer(&self, col: &Option<Col>) -> Result<Option<i32>, MapError> {
+        self.number::<i32>(col).or_else(|e| match self.number::<f64>(col) {
+            Ok(Some(x)) if x.fract() == 0.0 && x.abs() <= f64::from(i32::MAX) => Ok(Some(x as i32)),
+            _ => Err(e),
+        })
+    }
+
+    fn codebook(&self, codebook: &ResolvedCodebook) -> Result<Option<Codebook>, MapError> {
+        let (col, values) = match codebook {
+            ResolvedCodebook::None => return Ok(None),
+            ResolvedCodebook::Fixed(cb) => return Ok(Some(*cb)),
+            ResolvedCodebook::Column(col, values) => (col, values),
+        };
+        let value = match self.get(col) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        values.get(value).copied()
+            .or_else(|| serde_json::from_value(serde_json::Value::String(value.to_string())).ok())
+            .map(Some)
+            .ok_or_else(|| MapError::InvalidCodebook { column: col.name.clone(), value: value.to_string() })
+    }
+
+    fn location(&self, col: &Option<Col>) -> Result<Option<Location>, MapError> {
+        match col.as_ref().and_then(|c| self.get(c).map(|v| (c, v))) {
+            None => Ok(None),
+            Some((col, value)) => serde_json::from_value(serde_json::Value::String(value.to_string()))
+                .map(Some)
+                .map_err(|_| MapError::InvalidLocation { column: col.name.clone(), value: value.to_string() }),
+        }
+    }
+
+    fn claim(&self, m: &Resolved, position: Option<i32>) -> Result<Option<Claim<'static>>, MapError> {
+        let id = match self.opt(&m.claim_id) {
+            Some(id) => id.to_string(),
+            None => return Ok(None),
+        };
+        Ok(Some(Claim {
+            id: Cow::Owned(id),
+            r#type: self.string(&m.claim_type),
+            index: match &m.claim_index {
+                Some(_) => self.integer(&m.claim_index)?,
+                None => position,
+            },
+            procedure: self.string(&m.claim_procedure),
+        }))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Import
+
+/// Builds the events of one row for one mapping.
+fn map_row(row: &Row, m: &Resolved, integer_ids: bool) -> Result<Vec<OwnedEvent>, MapError> {
+    let patient = row.required(&m.patient)?;
+    let patient = if integer_ids {
+        SubjectID::Idint(patient.parse().map_err(|_| MapError::InvalidNumber {
+            column: m.patient.name.clone(), value: patient.to_string(),
+        })?)
+    } else {
+        SubjectID::IDstr(Cow::Owned(patient.to_string()))
+    };
+    let b = row.time(&m.begin)?;
+    let e = match &m.end {
+        Some(col) if row.get(col).is_some() => Some(row.time(col)?),
+        _ => None,
+    };
+    if let Some(e) = e {
+        if b.days_until(&e).is_some_and(|n| n < 0) {
+            return Err(MapError::EndBeforeBegin { begin: b.to_string(), end: e.to_string() });
+        }
+    }
+    let time = Interval::from_times(b, e).ok_or_else(|| MapError::NoInterval {
+        begin: b.to_string(), end: e.map(|e| e.to_string()),
+    })?;
+
+    let mut facts = Vec::new();
+    if m.kind.has_code() {
+        let codebook = row.codebook(&m.codebook)?;
+        let wide = m.code.len() > 1;
+        for (i, col) in m.code.iter().enumerate() {
+            let code = match row.get(col) {
+                Some(code) => Code { code: Cow::Owned(code.to_string()), codebook },
+                None if wide => continue,
+                None => return Err(MapError::Missing(col.name.clone())),
+            };
+            let claim = row.claim(m, if wide { Some(i as i32 + 1) } else { None })?;
+            let location = row.location(&m.location)?;
+            facts.push(match m.kind {
+                Kind::Diagnosis => Domain::Diagnosis(DiagnosisFacts { code, claim, location }),
+                Kind::Procedure => Domain::Procedure(ProcedureFacts { code, claim, location }),
+                Kind::Medication => {
+                    let fill = Fill {
+                        days_supply: row.integer(&m.days_supply)?,
+                        quantity: row.integer(&m.quantity)?,
+                        strength: row.string(&m.strength).map(Cow::Owned),
+                    };
+                    let has_fill = fill.days_supply.is_some() || fill.quantity.is_some()
+                        || fill.strength.is_some();
+                    Domain::Medication(MedicationFacts {
+                        code, fill: if has_fill { Some(fill) } else { None }, location, claim,
+                    })
+                }
+                Kind::Labs => {
+                    let value = LabValue {
+                        text: row.string(&m.lab_text).map(Cow::Owned),
+                        number: row.number(&m.lab_number)?,
+                        units: Cow::Owned(row.string(&m.lab_units).unwrap_or_default()),
+                    };
+                    Domain::Labs(LabsFacts { code, value, claim, location })
+                }
+                _ => unreachable!(),
+            });
+        }
+    } else {
+        facts.push(match m.kind {
+            Kind::Claim => {
+                let claim = row.claim(m, None)?
+                    .ok_or_else(|| MapError::Missing(m.claim_id.as_ref().unwrap().name.clone()))?;
+                let cost = match &m.cost {
+                    Some(col) if row.get(col).is_some() => Some(Cost {
+                        charge: row.string(&m.charge).map(Cow::Owned),
+                        cost: Cow::Owned(row.required(col)?.to_string()),
+                        allowed: row.string(&m.allowed).map(Cow::Owned),
+                        transaction: row.string(&m.transaction).map(Cow::Owned),
+                    }),
+                    _ => None,
+                };
+                Domain::Claim(ClaimFacts { claim, location: row.location(&m.location)?, cost })
+            }
+            Kind::Death => Domain::Death(DeathFacts {}),
+            Kind::Eligibility => Domain::Eligibility(EligibilityFacts {}),
+            Kind::Enrollment => Domain::Enrollment(EnrollmentFacts {}),
+            Kind::Demographics => {
+                let value = row.required(m.value.as_ref().unwrap())?;
+                Domain::Demographics(DemographicFacts {
+                    demo: DemographicInfo {
+                        field: m.field.unwrap(),
+                        info: Some(serde_json::Value::String(value.to_string())),
+                    },
+                })
+            }
+            _ => unreachable!(),
+        });
+    }
+
+    Ok(facts.into_iter()
+        .map(|facts| Event {
+            p: patient.clone(),
+            b,
+            e,
+            d: Cow::Borrowed(m.kind.name()),
+            concepts: vec![],
+            context: Context {
+                patient_id: patient.clone(),
+                time: time.clone(),
+                facts,
+                source: None,
+                misc: None,
+            },
+        })
+        .collect())
+}
+
+/// Imports the table read from `reader`, whose first row is a header, writing
+/// the events of each row to `output` in the order of the mappings. Rows that
+/// fail to map are passed to `on_error`.
+pub fn import<R, O, F>(config: &ImportConfig, reader: R, output: &mut O, mut on_error: F)
+                       -> Result<ImportSummary, ImportError>
+where R: io::Read,
+      O: OutHandler<'static> + ?Sized,
+      F: FnMut(RowError),
+{
+    if !config.delimiter.is_ascii() {
+        return Err(ImportError::Delimiter(config.delimiter));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(config.delimiter as u8)
+        .flexible(true)
+        .from_reader(reader);
+    let header = reader.headers()?.clone();
+    let mappings = config.mappings.iter()
+        .map(|m| Resolved::new(m, &header))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut summary = ImportSummary::default();
+    let mut record = csv::StringRecord::new();
+    let mut events = Vec::new();
+    loop {
+        let line = reader.position().line();
+        match reader.read_record(&mut record) {
+            Ok(true) => (),
+            Ok(false) => break,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                summary.rows += 1;
+                summary.failed += 1;
+                let line = e.position().map_or(line, |p| p.line());
+                on_error(RowError { line, domain: None, error: MapError::Unreadable(e.to_string()) });
+                continue;
+            }
+        }
+        summary.rows += 1;
+        let line = record.position().map_or(line, |p| p.line());
+        let row = Row { record: &record, date_format: config.date_format };
+
+        events.clear();
+        let mut failed = None;
+        for m in &mappings {
+            match map_row(&row, m, config.integer_ids) {
+                Ok(mut x) => events.append(&mut x),
+                Err(error) => {
+                    failed = Some(RowError { line, domain: Some(m.kind.name()), error });
+                    break;
+                }
+            }
+        }
+        match failed {
+            Some(error) => {
+                summary.failed += 1;
+                on_error(error);
+            }
+            None => {
+                summary.events += events.len();
+                for event in events.drain(..) {
+                    output.write_event(event)?;
+                }
+            }
+        }
+    }
+    output.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test_import {
+    use crate::import::*;
+
+    fn config(json: &str) -> ImportConfig {
+        ImportConfig::from_json(json.as_bytes()).unwrap()
+    }
+
+    fn run(config: &ImportConfig, table: &str) -> (Vec<String>, Vec<RowError>, ImportSummary) {
+        let mut events: Vec<OwnedEvent> = Vec::new();
+        let mut errors = Vec::new();
+        let summary = import(config, table.as_bytes(), &mut events, |e| errors.push(e)).unwrap();
+        let json = events.iter().map(|e| serde_json::to_string(e).unwrap()).collect();
+        (json, errors, summary)
+    }
+
+    #[test]
+    fn test_date_format() {
+        let date = |y, m, d| Some(EventTime::Date(Date::from_ymd(y, m, d).unwrap()));
+        assert_eq!(DateFormat::Iso.parse("2010-02-03"), date(2010, 2, 3));
+        assert_eq!(DateFormat::Iso.parse("-5"), Some(EventTime::Offset(-5)));
+        assert_eq!(DateFormat::Compact.parse("20100203"), date(2010, 2, 3));
+        assert_eq!(DateFormat::Compact.parse("2010023"), None);
+        assert_eq!(DateFormat::Us.parse("2/3/2010"), date(2010, 2, 3));
+        assert_eq!(DateFormat::Us.parse("02/30/2010"), None);
+        assert_eq!(DateFormat::Us.parse("2/3/10"), None);
+    }
+
+    #[test]
+    fn test_claims() {
+        let config = config(r#"{
+            "date_format": "yyyymmdd",
+            "mappings": [
+                {"domain": "Claim", "patient": "PATID", "begin": "FST_DT", "end": "LST_DT",
+                 "claim_id": "CLMID", "location": "POS", "cost": "COST"},
+                {"domain": "Diagnosis", "patient": "PATID", "begin": "FST_DT",
+                 "code": ["DIAG1", "DIAG2"],
+                 "codebook": {"column": "ICD_FLAG", "values": {"9": "ICD9", "10": "ICD10"}},
+                 "claim_id": "CLMID"}
+            ]}"#);
+        let table = "\
+            PATID,CLMID,FST_DT,LST_DT,POS,COST,ICD_FLAG,DIAG1,DIAG2\n\
+            p1,c1,20100101,20100102,Inpatient,12.50,10,E119,\n\
+            p2,c2,20100201,,,,ICD9,25000,4019\n";
+        let (events, errors, summary) = run(&config, table);
+        assert_eq!(errors, vec![]);
+        assert_eq!(summary, ImportSummary { rows: 2, events: 5, failed: 0 });
+        assert_eq!(events, vec![
+            "[\"p1\",\"2010-01-01\",\"2010-01-02\",\"Claim\",[],\
+              {\"patient_id\":\"p1\",\"time\":{\"begin\":\"2010-01-01\",\"end\":\"2010-01-02\"},\
+               \"domain\":\"Claim\",\"facts\":{\"claim\":{\"id\":\"c1\"},\"location\":\"Inpatient\",\
+               \"cost\":{\"cost\":\"12.50\"}}}]",
+            "[\"p1\",\"2010-01-01\",null,\"Diagnosis\",[],\
+              {\"patient_id\":\"p1\",\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+               \"domain\":\"Diagnosis\",\"facts\":{\"code\":{\"code\":\"E119\",\"codebook\":\"ICD10\"},\
+               \"claim\":{\"id\":\"c1\",\"index\":1}}}]",
+            "[\"p2\",\"2010-02-01\",null,\"Claim\",[],\
+              {\"patient_id\":\"p2\",\"time\":{\"begin\":\"2010-02-01\",\"end\":null},\
+               \"domain\":\"Claim\",\"facts\":{\"claim\":{\"id\":\"c2\"}}}]",
+            "[\"p2\",\"2010-02-01\",null,\"Diagnosis\",[],\
+              {\"patient_id\":\"p2\",\"time\":{\"begin\":\"2010-02-01\",\"end\":null},\
+               \"domain\":\"Diagnosis\",\"facts\":{\"code\":{\"code\":\"25000\",\"codebook\":\"ICD9\"},\
+               \"claim\":{\"id\":\"c2\",\"index\":1}}}]",
+            "[\"p2\",\"2010-02-01\",null,\"Diagnosis\",[],\
+              {\"patient_id\":\"p2\",\"time\":{\"begin\":\"2010-02-01\",\"end\":null},\
+               \"domain\":\"Diagnosis\",\"facts\":{\"code\":{\"code\":\"4019\",\"codebook\":\"ICD9\"},\
+               \"claim\":{\"id\":\"c2\",\"index\":2}}}]",
+        ]);
+    }
+
+    #[test]
+    fn test_medication_labs() {
+        let config = config(r#"{
+            "delimiter": "\t",
+            "integer_ids": true,
+            "mappings": [
+                {"domain": "Medication", "patient": "id", "begin": "date", "code": "ndc",
+                 "codebook": "NDC", "days_supply": "days", "quantity": "qty"},
+                {"domain": "Labs", "patient": "id", "begin": "date", "code": "loinc",
+                 "codebook": "LOINC", "lab_number": "result", "lab_units": "units"},
+                {"domain": "Demographics", "patient": "id", "begin": "date",
+                 "field": "Gender", "value": "sex"}
+            ]}"#);
+        let table = "id\tdate\tndc\tdays\tqty\tloinc\tresult\tunits\tsex\n\
+                     7\t2010-01-01\t00093005801\t30\t60.0\t4548-4\t6.5\t%\tF\n";
+        let (events, errors, _) = run(&config, table);
+        assert_eq!(errors, vec![]);
+        assert_eq!(events, vec![
+            "[7,\"2010-01-01\",null,\"Medication\",[],\
+              {\"patient_id\":7,\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+               \"domain\":\"Medication\",\"facts\":{\"code\":{\"code\":\"00093005801\",\"codebook\":\"NDC\"},\
+               \"fill\":{\"days_supply\":30,\"quantity\":60}}}]",
+            "[7,\"2010-01-01\",null,\"Labs\",[],\
+              {\"patient_id\":7,\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+               \"domain\":\"Labs\",\"facts\":{\"code\":{\"code\":\"4548-4\",\"codebook\":\"LOINC\"},\
+               \"value\":{\"number\":6.5,\"units\":\"%\"}}}]",
+            "[7,\"2010-01-01\",null,\"Demographics\",[],\
+              {\"patient_id\":7,\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+               \"domain\":\"Demographics\",\"facts\":{\"demo\":{\"field\":\"Gender\",\"info\":\"F\"}}}]",
+        ]);
+    }
+
+    #[test]
+    fn test_row_errors() {
+        let config = config(r#"{
+            "mappings": [
+                {"domain": "Enrollment", "patient": "id", "begin": "b", "end": "e"},
+                {"domain": "Procedure", "patient": "id", "begin": "b", "code": "cpt",
+                 "codebook": {"column": "cb"}, "claim_id": "claim", "claim_index": "line"}
+            ]}"#);
+        let table = "\
+            id,b,e,cpt,cb,claim,line\n\
+            a,2010-01-01,2010-12-31,99213,CPT,c,1\n\
+            ,2010-01-01,2010-12-31,99213,CPT,c,1\n\
+            b,2010-13-01,,99213,CPT,c,1\n\
+            c,2010-01-01,,99213,XYZ,c,1\n\
+            d,2010-01-01,,,CPT,c,1\n\
+            e,2010-01-01,,99213,CPT,c,one\n\
+            f,2010-01-01\n\
+            g,5,-3,99213,CPT,c,1\n\
+            h,-5,9223372036854775807,99213,CPT,c,1\n";
+        let (events, errors, summary) = run(&config, table);
+        assert_eq!(events.len(), 2);
+        assert_eq!(summary, ImportSummary { rows: 9, events: 2, failed: 8 });
+        let missing = |c: &str| MapError::Missing(c.to_string());
+        assert_eq!(errors.iter().map(|e| (e.line, e.domain)).collect::<Vec<_>>(), vec![
+            (3, Some("Enrollment")), (4, Some("Enrollment")), (5, Some("Procedure")),
+            (6, Some("Procedure")), (7, Some("Procedure")), (8, Some("Procedure")),
+            (9, Some("Enrollment")), (10, Some("Enrollment")),
+        ]);
+        assert_eq!(errors[0].error, missing("id"));
+        assert_eq!(errors[1].error, MapError::InvalidTime { column: "b".to_string(),
+                                                            value: "2010-13-01".to_string() });
+        assert!(matches!(&errors[2].error, MapError::InvalidCodebook { value, .. } if value == "XYZ"));
+        assert_eq!(errors[3].error, missing("cpt"));
+        assert!(matches!(&errors[4].error, MapError::InvalidNumber { column, .. } if column == "line"));
+        assert_eq!(errors[5].error, missing("cpt"));
+        assert_eq!(errors[6].error, MapError::EndBeforeBegin { begin: "5".to_string(),
+                                                               end: "-3".to_string() });
+        assert_eq!(errors[6].to_string(), "line 9 (Enrollment): end -3 is before begin 5");
+        assert_eq!(errors[7].error, MapError::NoInterval { begin: "-5".to_string(),
+                                                           end: Some(i64::MAX.to_string()) });
+        assert_eq!(errors[1].to_string(),
+                   "line 4 (Enrollment): invalid time \"2010-13-01\" in column \"b\"");
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let run = |json: &str| {
+            let mut events: Vec<OwnedEvent> = Vec::new();
+            import(&config(json), "id,b\n".as_bytes(), &mut events, |_| ()).unwrap_err().to_string()
+        };
+        assert_eq!(run(r#"{"mappings": [{"domain": "Visit", "patient": "id", "begin": "b"}]}"#),
+                   "unknown domain \"Visit\"");
+        assert_eq!(run(r#"{"mappings": [{"domain": "Death", "patient": "id", "begin": "date"}]}"#),
+                   "no column \"date\" in header");
+        assert_eq!(run(r#"{"mappings": [{"domain": "Diagnosis", "patient": "id", "begin": "b"}]}"#),
+                   "Diagnosis mapping needs `code`");
+        assert_eq!(run(r#"{"delimiter": "→", "mappings": []}"#),
+                   "delimiter '→' is not a single byte");
+        assert!(ImportConfig::from_json(r#"{"mappings": [{"domain": "Death", "patient": "id",
+                                            "begin": "b", "dx": "x"}]}"#.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+//! Sets of days: unions, intersections and gaps of intervals.
+//!
+//! An [`IntervalSet`] is a set of days stored as disjoint, sorted intervals.
+//! Each interval covers the days from its begin through its end, inclusive,
+//! so `[1, 5]` and `[6, 9]` are adjacent and combine into `[1, 9]`. An end of
+//! `None` covers every later day.
+//!
+//! A set holds either dates or offsets. Combining the two, whether by
+//! inserting an interval or by a set operation, fails with [`MixedTimes`].
+//!
+//! Example:
+//! ```
+//! use eddeserus::intervalset::*;
+//! use eddeserus::types::EventTime;
+//!
+//! let t = EventTime::Offset;
+//! let mut enrolled = IntervalSet::new();
+//! enrolled.insert(t(0), Some(t(99))).unwrap();
+//! enrolled.insert(t(110), Some(t(199))).unwrap();
+//!
+//! let gaps = enrolled.complement(t(0), Some(t(199))).unwrap();
+//! assert_eq!(gaps.iter().collect::<Vec<_>>(), vec![(t(100), Some(t(109)))]);
+//!
+//! let coalesced = enrolled.coalesce(10);
+//! assert_eq!(coalesced.iter().collect::<Vec<_>>(), vec![(t(0), Some(t(199)))]);
+//! ```
+
+use std::fmt;
+
+use crate::types::{Date, Domain, Event, EventTime, Interval, ParseTimeError};
+
+/// An attempt to combine dates with offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixedTimes;
+
+impl fmt::Display for MixedTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("cannot combine dates with offsets")
+    }
+}
+
+impl std::error::Error for MixedTimes {}
+
+/// An interval that cannot be added to a set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntervalError {
+    Time(ParseTimeError),
+    Mixed(MixedTimes),
+}
+
+impl fmt::Display for IntervalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntervalError::Time(e) => e.fmt(f),
+            IntervalError::Mixed(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for IntervalError {}
+
+impl From<ParseTimeError> for IntervalError {
+    fn from(e: ParseTimeError) -> Self {
+        IntervalError::Time(e)
+    }
+}
+
+impl From<MixedTimes> for IntervalError {
+    fn from(e: MixedTimes) -> Self {
+        IntervalError::Mixed(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Date,
+    Offset,
+}
+
+/// Stands for no end.
+const OPEN : i64 = i64::MAX;
+/// Stands for no begin, in complements.
+const UNBOUNDED : i64 = i64::MIN;
+
+/// A set of days.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntervalSet {
+    /// `None` while empty.
+    kind: Option<Kind>,
+    /// Disjoint, non-adjacent, sorted half-open ranges of days `[begin, end)`.
+    spans: Vec<(i64, i64)>,
+}
+
+fn split(t: EventTime) -> (Kind, i64) {
+    match t {
+        EventTime::Date(d) => (Kind::Date, d.days() as i64),
+        EventTime::Offset(i) => (Kind::Offset, i),
+    }
+}
+
+/// Sorts spans and combines those that overlap or are within `gap` days.
+fn normalize(mut spans: Vec<(i64, i64)>, gap: i64) -> Vec<(i64, i64)> {
+    spans.retain(|(b, e)| b < e);
+    spans.sort_unstable();
+    let mut out : Vec<(i64, i64)> = Vec::with_capacity(spans.len());
+    for (b, e) in spans {
+        match out.last_mut() {
+            Some(last) if b.saturating_sub(last.1) <= gap => last.1 = last.1.max(e),
+            _ => out.push((b, e)),
+        }
+    }
+    out
+}
+
+fn intersect(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        let begin = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if begin < end {
+            out.push((begin, end));
+        }
+        if a[i].1 < b[j].1 { i += 1 } else { j += 1 }
+    }
+    out
+}
+
+/// Every day not in `spans`.
+fn complement(spans: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut out = Vec::with_capacity(spans.len() + 1);
+    let mut begin = UNBOUNDED;
+    for (b, e) in spans {
+        if begin < *b {
+            out.push((begin, *b));
+        }
+        begin = *e;
+    }
+    if begin != OPEN {
+        out.push((begin, OPEN));
+    }
+    out
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        IntervalSet::default()
+    }
+
+    /// Builds a set from intervals, which must all be dates or all offsets.
+    pub fn from_intervals<'i, I>(intervals: I) -> Result<Self, IntervalError>
+    where I: IntoIterator<Item = &'i Interval>
+    {
+        let mut set = IntervalSet::new();
+        let mut spans = Vec::new();
+        for interval in intervals {
+            if let Some(span) = set.span(interval.begin()?, interval.end()?)? {
+                spans.push(span);
+            }
+        }
+        set.spans = normalize(spans, 0);
+        Ok(set)
+    }
+
+    /// Builds a set from the times (`b` and `e`) of the `Enrollment` events.
+    pub fn from_enrollment(events: &[Event]) -> Result<Self, MixedTimes> {
+        IntervalSet::from_events(events, |d| matches!(d, Domain::Enrollment(_)))
+    }
+
+    /// Builds a set from the times (`b` and `e`) of the `Eligibility` events.
+    pub fn from_eligibility(events: &[Event]) -> Result<Self, MixedTimes> {
+        IntervalSet::from_events(events, |d| matches!(d, Domain::Eligibility(_)))
+    }
+
+    fn from_events<F>(events: &[Event], f: F) -> Result<Self, MixedTimes>
+    where F: Fn(&Domain) -> bool
+    {
+        let mut set = IntervalSet::new();
+        let mut spans = Vec::new();
+        for event in events.iter().filter(|e| f(&e.context.facts)) {
+            if let Some(span) = set.span(event.b, event.e)? {
+                spans.push(span);
+            }
+        }
+        set.spans = normalize(spans, 0);
+        Ok(set)
+    }
+
+    fn kind(&mut self, kind: Kind) -> Result<(), MixedTimes> {
+        match self.kind {
+            Some(k) if k != kind => Err(MixedTimes),
+            _ => {
+                self.kind = Some(kind);
+                Ok(())
+            },
+        }
+    }
+
+    /// The span of days from `begin` through `end`, or `None` if it is empty.
+    /// Sets the kind of the set.
+    fn span(&mut self, begin: EventTime, end: Option<EventTime>)
+        -> Result<Option<(i64, i64)>, MixedTimes>
+    {
+        let (kind, b) = split(begin);
+        let e = match end.map(split) {
+            Some((k, _)) if k != kind => return Err(MixedTimes),
+            Some((_, e)) => e.saturating_add(1),
+            None => OPEN,
+        };
+        if b >= e {
+            return Ok(None);
+        }
+        self.kind(kind)?;
+        Ok(Some((b, e)))
+    }
+
+    /// Adds the days from `begin` through `end`. An end before the begin adds
+    /// nothing.
+    ///
+    /// Finds the spans that overlap or touch the new one by binary search and
+    /// merges only those.
+    pub fn insert(&mut self, begin: EventTime, end: Option<EventTime>) -> Result<(), MixedTimes> {
+        let (b, e) = match self.span(begin, end)? {
+            Some(span) => span,
+            None => return Ok(()),
+        };
+        let first = self.spans.partition_point(|s| s.1 < b);
+        let last = self.spans.partition_point(|s| s.0 <= e);
+        let (b, e) = match self.spans.get(first..last) {
+            Some([head, .., tail]) => (b.min(head.0), e.max(tail.1)),
+            Some([only]) => (b.min(only.0), e.max(only.1)),
+            _ => (b, e),
+        };
+        self.spans.splice(first..last, std::iter::once((b, e)));
+        Ok(())
+    }
+
+    /// The kind shared by two sets, if any.
+    fn common(&self, other: &IntervalSet) -> Result<Option<Kind>, MixedTimes> {
+        match (self.kind, other.kind) {
+            (Some(a), Some(b)) if a != b => Err(MixedTimes),
+            (a, b) => Ok(a.or(b)),
+        }
+    }
+
+    fn with(kind: Option<Kind>, spans: Vec<(i64, i64)>) -> IntervalSet {
+        let kind = if spans.is_empty() { None } else { kind };
+        IntervalSet { kind, spans }
+    }
+
+    /// The days in either set.
+    pub fn union(&self, other: &IntervalSet) -> Result<IntervalSet, MixedTimes> {
+        let kind = self.common(other)?;
+        let spans = self.spans.iter().chain(&other.spans).copied().collect();
+        Ok(IntervalSet::with(kind, normalize(spans, 0)))
+    }
+
+    /// The days in both sets.
+    pub fn intersection(&self, other: &IntervalSet) -> Result<IntervalSet, MixedTimes> {
+        let kind = self.common(other)?;
+        Ok(IntervalSet::with(kind, intersect(&self.spans, &other.spans)))
+    }
+
+    /// The days in `self` but not in `other`.
+    pub fn difference(&self, other: &IntervalSet) -> Result<IntervalSet, MixedTimes> {
+        let kind = self.common(other)?;
+        Ok(IntervalSet::with(kind, intersect(&self.spans, &complement(&other.spans))))
+    }
+
+    /// The days from `begin` through `end` that are not in the set, such as
+    /// the gaps in coverage during a study window.
+    pub fn complement(&self, begin: EventTime, end: Option<EventTime>)
+                      -> Result<IntervalSet, MixedTimes> {
+        let mut window = IntervalSet::new();
+        window.insert(begin, end)?;
+        window.difference(self)
+    }
+
+    /// Combines intervals separated by at most `gap` missing days.
+    pub fn coalesce(&self, gap: i64) -> IntervalSet {
+        IntervalSet::with(self.kind, normalize(self.spans.clone(), gap.max(0)))
+    }
+
+    /// The missing days between the intervals of the set.
+    pub fn gaps(&self) -> IntervalSet {
+        let spans = self.spans.windows(2).map(|w| (w[0].1, w[1].0)).collect();
+        IntervalSet::with(self.kind, spans)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Whether the day `t` is in the set.
+    pub fn contains(&self, t: EventTime) -> bool {
+        let (kind, t) = split(t);
+        self.kind == Some(kind)
+            && self.spans.iter().any(|(b, e)| *b <= t && t < *e)
+    }
+
+    /// The number of days in the set, or `None` if it has no end.
+    pub fn days(&self) -> Option<i64> {
+        self.spans.iter().map(|(b, e)| if *e == OPEN { None } else { Some(e - b) }).sum()
+    }
+
+    fn time(&self, t: i64) -> EventTime {
+        match self.kind {
+            Some(Kind::Date) => EventTime::Date(Date::from_days(t as i32)),
+            _ => EventTime::Offset(t),
+        }
+    }
+
+    /// The intervals of the set in order, as inclusive `(begin, end)` pairs,
+    /// which can be related to other intervals by [`Allen`](crate::allen::Allen).
+    pub fn iter(&self) -> impl Iterator<Item = (EventTime, Option<EventTime>)> + '_ {
+        self.spans.iter().map(move |(b, e)| {
+            let end = if *e == OPEN { None } else { Some(self.time(e - 1)) };
+            (self.time(*b), end)
+        })
+    }
+
+    /// The intervals of the set in order (see [`Interval::from_times`]), or
+    /// `None` if the set holds negative offsets.
+    pub fn to_intervals(&self) -> Option<Vec<Interval>> {
+        self.iter()
+            .map(|(b, e)| Interval::from_times(b, e))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_intervalset {
+    use crate::intervalset::*;
+    use crate::types::*;
+
+    fn set(spans: &[(i64, Option<i64>)]) -> IntervalSet {
+        let mut s = IntervalSet::new();
+        for (b, e) in spans {
+            s.insert(EventTime::Offset(*b), e.map(EventTime::Offset)).unwrap();
+        }
+        s
+    }
+
+    fn spans(s: &IntervalSet) -> Vec<(i64, Option<i64>)> {
+        s.iter().map(|(b, e)| (b.as_offset().unwrap(), e.map(|e| e.as_offset().unwrap()))).collect()
+    }
+
+    #[test]
+    fn test_insert() {
+        let s = set(&[(10, Some(20)), (0, Some(5)), (6, Some(8)), (15, Some(30)), (40, Some(39))]);
+        assert_eq!(spans(&s), vec![(0, Some(8)), (10, Some(30))]);
+        assert_eq!(s.days(), Some(30));
+        assert!(s.contains(EventTime::Offset(8)) && !s.contains(EventTime::Offset(9)));
+        assert_eq!(spans(&set(&[(5, None), (0, Some(3))])), vec![(0, Some(3)), (5, None)]);
+        assert_eq!(set(&[(5, None)]).days(), None);
+
+        let mut s = set(&[(0, Some(2)), (10, Some(12)), (20, Some(22)), (30, Some(32))]);
+        s.insert(EventTime::Offset(3), Some(EventTime::Offset(19))).unwrap();
+        assert_eq!(spans(&s), vec![(0, Some(22)), (30, Some(32))]);
+        s.insert(EventTime::Offset(25), Some(EventTime::Offset(26))).unwrap();
+        s.insert(EventTime::Offset(40), None).unwrap();
+        assert_eq!(spans(&s), vec![(0, Some(22)), (25, Some(26)), (30, Some(32)), (40, None)]);
+        s.insert(EventTime::Offset(24), Some(EventTime::Offset(50))).unwrap();
+        assert_eq!(spans(&s), vec![(0, Some(22)), (24, None)]);
+    }
+
+    #[test]
+    fn test_operations() {
+        let a = set(&[(0, Some(10)), (20, Some(30))]);
+        let b = set(&[(5, Some(25))]);
+        assert_eq!(spans(&a.union(&b).unwrap()), vec![(0, Some(30))]);
+        assert_eq!(spans(&a.intersection(&b).unwrap()), vec![(5, Some(10)), (20, Some(25))]);
+        assert_eq!(spans(&a.difference(&b).unwrap()), vec![(0, Some(4)), (26, Some(30))]);
+        assert_eq!(spans(&b.difference(&a).unwrap()), vec![(11, Some(19))]);
+        assert_eq!(spans(&a.complement(EventTime::Offset(-5), None).unwrap()),
+                   vec![(-5, Some(-1)), (11, Some(19)), (31, None)]);
+        assert_eq!(spans(&a.gaps()), vec![(11, Some(19))]);
+        assert!(a.intersection(&IntervalSet::new()).unwrap().is_empty());
+
+        let open = set(&[(15, None)]);
+        assert_eq!(spans(&a.union(&open).unwrap()), vec![(0, Some(10)), (15, None)]);
+        assert_eq!(spans(&open.difference(&a).unwrap()), vec![(15, Some(19)), (31, None)]);
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let s = set(&[(0, Some(10)), (14, Some(20)), (30, Some(40))]);
+        assert_eq!(spans(&s.coalesce(2)), spans(&s));
+        assert_eq!(spans(&s.coalesce(3)), vec![(0, Some(20)), (30, Some(40))]);
+        assert_eq!(spans(&s.coalesce(9)), vec![(0, Some(40))]);
+    }
+
+    #[test]
+    fn test_mixed() {
+        let date = EventTime::Date(Date::from_ymd(2010, 1, 1).unwrap());
+        let mut s = set(&[(0, Some(10))]);
+        assert_eq!(s.insert(date, None), Err(MixedTimes));
+        assert_eq!(s.insert(EventTime::Offset(0), Some(date)), Err(MixedTimes));
+
+        let mut d = IntervalSet::new();
+        d.insert(date, Some(date.add_days(30))).unwrap();
+        assert_eq!(s.union(&d), Err(MixedTimes));
+        assert_eq!(d.iter().next(), Some((date, Some(date.add_days(30)))));
+
+        let intervals = vec![Interval::IntervalInt { begin: 0, end: Some(3) },
+                             Interval::IntervalStr { begin: "4".to_string(), end: None }];
+        let s = IntervalSet::from_intervals(&intervals).unwrap();
+        assert_eq!(spans(&s), vec![(0, None)]);
+        assert_eq!(s.to_intervals().map(|i| i.len()), Some(1));
+        assert_eq!(s.complement(EventTime::Offset(-5), None).unwrap().to_intervals().map(|i| i.len()), None);
+        let bad = [Interval::IntervalStr { begin: "x".to_string(), end: None }];
+        assert!(matches!(IntervalSet::from_intervals(&bad), Err(IntervalError::Time(_))));
+    }
+
+    #[test]
+    fn test_from_events() {
+        let json = "\
+            [\"a\",\"2010-01-01\",\"2010-06-30\",\"Enrollment\",[],\
+             {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-01-01\",\"end\":\"2010-06-30\"},\
+              \"domain\":\"Enrollment\",\"facts\":{}}]\n\
+            [\"a\",\"2010-07-01\",\"2010-12-31\",\"Enrollment\",[],\
+             {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-07-01\",\"end\":\"2010-12-31\"},\
+              \"domain\":\"Enrollment\",\"facts\":{}}]\n\
+            [\"a\",\"2009-01-01\",null,\"Death\",[],\
+             {\"patient_id\":\"a\",\"time\":{\"begin\":\"2009-01-01\",\"end\":null},\
+              \"domain\":\"Death\",\"facts\":{}}]\n";
+        let events : Vec<Event> = json.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let s = IntervalSet::from_enrollment(&events).unwrap();
+        assert_eq!(s.days(), Some(365));
+        assert_eq!(s.iter().count(), 1);
+        assert!(IntervalSet::from_eligibility(&events).unwrap().is_empty());
+    }
+}
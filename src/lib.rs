@@ -14,12 +14,40 @@
 // Rust types corresponding to events and elements thereof.
 pub mod types;
 
+// Typed dates and offsets for event times.
+pub mod time;
+
+// Allen's interval relations between intervals and events.
+pub mod allen;
+
+// Sets of days built from intervals.
+pub mod intervalset;
+
+// Continuous enrollment checks over lookback windows.
+pub mod enrollment;
+
+// Drug eras built from Medication fills.
+pub mod drugera;
+
+// Cohort definitions applied to timelines.
+pub mod cohort;
+
+// Feature matrices of covariates at index dates.
+pub mod features;
+
+// Codebook-specific checks and normalization of codes.
+pub mod codes;
+
+// Value sets mapping codes to concepts.
+pub mod valueset;
+
 
 pub mod sede{
     //! Provides functions for deserialization from JSON to an `Event` and 
     //! serialization from an `Event` to JSON.
 
-    use crate::types::{Event};
+    use crate::binary::{self, Encoding};
+    use crate::types::{Event, OwnedEvent};
     use serde_json::{Result};
 
     /// Deserialize a string reference to a `serde_json::Result<Event>`.
@@ -40,9 +68,9 @@ pub mod sede{
     /// println!("{:?}", deserialize_event(&json));
     /// ```
     ///
-    pub fn deserialize_event(x: &std::string::String) -> Result<Event> 
+    pub fn deserialize_event(x: &str) -> Result<Event<'_>>
     {
-        serde_json::from_str(&x)
+        serde_json::from_str(x)
     }
 
     /// Serialize an `Event` to a `serde_json::Result<String>`.
@@ -68,48 +96,72 @@ pub mod sede{
         serde_json::to_string(x)
     }
 
-}
+    /// Serialize an `Event` to a JSON object keyed by `patient`, `begin`,
+    /// `end`, `domain`, `concepts` and `context`. `deserialize_event`
+    /// accepts this form as well.
+    ///
+    /// Example:
+    /// ```
+    /// use eddeserus::sede::*;
+    /// let json = "\
+    ///     [\"xyz\",0,null,\"Death\",[],\
+    ///      {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":null},\
+    ///       \"domain\":\"Death\",\"facts\":{}}]";
+    /// let keyed = serialize_event_keyed(&deserialize_event(json).unwrap()).unwrap();
+    /// assert!(keyed.starts_with("{\"patient\":\"xyz\",\"begin\":0,\"end\":null,"));
+    /// assert_eq!(json, serialize_event(&deserialize_event(&keyed).unwrap()).unwrap());
+    /// ```
+    ///
+    pub fn serialize_event_keyed(x: &Event) -> Result<String> {
+        serde_json::to_string(&x.keyed())
+    }
 
-/// Provides functions for processing events (`&str` -> process -> `io::stdput`).
-/// 
-/// APIs in this module are subject to change. Specifically, future versions 
-/// may have a type signature such as `InHandler -> Processor -> OutHandler`. 
-/// In this way, users could specify how to handle IO; instead of the current
-/// situation, where the user had to handle inputs to get a `&str` and has no
-/// choice in output.  
-pub mod process{
-    use crate::sede::serialize_event;
-    use serde_json::{Deserializer};
-    use crate::types::Event;
-    use std::io::{self, Write};
-
-    /// Process a string of events
-    /// 
-    /// For each `Event` in `events_json`, this function transforms each event
-    /// by the `processor` function, outputting successfully processed events
-    /// to `stdout` and unsucessful events to `stderr`.
-    pub fn process_events(events_json: &str, 
-                          processor: &mut dyn std::ops::Fn(Event) -> Event) 
-                          -> io::Result<()> {
-        
-        let stream = Deserializer::from_str(&events_json).into_iter::<Event>();
-        let stdout = io::stdout(); 
-        let mut outhandle = io::BufWriter::new(stdout.lock()); 
-        let mut errhandle = io::BufWriter::new(io::stderr()); 
-
-        for event in stream {
-
-            let p = serialize_event(&processor(event?));
-
-            match p {
-                Ok(v) =>  writeln!(outhandle, "{}", v).ok(), 
-                // (.ok() added to hide warning: unused std::result:Result)
-                Err(e) => writeln!(errhandle, "{}", e).ok(),
-            };        
-        }
-
-        Ok(())
+    /// Serialize an `Event` to bytes in a binary format, such as
+    /// `binary::MessagePack`. See the [`binary`](crate::binary) module.
+    pub fn serialize_event_binary<F: Encoding>(x: &Event) -> std::result::Result<Vec<u8>, binary::Error> {
+        let mut bytes = Vec::new();
+        binary::encode::<F>(x, &mut bytes)?;
+        Ok(bytes)
+    }
 
+    /// Deserialize bytes in a binary format to an `OwnedEvent`.
+    pub fn deserialize_event_binary<F: Encoding>(x: &[u8]) -> std::result::Result<OwnedEvent, binary::Error> {
+        binary::decode::<F>(x)
     }
+
 }
 
+// Pipeline for processing events (`InHandler -> Processor -> OutHandler`).
+pub mod process;
+
+// Processing newline-delimited JSON on several threads.
+pub mod parallel;
+
+// Memory-mapped files of events.
+pub mod file;
+
+// Events grouped by subject into timelines.
+pub mod timeline;
+
+// External-memory sorting of events by subject and time.
+pub mod sort;
+
+// Consistency checks between an event's header and its context.
+pub mod validate;
+
+// Import of events from CSV and TSV tables.
+pub mod import;
+
+// Flat CSV export of events.
+pub mod flat;
+
+// Binary encodings of events, with length-prefixed framing.
+pub mod binary;
+
+// Arrow record batches and Parquet files of events.
+#[cfg(feature = "parquet")]
+pub mod columnar;
+
+// Fixtures shared by the tests of several modules.
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,379 @@
+//! Processes newline-delimited JSON on several threads.
+//!
+//! Each line of input is independent, so [`process_parallel`] reads the input
+//! in chunks that end on a line boundary and hands them to a pool of worker
+//! threads. Each worker deserializes, processes and serializes the events of a
+//! chunk with its own [`Processor`], made by a function called once per
+//! worker. The results are written by the calling thread, either in input
+//! order or, with [`Order::Arrival`], as soon as each chunk is done.
+//!
+//! At most two chunks per worker are in memory at once, so memory use is
+//! bounded by about `2 * threads * chunk_size` bytes plus their output.
+//!
+//! Example:
+//! ```
+//! use eddeserus::parallel::*;
+//! use eddeserus::process::*;
+//! use eddeserus::types::Event;
+//!
+//! let json = "\
+//!     [\"xyz\",\"2010-01-01\",null,\"Death\",[],\
+//!      {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n\
+//!     [\"abc\",\"2010-01-01\",null,\"Enrollment\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Enrollment\",\"facts\":{}}]\n";
+//!
+//! let mut out = Vec::new();
+//! let summary = process_parallel(json.as_bytes(),
+//!                                || filter(|e: &Event| e.d == "Death"),
+//!                                &mut out,
+//!                                &mut Stop,
+//!                                &Options::default()).unwrap();
+//! assert_eq!(summary.ok, 2);
+//! assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::process::{ErrorHandler, LineError, Lines, Processor, Summary};
+
+/// The order in which [`process_parallel`] writes results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// The order of the input, as [`process`](crate::process::process) does.
+    Input,
+    /// The order in which chunks finish. Lines within a chunk keep their
+    /// order, and failed lines are still reported with their line numbers.
+    Arrival,
+}
+
+/// Settings for [`process_parallel`].
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Number of worker threads. Defaults to the available parallelism.
+    pub threads: usize,
+    /// Size in bytes of the chunks handed to workers. A chunk is extended to
+    /// the end of its last line, so it may be larger. Defaults to 1 MiB.
+    pub chunk_size: usize,
+    /// Defaults to [`Order::Input`].
+    pub order: Order,
+    /// Write events as objects rather than arrays (see `Event::keyed`).
+    /// Defaults to `false`.
+    pub keyed: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: 1 << 20,
+            order: Order::Input,
+            keyed: false,
+        }
+    }
+}
+
+/// Lines of input handed to a worker.
+struct Chunk {
+    index: usize,
+    /// Lines before this chunk.
+    line: usize,
+    offset: u64,
+    bytes: Vec<u8>,
+}
+
+/// A processed chunk.
+struct Done {
+    index: usize,
+    /// Newline-delimited JSON of the events kept by the processor.
+    bytes: Vec<u8>,
+    /// Failed lines, with the length of `bytes` when each was reached.
+    errors: Vec<(usize, LineError)>,
+    summary: Summary,
+}
+
+/// Reads line-aligned chunks, waiting for a token before each one.
+fn read_chunks<R: BufRead>(mut reader: R,
+                           chunk_size: usize,
+                           jobs: SyncSender<Chunk>,
+                           tokens: Receiver<()>)
+                           -> io::Result<()> {
+    let (mut index, mut line, mut offset) = (0, 0, 0);
+    while tokens.recv().is_ok() {
+        let mut bytes = Vec::with_capacity(chunk_size);
+        (&mut reader).take(chunk_size as u64).read_to_end(&mut bytes)?;
+        if bytes.last().is_some_and(|b| *b != b'\n') {
+            reader.read_until(b'\n', &mut bytes)?;
+        }
+        if bytes.is_empty() {
+            break;
+        }
+
+        let lines = bytes.iter().filter(|b| **b == b'\n').count();
+        let len = bytes.len() as u64;
+        if jobs.send(Chunk { index, line, offset, bytes }).is_err() {
+            break;
+        }
+        index += 1;
+        line += lines;
+        offset += len;
+    }
+    Ok(())
+}
+
+fn process_chunk<P: Processor>(chunk: Chunk, processor: &mut P, keyed: bool) -> io::Result<Done> {
+    let mut lines = Lines { line: chunk.line, offset: chunk.offset, summary: Summary::default() };
+    let mut bytes = Vec::with_capacity(chunk.bytes.len());
+    let mut errors = Vec::new();
+
+    for raw in chunk.bytes.split_inclusive(|b| *b == b'\n') {
+        match lines.parse(raw) {
+            Some(Ok(event)) => if let Some(e) = processor.process(event) {
+                if keyed {
+                    serde_json::to_writer(&mut bytes, &e.keyed())?;
+                } else {
+                    serde_json::to_writer(&mut bytes, &e)?;
+                }
+                bytes.push(b'\n');
+            },
+            Some(Err(error)) => errors.push((bytes.len(), error)),
+            None => (),
+        }
+    }
+    Ok(Done { index: chunk.index, bytes, errors, summary: lines.summary })
+}
+
+fn work<F, P>(make_processor: &F, keyed: bool, jobs: &Mutex<Receiver<Chunk>>,
+              results: Sender<io::Result<Done>>)
+where F: Fn() -> P,
+      P: Processor
+{
+    let mut processor = make_processor();
+    loop {
+        // The lock is released before the chunk is processed.
+        let chunk = match jobs.lock().map(|jobs| jobs.recv()) {
+            Ok(Ok(chunk)) => chunk,
+            _ => return,
+        };
+        if results.send(process_chunk(chunk, &mut processor, keyed)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Writes a processed chunk, passing its failed lines to `errors` where they
+/// occurred.
+fn write_done<W, E>(done: Done, writer: &mut W, errors: &mut E, summary: &mut Summary)
+                    -> io::Result<()>
+where W: Write + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    let mut start = 0;
+    for (end, error) in done.errors {
+        writer.write_all(&done.bytes[start..end])?;
+        start = end;
+        errors.handle_error(error)?;
+    }
+    writer.write_all(&done.bytes[start..])?;
+    summary.ok += done.summary.ok;
+    summary.failed += done.summary.failed;
+    Ok(())
+}
+
+/// Collects processed chunks and writes them in the requested order, returning
+/// a token for each chunk written.
+fn write_all<W, E>(results: Receiver<io::Result<Done>>,
+                   tokens: SyncSender<()>,
+                   writer: &mut W,
+                   errors: &mut E,
+                   order: Order)
+                   -> io::Result<Summary>
+where W: Write + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    let mut summary = Summary::default();
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    for done in results {
+        let done = done?;
+        match order {
+            Order::Arrival => {
+                write_done(done, writer, errors, &mut summary)?;
+                let _ = tokens.send(());
+            },
+            Order::Input => {
+                pending.insert(done.index, done);
+                while let Some(done) = pending.remove(&next) {
+                    write_done(done, writer, errors, &mut summary)?;
+                    let _ = tokens.send(());
+                    next += 1;
+                }
+            },
+        }
+    }
+    Ok(summary)
+}
+
+/// Processes every line of `reader` on `options.threads` worker threads,
+/// writing the kept events to `writer` as newline-delimited JSON.
+///
+/// `make_processor` is called once in each worker. Lines that fail to
+/// deserialize go to `errors`, on the calling thread, at their place in the
+/// output; if `errors` returns an error, processing stops and the output
+/// ends with the events before the failed line (in [`Order::Input`]).
+/// Blank lines are skipped.
+pub fn process_parallel<R, F, P, W, E>(reader: R,
+                                       make_processor: F,
+                                       writer: &mut W,
+                                       errors: &mut E,
+                                       options: &Options)
+                                       -> io::Result<Summary>
+where R: BufRead + Send,
+      F: Fn() -> P + Sync,
+      P: Processor,
+      W: Write + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    let threads = options.threads.max(1);
+    let chunk_size = options.chunk_size.max(1);
+    let in_flight = 2 * threads;
+
+    let (token_tx, token_rx) = mpsc::sync_channel(in_flight);
+    for _ in 0..in_flight {
+        token_tx.send(()).expect("receiver is alive");
+    }
+    let (job_tx, job_rx) = mpsc::sync_channel(threads);
+    let job_rx = Mutex::new(job_rx);
+    let (result_tx, result_rx) = mpsc::channel();
+
+    let summary = thread::scope(|s| {
+        let reader = s.spawn(move || read_chunks(reader, chunk_size, job_tx, token_rx));
+        for _ in 0..threads {
+            let (make_processor, job_rx, result_tx) = (&make_processor, &job_rx, result_tx.clone());
+            s.spawn(move || work(make_processor, options.keyed, job_rx, result_tx));
+        }
+        drop(result_tx);
+
+        // Returning drops the receivers, which stops the other threads.
+        let summary = write_all(result_rx, token_tx, writer, errors, options.order)?;
+        match reader.join() {
+            Ok(result) => result.map(|_| summary),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })?;
+
+    writer.flush()?;
+    errors.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test_parallel {
+    use crate::parallel::*;
+    use crate::process::*;
+    use crate::types::Event;
+
+    fn input() -> String {
+        include_str!("../resources/50events.json").repeat(20)
+    }
+
+    fn options(order: Order) -> Options {
+        Options { threads: 4, chunk_size: 1000, order, keyed: false }
+    }
+
+    fn serial(json: &str) -> String {
+        let mut out = WriterOut::new(Vec::new());
+        process_str(json, &mut map(|e| e), &mut out).unwrap();
+        String::from_utf8(out.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_input_order() {
+        let json = input();
+        let mut out = Vec::new();
+        let summary = process_parallel(json.as_bytes(), || map(|e| e), &mut out, &mut Stop,
+                                       &options(Order::Input)).unwrap();
+        assert_eq!(summary, Summary { ok: 1000, failed: 0 });
+        assert_eq!(String::from_utf8(out).unwrap(), serial(&json));
+    }
+
+    #[test]
+    fn test_keyed() {
+        let json = input();
+        let mut out = Vec::new();
+        let options = Options { keyed: true, ..options(Order::Input) };
+        process_parallel(json.as_bytes(), || map(|e| e), &mut out, &mut Stop, &options).unwrap();
+
+        let mut expected = WriterOut::keyed(Vec::new());
+        process_str(&json, &mut map(|e| e), &mut expected).unwrap();
+        assert_eq!(out, expected.into_inner().unwrap());
+    }
+
+    #[test]
+    fn test_arrival_order() {
+        let json = input();
+        let mut out = Vec::new();
+        process_parallel(json.as_bytes(), || map(|e| e), &mut out, &mut Stop,
+                         &options(Order::Arrival)).unwrap();
+
+        let expected = serial(&json);
+        let mut expected : Vec<&str> = expected.lines().collect();
+        let out = String::from_utf8(out).unwrap();
+        let mut out : Vec<&str> = out.lines().collect();
+        expected.sort();
+        out.sort();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_processor_per_worker() {
+        use std::rc::Rc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Processors are made in their worker, so they need not be `Send`.
+        let made = AtomicUsize::new(0);
+        let make = || {
+            made.fetch_add(1, Ordering::SeqCst);
+            let seen = Rc::new(());
+            filter(move |e: &Event| Rc::strong_count(&seen) == 1 && e.d == "Diagnosis")
+        };
+        let mut out = Vec::new();
+        process_parallel(input().as_bytes(), make, &mut out, &mut Stop,
+                         &options(Order::Input)).unwrap();
+        assert_eq!(made.load(Ordering::SeqCst), 4);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1000);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut json = input();
+        json.push_str("not an event\n");
+        json.push_str(&input());
+        json.push_str("[]");
+
+        let mut errors : Vec<LineError> = Vec::new();
+        let mut out = Vec::new();
+        let summary = process_parallel(json.as_bytes(), || map(|e| e), &mut out, &mut errors,
+                                       &options(Order::Arrival)).unwrap();
+        assert_eq!(summary, Summary { ok: 2000, failed: 2 });
+        let mut lines : Vec<(usize, &str)> =
+            errors.iter().map(|e| (e.line, e.text.as_str())).collect();
+        lines.sort();
+        assert_eq!(lines, vec![(1001, "not an event"), (2002, "[]")]);
+        assert_eq!(errors.iter().find(|e| e.line == 1001).unwrap().offset,
+                   input().len() as u64);
+
+        // Stop writes the events before the failed line, then stops.
+        let mut out = Vec::new();
+        let result = process_parallel(json.as_bytes(), || map(|e| e), &mut out, &mut Stop,
+                                      &options(Order::Input));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1000);
+    }
+}
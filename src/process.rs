@@ -0,0 +1,687 @@
+//! Provides a pipeline for processing events:
+//! `InHandler -> Processor -> OutHandler`.
+//!
+//! An [`InHandler`] yields lines of newline-delimited JSON from some source
+//! (any `io::Read`, a file, or an in-memory buffer). Each line is deserialized
+//! to an `Event`, transformed (or dropped) by a [`Processor`], and handed to an
+//! [`OutHandler`] (any `io::Write`, a `Vec<Event>`, or a callback). Lines
+//! that fail to deserialize go to an [`ErrorHandler`], which can stop
+//! processing, collect the failures, or write them to a dead-letter sink.
+//! [`process_str`] and [`process_events`] instead read a stream of events
+//! separated by any whitespace, as a `serde_json::Deserializer` does.
+//!
+//! Example:
+//! ```
+//! use eddeserus::process::*;
+//! use eddeserus::types::{Event, SubjectID};
+//!
+//! let json = "\
+//!     [\"xyz\",\"2010-01-01\",null,\"Death\",[],\
+//!      {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n\
+//!     [\"abc\",\"2010-01-01\",null,\"Death\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n";
+//!
+//! let mut keep_xyz = filter(|e: &Event| matches!(&e.p, SubjectID::IDstr(id) if id == "xyz"));
+//! let mut out : Vec<Event> = Vec::new();
+//! process_str(json, &mut keep_xyz, &mut out).unwrap();
+//! assert_eq!(out.len(), 1);
+//! ```
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Deserializer;
+
+use crate::types::Event;
+
+/*----------------------------------------------------------------------------*/
+// Input
+
+/// A source of newline-delimited JSON events.
+pub trait InHandler {
+    /// Returns the next line of input, including its line terminator, or
+    /// `None` at the end of input.
+    ///
+    /// Lines are bytes rather than `&str` so that a line with invalid UTF-8
+    /// fails to deserialize on its own instead of ending the input.
+    fn next_line(&mut self) -> Option<io::Result<&[u8]>>;
+}
+
+/// Reads lines from any `io::BufRead` (e.g. a file or `stdin`).
+pub struct ReaderIn<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> ReaderIn<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderIn { reader, buf: Vec::new() }
+    }
+}
+
+impl<R: io::Read> ReaderIn<BufReader<R>> {
+    /// Wraps an unbuffered `io::Read` in a `BufReader`.
+    pub fn from_reader(reader: R) -> Self {
+        ReaderIn::new(BufReader::new(reader))
+    }
+}
+
+impl ReaderIn<BufReader<File>> {
+    /// Opens the file at `path` for reading.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(ReaderIn::from_reader(File::open(path)?))
+    }
+}
+
+impl<R: BufRead> InHandler for ReaderIn<R> {
+    fn next_line(&mut self) -> Option<io::Result<&[u8]>> {
+        self.buf.clear();
+        match self.reader.read_until(b'\n', &mut self.buf) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(&self.buf)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Reads lines from an in-memory string.
+pub struct StrIn<'a> {
+    lines: std::str::SplitInclusive<'a, char>,
+}
+
+impl<'a> StrIn<'a> {
+    pub fn new(input: &'a str) -> Self {
+        StrIn { lines: input.split_inclusive('\n') }
+    }
+}
+
+impl<'a> Iterator for StrIn<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.lines.next()
+    }
+}
+
+impl InHandler for StrIn<'_> {
+    fn next_line(&mut self) -> Option<io::Result<&[u8]>> {
+        self.next().map(|line| Ok(line.as_bytes()))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Processors
+
+/// A stage that transforms events.
+pub trait Processor {
+    /// Processes an event, returning `None` to drop it from the output.
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>>;
+
+    /// Runs `next` on the events that this processor keeps.
+    fn then<P: Processor>(self, next: P) -> Then<Self, P>
+    where Self: Sized
+    {
+        Then(self, next)
+    }
+}
+
+impl<P: Processor + ?Sized> Processor for &mut P {
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        (**self).process(event)
+    }
+}
+
+impl<P: Processor + ?Sized> Processor for Box<P> {
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        (**self).process(event)
+    }
+}
+
+/// A processor that transforms every event by a function. See [`map`].
+pub struct Map<F>(F);
+
+/// Creates a processor from a function `Event -> Event`.
+pub fn map<F>(f: F) -> Map<F>
+where F: for<'a> FnMut(Event<'a>) -> Event<'a>
+{
+    Map(f)
+}
+
+impl<F> Processor for Map<F>
+where F: for<'a> FnMut(Event<'a>) -> Event<'a>
+{
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        Some((self.0)(event))
+    }
+}
+
+/// A processor that keeps only events satisfying a predicate. See [`filter`].
+pub struct Filter<F>(F);
+
+/// Creates a processor that keeps events for which `f` returns `true`.
+pub fn filter<F>(f: F) -> Filter<F>
+where F: FnMut(&Event) -> bool
+{
+    Filter(f)
+}
+
+impl<F> Processor for Filter<F>
+where F: FnMut(&Event) -> bool
+{
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        if (self.0)(&event) { Some(event) } else { None }
+    }
+}
+
+/// Two processors run in sequence. See [`Processor::then`].
+pub struct Then<A, B>(A, B);
+
+impl<A: Processor, B: Processor> Processor for Then<A, B> {
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        self.0.process(event).and_then(|e| self.1.process(e))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Output
+
+/// A destination for processed events.
+///
+/// The lifetime `'a` is that of the data the events borrow from. Handlers
+/// that do not keep events (such as [`WriterOut`]) implement this trait for
+/// every `'a`; a `Vec<Event<'a>>` can only collect events borrowed from input
+/// that outlives it (see [`process_str`]). To keep events from a streaming
+/// input, convert them with `Event::into_owned` in a [`callback`].
+pub trait OutHandler<'a> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()>;
+
+    /// Called once all input has been processed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes events as newline-delimited JSON to any `io::Write`.
+pub struct WriterOut<W: Write> {
+    writer: BufWriter<W>,
+    keyed: bool,
+}
+
+impl<W: Write> WriterOut<W> {
+    pub fn new(writer: W) -> Self {
+        WriterOut { writer: BufWriter::new(writer), keyed: false }
+    }
+
+    /// Writes events as objects rather than arrays (see `Event::keyed`).
+    pub fn keyed(writer: W) -> Self {
+        WriterOut { writer: BufWriter::new(writer), keyed: true }
+    }
+
+    /// Flushes the buffer and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<'a, W: Write> OutHandler<'a> for WriterOut<W> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        if self.keyed {
+            serde_json::to_writer(&mut self.writer, &event.keyed())?;
+        } else {
+            serde_json::to_writer(&mut self.writer, &event)?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<'a> OutHandler<'a> for Vec<Event<'a>> {
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+/// Passes each event to a function. See [`callback`].
+pub struct Callback<F>(pub F);
+
+/// Creates an output handler from a function.
+pub fn callback<F>(f: F) -> Callback<F>
+where F: for<'a> FnMut(Event<'a>) -> io::Result<()>
+{
+    Callback(f)
+}
+
+impl<'a, F> OutHandler<'a> for Callback<F>
+where F: FnMut(Event<'a>) -> io::Result<()>
+{
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        (self.0)(event)
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Errors
+
+/// A line of input that could not be deserialized to an `Event`.
+#[derive(Debug)]
+pub struct LineError {
+    /// 1-based line number.
+    pub line: usize,
+    /// Byte offset of the start of the line.
+    pub offset: u64,
+    /// The text of the line, without its line terminator. Invalid UTF-8 is
+    /// replaced by `U+FFFD`.
+    pub text: String,
+    pub error: serde_json::Error,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} (byte {}): {}", self.line, self.offset, self.error)
+    }
+}
+
+impl std::error::Error for LineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Decides what happens to lines that cannot be deserialized.
+pub trait ErrorHandler {
+    /// Handles a failed line. Returning an error stops processing.
+    fn handle_error(&mut self, error: LineError) -> io::Result<()>;
+
+    /// Called once all input has been processed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Stops processing at the first failed line.
+pub struct Stop;
+
+impl ErrorHandler for Stop {
+    fn handle_error(&mut self, error: LineError) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
+/// Collects failed lines and continues.
+impl ErrorHandler for Vec<LineError> {
+    fn handle_error(&mut self, error: LineError) -> io::Result<()> {
+        self.push(error);
+        Ok(())
+    }
+}
+
+/// Writes failed lines to a dead-letter sink and continues.
+///
+/// Each failed line is written as one JSON object with the keys `line`,
+/// `offset`, `error` and `text`.
+pub struct DeadLetter<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl<W: Write> DeadLetter<W> {
+    pub fn new(writer: W) -> Self {
+        DeadLetter { writer: BufWriter::new(writer) }
+    }
+
+    /// Flushes the buffer and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: Write> ErrorHandler for DeadLetter<W> {
+    fn handle_error(&mut self, error: LineError) -> io::Result<()> {
+        let record = serde_json::json!({
+            "line": error.line,
+            "offset": error.offset,
+            "error": error.error.to_string(),
+            "text": error.text,
+        });
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Running a pipeline
+
+/// Counts of lines processed by a pipeline.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// Lines deserialized to an `Event`.
+    pub ok: usize,
+    /// Lines that failed to deserialize.
+    pub failed: usize,
+}
+
+/// Processes every line of `input` by `processor`, writing results to `output`.
+///
+/// Blank lines are skipped. Processing stops at the first line that cannot be
+/// read or deserialized; see [`process_with_errors`] to continue instead.
+pub fn process<I, P, O>(input: &mut I, processor: &mut P, output: &mut O)
+                        -> io::Result<Summary>
+where I: InHandler + ?Sized,
+      P: Processor + ?Sized,
+      O: for<'a> OutHandler<'a> + ?Sized
+{
+    process_with_errors(input, processor, output, &mut Stop)
+}
+
+/// Processes every line of `input`, passing lines that fail to deserialize
+/// to `errors`.
+///
+/// Processing continues past failed lines unless `errors` returns an error.
+pub fn process_with_errors<I, P, O, E>(input: &mut I,
+                                       processor: &mut P,
+                                       output: &mut O,
+                                       errors: &mut E)
+                                       -> io::Result<Summary>
+where I: InHandler + ?Sized,
+      P: Processor + ?Sized,
+      O: for<'a> OutHandler<'a> + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    let mut lines = Lines::default();
+    while let Some(line) = input.next_line() {
+        lines.process_line(line?, processor, output, errors)?;
+    }
+    output.flush()?;
+    errors.flush()?;
+    Ok(lines.summary)
+}
+
+/// Processes every event of an in-memory string.
+///
+/// The events may be separated by any whitespace, not only newlines, and may
+/// span lines (e.g. pretty-printed JSON). Processing stops at the first event
+/// that cannot be deserialized; `Summary::ok` counts the events before it.
+///
+/// Unlike [`process`], the events passed to `output` may borrow from `input`,
+/// so they can be collected into a `Vec<Event>`.
+pub fn process_str<'a, P, O>(input: &'a str, processor: &mut P, output: &mut O)
+                             -> io::Result<Summary>
+where P: Processor + ?Sized,
+      O: OutHandler<'a> + ?Sized
+{
+    let mut summary = Summary::default();
+    for event in Deserializer::from_str(input).into_iter::<Event<'a>>() {
+        let event = event?;
+        summary.ok += 1;
+        if let Some(e) = processor.process(event) {
+            output.write_event(e)?;
+        }
+    }
+    output.flush()?;
+    Ok(summary)
+}
+
+/// Processes every line of an in-memory string, passing lines that fail to
+/// deserialize to `errors`.
+///
+/// Unlike [`process_str`], this needs one event per line, so that it can
+/// continue with the next line after a failure.
+pub fn process_str_with_errors<'a, P, O, E>(input: &'a str,
+                                            processor: &mut P,
+                                            output: &mut O,
+                                            errors: &mut E)
+                                            -> io::Result<Summary>
+where P: Processor + ?Sized,
+      O: OutHandler<'a> + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    process_bytes_with_errors(input.as_bytes(), processor, output, errors)
+}
+
+/// Processes every line of an in-memory buffer, such as a memory-mapped file
+/// (see [`EventFile`](crate::file::EventFile)).
+///
+/// As with [`process_str`], events may borrow from `input`. A line that is not
+/// valid UTF-8 fails to deserialize on its own.
+pub fn process_bytes<'a, P, O>(input: &'a [u8], processor: &mut P, output: &mut O)
+                               -> io::Result<Summary>
+where P: Processor + ?Sized,
+      O: OutHandler<'a> + ?Sized
+{
+    process_bytes_with_errors(input, processor, output, &mut Stop)
+}
+
+/// Processes every line of an in-memory buffer, passing lines that fail to
+/// deserialize to `errors`.
+pub fn process_bytes_with_errors<'a, P, O, E>(input: &'a [u8],
+                                              processor: &mut P,
+                                              output: &mut O,
+                                              errors: &mut E)
+                                              -> io::Result<Summary>
+where P: Processor + ?Sized,
+      O: OutHandler<'a> + ?Sized,
+      E: ErrorHandler + ?Sized
+{
+    let mut lines = Lines::default();
+    for line in input.split_inclusive(|b| *b == b'\n') {
+        lines.process_line(line, processor, output, errors)?;
+    }
+    output.flush()?;
+    errors.flush()?;
+    Ok(lines.summary)
+}
+
+/// Position in the input and counts so far.
+#[derive(Default)]
+pub(crate) struct Lines {
+    /// Lines seen so far.
+    pub(crate) line: usize,
+    /// Byte offset of the next line.
+    pub(crate) offset: u64,
+    pub(crate) summary: Summary,
+}
+
+impl Lines {
+    /// Deserializes the next line of input, or returns `None` if it is blank.
+    pub(crate) fn parse<'a>(&mut self, raw: &'a [u8]) -> Option<Result<Event<'a>, LineError>> {
+        self.parse_as(raw)
+    }
+
+    /// Deserializes the next line of input to any type, such as part of an
+    /// event.
+    pub(crate) fn parse_as<'a, T>(&mut self, raw: &'a [u8]) -> Option<Result<T, LineError>>
+    where T: Deserialize<'a>
+    {
+        self.line += 1;
+        let offset = self.offset;
+        self.offset += raw.len() as u64;
+
+        let line = raw.trim_ascii();
+        if line.is_empty() {
+            return None;
+        }
+
+        match serde_json::from_slice::<T>(line) {
+            Ok(value) => {
+                self.summary.ok += 1;
+                Some(Ok(value))
+            },
+            Err(error) => {
+                self.summary.failed += 1;
+                let text = raw.strip_suffix(b"\n").unwrap_or(raw);
+                let text = text.strip_suffix(b"\r").unwrap_or(text);
+                Some(Err(LineError {
+                    line: self.line,
+                    offset,
+                    text: String::from_utf8_lossy(text).into_owned(),
+                    error,
+                }))
+            },
+        }
+    }
+
+    fn process_line<'a, P, O, E>(&mut self,
+                                 raw: &'a [u8],
+                                 processor: &mut P,
+                                 output: &mut O,
+                                 errors: &mut E)
+                                 -> io::Result<()>
+    where P: Processor + ?Sized,
+          O: OutHandler<'a> + ?Sized,
+          E: ErrorHandler + ?Sized
+    {
+        match self.parse(raw) {
+            Some(Ok(event)) => match processor.process(event) {
+                Some(e) => output.write_event(e),
+                None    => Ok(()),
+            },
+            Some(Err(error)) => errors.handle_error(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Process a string of events
+///
+/// For each `Event` in `events_json`, this function transforms each event
+/// by the `processor` function, outputting processed events to `stdout`.
+/// This is a shorthand for [`process_str`] with a [`WriterOut`] on `stdout`,
+/// so the events may be separated by any whitespace.
+pub fn process_events(events_json: &str,
+                      processor: &mut dyn std::ops::Fn(Event) -> Event)
+                      -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut output = WriterOut::new(stdout.lock());
+    process_str(events_json, &mut map(processor), &mut output).map(|_| ())
+}
+
+#[cfg(test)]
+mod test_process {
+    use crate::process::*;
+    use crate::types::Event;
+
+    const EVENTS: &str = "\
+        [\"xyz\",\"2010-01-01\",null,\"Death\",[],\
+        {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":1},\
+         \"domain\":\"Death\",\"facts\":{}}]\n\
+        \n\
+        [\"abc\",\"2010-01-01\",null,\"Enrollment\",[],\
+        {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":1},\
+         \"domain\":\"Enrollment\",\"facts\":{}}]\n";
+
+    #[test]
+    fn test_reader_to_writer() {
+        let mut input = ReaderIn::from_reader(EVENTS.as_bytes());
+        let mut output = WriterOut::new(Vec::new());
+        process(&mut input, &mut map(|e| e), &mut output).unwrap();
+
+        let written = String::from_utf8(output.into_inner().unwrap()).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        assert!(written.starts_with("[\"xyz\",\"2010-01-01\",null,\"Death\""));
+    }
+
+    #[test]
+    fn test_keyed_writer() {
+        let mut output = WriterOut::keyed(Vec::new());
+        process_str(EVENTS, &mut map(|e| e), &mut output).unwrap();
+        let written = String::from_utf8(output.into_inner().unwrap()).unwrap();
+        assert!(written.starts_with("{\"patient\":\"xyz\",\"begin\":\"2010-01-01\",\"end\":null,"));
+
+        let mut output = WriterOut::new(Vec::new());
+        process_str(&written, &mut map(|e| e), &mut output).unwrap();
+        let written = String::from_utf8(output.into_inner().unwrap()).unwrap();
+        assert_eq!(written, EVENTS.replace("\n\n", "\n"));
+    }
+
+    #[test]
+    fn test_str_to_vec() {
+        let mut out : Vec<Event> = Vec::new();
+        let mut processor = filter(|e: &Event| e.d == "Enrollment")
+            .then(map(|mut e| { e.concepts.push("enrolled".to_string()); e }));
+        process_str(EVENTS, &mut processor, &mut out).unwrap();
+
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].concepts, vec!["enrolled"]);
+    }
+
+    #[test]
+    fn test_str_stream() {
+        // Events on one line, and pretty-printed across lines.
+        let event: Event = serde_json::from_str(EVENTS.lines().next().unwrap()).unwrap();
+        let pretty = serde_json::to_string_pretty(&event).unwrap();
+        let json = format!("{} {}\t{}\n\n{}", EVENTS.replace('\n', " "), pretty,
+                           EVENTS.lines().next().unwrap(), pretty);
+        let mut out : Vec<Event> = Vec::new();
+        let summary = process_str(&json, &mut map(|e| e), &mut out).unwrap();
+        assert_eq!(summary, Summary { ok: 5, failed: 0 });
+        assert_eq!(out.iter().filter(|e| e.d == "Death").count(), 4);
+
+        let mut out : Vec<Event> = Vec::new();
+        let error = process_str("[1] [\"xyz\"", &mut map(|e| e), &mut out).unwrap_err();
+        assert!(error.to_string().contains("line 1 column"));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_callback() {
+        let mut n = 0;
+        let mut output = callback(|_e| { n += 1; Ok(()) });
+        process(&mut StrIn::new(EVENTS), &mut map(|e| e), &mut output).unwrap();
+        assert_eq!(n, 2);
+    }
+
+    #[test]
+    fn test_stops_at_bad_line() {
+        let json = format!("{}not json\n{}", EVENTS, EVENTS);
+        let mut out : Vec<Event> = Vec::new();
+        assert!(process_str(&json, &mut map(|e| e), &mut out).is_err());
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn test_collect_errors() {
+        let json = format!("{}not json\n{}", EVENTS, EVENTS);
+        let mut out : Vec<Event> = Vec::new();
+        let mut errors : Vec<LineError> = Vec::new();
+        let summary = process_str_with_errors(&json, &mut map(|e| e),
+                                              &mut out, &mut errors).unwrap();
+
+        assert_eq!(summary, Summary { ok: 4, failed: 1 });
+        assert_eq!(out.len(), 4);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+        assert_eq!(errors[0].offset, EVENTS.len() as u64);
+        assert_eq!(errors[0].text, "not json");
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        let mut bytes = EVENTS.as_bytes().to_vec();
+        bytes.extend_from_slice(b"[\"\xff\"]\r\n");
+        bytes.extend_from_slice(EVENTS.as_bytes());
+
+        let mut input = ReaderIn::from_reader(&bytes[..]);
+        let mut output = WriterOut::new(Vec::new());
+        let mut errors = DeadLetter::new(Vec::new());
+        let summary = process_with_errors(&mut input, &mut map(|e| e),
+                                          &mut output, &mut errors).unwrap();
+        assert_eq!(summary, Summary { ok: 4, failed: 1 });
+
+        let dead = String::from_utf8(errors.into_inner().unwrap()).unwrap();
+        let record : serde_json::Value = serde_json::from_str(&dead).unwrap();
+        assert_eq!(record["line"], 4);
+        assert_eq!(record["text"], "[\"\u{fffd}\"]");
+    }
+}
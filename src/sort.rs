@@ -0,0 +1,395 @@
+//! Sorts newline-delimited JSON events by `(p, b, e, d)` in bounded memory.
+//!
+//! A [`Sorter`] decodes only the first four elements of each event, keeping
+//! the line itself as raw bytes, so events are written back exactly as they
+//! were read. Lines are buffered up to [`SortOptions::memory`] bytes, then
+//! sorted and spilled to a temporary file (a run). At the end the runs are
+//! merged, at most 128 at a time. The sort is stable, so events with equal
+//! keys keep their input order.
+//!
+//! Subjects are ordered as [`SubjectID`]s, and times as in
+//! [`compare_events`](crate::timeline::compare_events), so the output of a
+//! sort can be grouped by [`group_sorted`](crate::timeline::group_sorted)
+//! without re-sorting.
+//!
+//! Example:
+//! ```
+//! use eddeserus::process::ReaderIn;
+//! use eddeserus::sort::*;
+//!
+//! let json = "\
+//!     [\"xyz\",1,null,\"Death\",[],\
+//!      {\"patient_id\":\"xyz\",\"time\":{\"begin\":1,\"end\":null},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n\
+//!     [\"abc\",1,null,\"Death\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":1,\"end\":null},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n";
+//!
+//! let mut out = Vec::new();
+//! sort_events(&mut ReaderIn::from_reader(json.as_bytes()), &mut out,
+//!             &SortOptions::default()).unwrap();
+//! assert!(out.starts_with(b"[\"abc\""));
+//! ```
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{self, AtomicUsize};
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::process::{ErrorHandler, InHandler, Lines, Stop, Summary};
+use crate::timeline::compare_ends;
+use crate::types::{EventTime, SubjectID};
+
+/// Most runs merged at once.
+const MAX_FAN_IN : usize = 128;
+
+/// Settings for a [`Sorter`].
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// Bytes of lines to buffer before spilling a run. Defaults to 256 MiB.
+    pub memory: usize,
+    /// Directory for runs. Defaults to the system's temporary directory.
+    pub dir: PathBuf,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        SortOptions { memory: 256 << 20, dir: std::env::temp_dir() }
+    }
+}
+
+/// The header of an event, skipping its concepts and context. Like `Event`,
+/// it reads both the array and the keyed form.
+#[derive(Deserialize)]
+struct Header<'a> {
+    #[serde(borrow, alias = "patient")]
+    p: SubjectID<'a>,
+    #[serde(alias = "begin")]
+    b: EventTime,
+    #[serde(alias = "end")]
+    e: Option<EventTime>,
+    #[serde(borrow, alias = "domain")]
+    d: Cow<'a, str>,
+    #[allow(dead_code)]
+    concepts: IgnoredAny,
+    #[allow(dead_code)]
+    context: IgnoredAny,
+}
+
+/// A line of input with its sort key.
+struct Record {
+    p: SubjectID<'static>,
+    b: EventTime,
+    e: Option<EventTime>,
+    d: String,
+    line: Vec<u8>,
+}
+
+impl Record {
+    fn new(header: Header, line: &[u8]) -> Record {
+        Record {
+            p: header.p.into_owned(),
+            b: header.b,
+            e: header.e,
+            d: header.d.into_owned(),
+            line: line.to_vec(),
+        }
+    }
+
+    /// Approximate bytes of memory used.
+    fn size(&self) -> usize {
+        let p = match &self.p {
+            SubjectID::IDstr(s) => s.len(),
+            SubjectID::Idint(_) => 0,
+        };
+        std::mem::size_of::<Record>() + p + self.d.len() + self.line.len()
+    }
+
+    fn compare(&self, other: &Record) -> Ordering {
+        self.p.cmp(&other.p)
+            .then_with(|| self.b.total_cmp(&other.b))
+            .then_with(|| compare_ends(&self.e, &other.e))
+            .then_with(|| self.d.cmp(&other.d))
+    }
+}
+
+/// Sorts events from one or more inputs.
+pub struct Sorter {
+    options: SortOptions,
+    buffer: Vec<Record>,
+    used: usize,
+    runs: Vec<Run>,
+}
+
+impl Sorter {
+    pub fn new(options: SortOptions) -> Self {
+        Sorter { options, buffer: Vec::new(), used: 0, runs: Vec::new() }
+    }
+
+    /// Adds every line of `input`, passing lines whose header cannot be
+    /// deserialized to `errors`. Blank lines are skipped.
+    pub fn add<I, E>(&mut self, input: &mut I, errors: &mut E) -> io::Result<Summary>
+    where I: InHandler + ?Sized,
+          E: ErrorHandler + ?Sized
+    {
+        let mut lines = Lines::default();
+        while let Some(raw) = input.next_line() {
+            let raw = raw?;
+            match lines.parse_as::<Header>(raw) {
+                Some(Ok(header)) => {
+                    let record = Record::new(header, raw.trim_ascii());
+                    self.used += record.size();
+                    self.buffer.push(record);
+                    if self.used > self.options.memory {
+                        self.spill()?;
+                    }
+                },
+                Some(Err(error)) => errors.handle_error(error)?,
+                None => (),
+            }
+        }
+        errors.flush()?;
+        Ok(lines.summary)
+    }
+
+    /// Writes all events added so far, in order, as newline-delimited JSON.
+    pub fn finish<W: Write + ?Sized>(mut self, output: &mut W) -> io::Result<()> {
+        if self.runs.is_empty() {
+            self.buffer.sort_by(Record::compare);
+            for record in &self.buffer {
+                output.write_all(&record.line)?;
+                output.write_all(b"\n")?;
+            }
+        } else {
+            self.spill()?;
+            let mut runs = std::mem::take(&mut self.runs);
+            while runs.len() > MAX_FAN_IN {
+                let rest = runs.split_off(MAX_FAN_IN);
+                let mut run = Run::create(&self.options)?;
+                merge(&runs, &mut run.writer()?)?;
+                runs = std::iter::once(run).chain(rest).collect();
+            }
+            merge(&runs, output)?;
+        }
+        output.flush()
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_by(Record::compare);
+        let mut run = Run::create(&self.options)?;
+        let mut writer = run.writer()?;
+        for record in self.buffer.drain(..) {
+            writer.write_all(&record.line)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        self.runs.push(run);
+        self.used = 0;
+        Ok(())
+    }
+}
+
+/// Sorts the events of `input` into `output`, stopping at the first line whose
+/// header cannot be deserialized.
+pub fn sort_events<I, W>(input: &mut I, output: &mut W, options: &SortOptions)
+                         -> io::Result<Summary>
+where I: InHandler + ?Sized,
+      W: Write + ?Sized
+{
+    let mut sorter = Sorter::new(options.clone());
+    let summary = sorter.add(input, &mut Stop)?;
+    sorter.finish(output)?;
+    Ok(summary)
+}
+
+/*----------------------------------------------------------------------------*/
+// Runs
+
+/// Distinguishes the runs of one process.
+static RUNS : AtomicUsize = AtomicUsize::new(0);
+
+/// A file of sorted lines, removed when dropped.
+struct Run {
+    path: PathBuf,
+}
+
+impl Run {
+    fn create(options: &SortOptions) -> io::Result<Run> {
+        let id = RUNS.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = options.dir.join(format!("eddeserus-sort-{}-{}.json", std::process::id(), id));
+        File::create(&path)?;
+        Ok(Run { path })
+    }
+
+    fn writer(&mut self) -> io::Result<BufWriter<File>> {
+        Ok(BufWriter::new(File::create(&self.path)?))
+    }
+
+    fn reader(&self) -> io::Result<BufReader<File>> {
+        Ok(BufReader::new(File::open(&self.path)?))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_record<R: BufRead>(reader: &mut R, buf: &mut Vec<u8>) -> io::Result<Option<Record>> {
+    buf.clear();
+    if reader.read_until(b'\n', buf)? == 0 {
+        return Ok(None);
+    }
+    let line = buf.trim_ascii();
+    let header = serde_json::from_slice::<Header>(line)?;
+    Ok(Some(Record::new(header, line)))
+}
+
+/// The next record of a run, ordered so that a `BinaryHeap` pops the least.
+struct Head {
+    record: Record,
+    run: usize,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Head) -> Ordering {
+        other.record.compare(&self.record).then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Merges runs, taking equal records from earlier runs first.
+fn merge<W: Write + ?Sized>(runs: &[Run], output: &mut W) -> io::Result<()> {
+    let mut readers = runs.iter().map(Run::reader).collect::<io::Result<Vec<_>>>()?;
+    let mut buf = Vec::new();
+    let mut heap = BinaryHeap::with_capacity(runs.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(record) = read_record(reader, &mut buf)? {
+            heap.push(Head { record, run });
+        }
+    }
+
+    while let Some(Head { record, run }) = heap.pop() {
+        output.write_all(&record.line)?;
+        output.write_all(b"\n")?;
+        if let Some(record) = read_record(&mut readers[run], &mut buf)? {
+            heap.push(Head { record, run });
+        }
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod test_sort {
+    use crate::process::*;
+    use crate::sort::*;
+    use crate::types::Event;
+
+    fn event(p: &str, b: i64, e: Option<i64>, d: &str, tag: usize) -> String {
+        let e = e.map_or("null".to_string(), |e| e.to_string());
+        format!("[\"{p}\",{b},{e},\"{d}\",[],\
+                 {{\"patient_id\":\"{p}\",\"time\":{{\"begin\":{b},\"end\":{e}}},\
+                   \"domain\":\"{d}\",\"facts\":{{}},\"misc\":{{\"tag\":{t}}}}}]\n",
+                p = p, b = b, e = e, d = d, t = tag)
+    }
+
+    fn input() -> String {
+        (0..300)
+            .map(|i| event(&format!("p{}", (i * 7) % 11), (i * 13 % 5) as i64,
+                           if i % 3 == 0 { None } else { Some(9) },
+                           if i % 2 == 0 { "Death" } else { "Enrollment" }, i))
+            .collect()
+    }
+
+    fn sort(json: &str, options: &SortOptions) -> String {
+        let mut out = Vec::new();
+        sort_events(&mut StrIn::new(json), &mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let json = input();
+        let sorted = sort(&json, &SortOptions::default());
+
+        // The same lines, in order of their keys and stable within equal keys.
+        let mut expected : Vec<(usize, &str)> = json.lines().enumerate().collect();
+        let key = |line: &str| {
+            let header : Header = serde_json::from_str(line).unwrap();
+            Record::new(header, line.as_bytes())
+        };
+        expected.sort_by(|a, b| key(a.1).compare(&key(b.1)).then(a.0.cmp(&b.0)));
+        let expected : Vec<&str> = expected.into_iter().map(|(_, l)| l).collect();
+        assert_eq!(sorted.lines().collect::<Vec<_>>(), expected);
+        assert!(sorted.starts_with("[\"p0\",0,9,"));
+    }
+
+    #[test]
+    fn test_spill() {
+        let json = input();
+        let expected = sort(&json, &SortOptions::default());
+
+        // A few runs.
+        let options = SortOptions { memory: 10_000, ..SortOptions::default() };
+        assert_eq!(sort(&json, &options), expected);
+
+        // One run per event, merged in two passes.
+        let options = SortOptions { memory: 1, ..SortOptions::default() };
+        assert_eq!(sort(&json, &options), expected);
+    }
+
+    #[test]
+    fn test_keyed() {
+        let json = input();
+        let keyed: String = json.lines()
+            .map(|line| {
+                let event: Event = serde_json::from_str(line).unwrap();
+                serde_json::to_string(&event.keyed()).unwrap() + "\n"
+            })
+            .collect();
+        let tuples = |sorted: String| -> Vec<String> {
+            sorted.lines()
+                .map(|line| serde_json::to_string(&serde_json::from_str::<Event>(line).unwrap()).unwrap())
+                .collect()
+        };
+        let expected = tuples(sort(&json, &SortOptions::default()));
+        assert_eq!(tuples(sort(&keyed, &SortOptions::default())), expected);
+        let options = SortOptions { memory: 0, ..SortOptions::default() };
+        assert_eq!(tuples(sort(&keyed, &options)), expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let json = format!("{}\n[1,2]\n{}", event("b", 0, None, "Death", 0),
+                           event("a", 0, None, "Death", 1));
+        let mut sorter = Sorter::new(SortOptions::default());
+        let mut errors : Vec<LineError> = Vec::new();
+        let summary = sorter.add(&mut StrIn::new(&json), &mut errors).unwrap();
+        assert_eq!(summary, Summary { ok: 2, failed: 1 });
+        assert_eq!(errors[0].line, 3);
+
+        let mut out = Vec::new();
+        sorter.finish(&mut out).unwrap();
+        assert!(out.starts_with(b"[\"a\""));
+    }
+}
@@ -0,0 +1,72 @@
+//! Fixtures shared by the tests of several modules.
+
+use serde::Serialize;
+
+use crate::types::{Event, OwnedEvent};
+
+/// One event of every domain, one per line: string and integer IDs, dates and
+/// offsets, every field of every fact, and raw JSON with unusual spacing and
+/// characters that need quoting in CSV.
+pub const EVENTS: &str = "\
+    [\"a\",\"2010-01-01\",\"2010-01-02\",\"Claim\",[],\
+     {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-01-01\",\"end\":\"2010-01-02\"},\
+      \"domain\":\"Claim\",\"facts\":{\"claim\":{\"id\":\"c1\",\"type\":\"IP\",\"index\":2,\"procedure\":\"x\"},\
+      \"location\":\"Inpatient\",\
+      \"cost\":{\"charge\":\"20\",\"cost\":\"12.5\",\"allowed\":\"15\",\"transaction\":\"t\"}}}]\n\
+    [\"a\",\"2011-05-06\",null,\"Death\",[],\
+     {\"patient_id\":\"a\",\"time\":{\"begin\":\"2011-05-06\",\"end\":null},\
+      \"domain\":\"Death\",\"facts\":{},\"source\":{ \"table\" : [1, 2.50,\"\\u00e9\"] }}]\n\
+    [7,0,null,\"Demographics\",[],\
+     {\"patient_id\":7,\"time\":{\"begin\":0,\"end\":null},\
+      \"domain\":\"Demographics\",\"facts\":{\"demo\":{\"field\":\"RaceCodes\",\"info\":[\"a\",{\"b\":1}]}}}]\n\
+    [7,1,null,\"Demographics\",[],\
+     {\"patient_id\":7,\"time\":{\"begin\":1,\"end\":null},\
+      \"domain\":\"Demographics\",\"facts\":{\"demo\":{\"field\":\"BirthYear\",\"info\":1980}}}]\n\
+    [7,2,5,\"Diagnosis\",[\"dm\"],\
+     {\"patient_id\":7,\"time\":{\"begin\":2,\"end\":5},\
+      \"domain\":\"Diagnosis\",\"facts\":{\"code\":{\"code\":\"E11.9\",\"codebook\":\"ICD10\"},\
+      \"claim\":{\"id\":\"c2\"},\"location\":\"Outpatient\"}}]\n\
+    [7,2,null,\"Eligibility\",[],\
+     {\"patient_id\":7,\"time\":{\"begin\":2,\"end\":null},\
+      \"domain\":\"Eligibility\",\"facts\":{}}]\n\
+    [\"b\",\"2009-01-01\",\"2009-12-31\",\"Enrollment\",[],\
+     {\"patient_id\":\"b\",\"time\":{\"begin\":\"2009-01-01\",\"end\":\"2009-12-31\"},\
+      \"domain\":\"Enrollment\",\"facts\":{}}]\n\
+    [7,3,null,\"Labs\",[\"a1c\",\"lab\"],\
+     {\"patient_id\":7,\"time\":{\"begin\":3,\"end\":null},\
+      \"domain\":\"Labs\",\"facts\":{\"code\":{\"code\":\"4548-4\",\"codebook\":\"LOINC\"},\
+      \"value\":{\"text\":\"high\",\"number\":6.5,\"units\":\"%\"}},\"misc\":{\"k\" :[1,2]}}]\n\
+    [\"a\",\"2010-01-01\",null,\"Medication\",[\"statin\"],\
+     {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+      \"domain\":\"Medication\",\"facts\":{\"code\":{\"code\":\"00093005801\",\"codebook\":\"NDC\"},\
+      \"fill\":{\"days_supply\":30,\"quantity\":60,\"strength\":\"5mg\"},\"location\":\"Unknown\",\
+      \"claim\":{\"id\":\"c3\"}}}]\n\
+    [\"a\",\"2010-02-01\",null,\"Medication\",[],\
+     {\"patient_id\":\"a\",\"time\":{\"begin\":\"2010-02-01\",\"end\":null},\
+      \"domain\":\"Medication\",\"facts\":{\"code\":{\"code\":\"statin\"}},\
+      \"source\":{\"table\":\"rx, \\\"fills\\\"\"}}]\n\
+    [7,-4,null,\"Procedure\",[],\
+     {\"patient_id\":7,\"time\":{\"begin\":4,\"end\":null},\
+      \"domain\":\"Procedure\",\"facts\":{\"code\":{\"code\":\"99213\",\"codebook\":\"CPT\"}}}]\n\
+    [\"c\",\"1969-12-31\",null,\"Undefined\",[],\
+     {\"patient_id\":\"c\",\"time\":{\"begin\":\"1969-12-31\",\"end\":null},\
+      \"domain\":\"Undefined\",\"facts\":{}}]\n";
+
+/// An event of patient `p` in domain `d` from `b` through `e`. The patient and
+/// times are written as JSON, so integers give integer IDs and offsets and
+/// strings give string IDs and dates. `facts` is the JSON of the facts.
+pub fn event<P, T>(p: P, b: T, e: Option<T>, d: &str, concepts: &[&str], facts: &str) -> OwnedEvent
+where P: Serialize, T: Serialize
+{
+    let json = format!("\
+        [{p},{b},{e},\"{d}\",{c},\
+         {{\"patient_id\":{p},\"time\":{{\"begin\":{b},\"end\":{e}}},\
+           \"domain\":\"{d}\",\"facts\":{f}}}]",
+        p = serde_json::to_string(&p).unwrap(),
+        b = serde_json::to_string(&b).unwrap(),
+        e = serde_json::to_string(&e).unwrap(),
+        d = d,
+        c = serde_json::to_string(concepts).unwrap(),
+        f = facts);
+    serde_json::from_str::<Event>(&json).unwrap().into_owned()
+}
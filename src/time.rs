@@ -0,0 +1,423 @@
+//! Typed event times.
+//!
+//! EDM times are either calendar dates (`"2001-05-01"`) or integer offsets
+//! (e.g. days since some study-specific origin). [`EventTime`] holds either
+//! and (de)serializes to exactly the JSON it was read from.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, Visitor};
+
+/*----------------------------------------------------------------------------*/
+// Date
+
+/// A calendar date, stored as the number of days since 1970-01-01.
+///
+/// Dates are read and written in `YYYY-MM-DD` form. Years before 0000 or
+/// after 9999 have a sign and at least four digits (`-0001-12-31`,
+/// `+10000-01-01`), as in ISO 8601's expanded form, so that every date reads
+/// back as itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    days: i32,
+}
+
+impl Date {
+    /// Returns the date for a year, month (1-12) and day (1-31), or `None`
+    /// if there is no such date or it is too far from 1970 to hold.
+    pub fn from_ymd(year: i32, month: u32, day: u32) -> Option<Date> {
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return None;
+        }
+        i32::try_from(days_from_civil(year, month, day)).ok().map(|days| Date { days })
+    }
+
+    /// Returns the date `days` days after 1970-01-01.
+    pub fn from_days(days: i32) -> Date {
+        Date { days }
+    }
+
+    /// Number of days since 1970-01-01.
+    pub fn days(self) -> i32 {
+        self.days
+    }
+
+    pub fn year(self) -> i32 {
+        civil_from_days(self.days).0
+    }
+
+    pub fn month(self) -> u32 {
+        civil_from_days(self.days).1
+    }
+
+    pub fn day(self) -> u32 {
+        civil_from_days(self.days).2
+    }
+
+    /// Number of days from `self` to `other` (negative if `other` is earlier).
+    pub fn days_until(self, other: Date) -> i64 {
+        i64::from(other.days) - i64::from(self.days)
+    }
+
+    /// The date `n` days later (earlier if `n` is negative), saturating at
+    /// the earliest and latest dates that a `Date` can hold.
+    pub fn add_days(self, n: i64) -> Date {
+        let days = i64::from(self.days).saturating_add(n);
+        Date { days: i32::try_from(days).unwrap_or(if n < 0 { i32::MIN } else { i32::MAX }) }
+    }
+}
+
+fn is_leap_year(y: i32) -> bool {
+    (y % 4 == 0 && y % 100 != 0) || y % 400 == 0
+}
+
+fn days_in_month(y: i32, m: u32) -> u32 {
+    match m {
+        2 if is_leap_year(y) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Conversions between civil dates and days since 1970-01-01; see
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i32, m: u32, d: u32) -> i64 {
+    // In i64, so that every `i32` year has a number of days.
+    let y = i64::from(y) - if m <= 2 { 1 } else { 0 };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (i64::from(m) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(d) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i32) -> (i32, u32, u32) {
+    // In i64, so that every `i32` number of days has a date.
+    let z = i64::from(z) + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y as i32, m, d)
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (y, m, d) = civil_from_days(self.days);
+        if (0..=9999).contains(&y) {
+            write!(f, "{:04}-{:02}-{:02}", y, m, d)
+        } else {
+            write!(f, "{:+05}-{:02}-{:02}", y, m, d)
+        }
+    }
+}
+
+impl FromStr for Date {
+    type Err = ParseTimeError;
+
+    /// Parses a date of the form `YYYY-MM-DD`, or `±YYYYY-MM-DD` with a sign
+    /// and four or more digits of year.
+    fn from_str(s: &str) -> Result<Date, ParseTimeError> {
+        let err = || ParseTimeError { input: s.to_string() };
+        if !s.is_ascii() || s.len() < 10 {
+            return Err(err());
+        }
+        let (year, rest) = s.split_at(s.len() - 6);
+        let b = rest.as_bytes();
+        if b[0] != b'-' || b[3] != b'-' {
+            return Err(err());
+        }
+        let digits = |x: &str| -> Result<u32, ParseTimeError> {
+            if !x.is_empty() && x.bytes().all(|c| c.is_ascii_digit()) {
+                x.parse().map_err(|_| err())
+            } else {
+                Err(err())
+            }
+        };
+        let y = match year.as_bytes()[0] {
+            b'+' | b'-' if year.len() >= 5 => {
+                let y = i64::from(digits(&year[1..])?);
+                i32::try_from(if year.starts_with('-') { -y } else { y }).map_err(|_| err())?
+            },
+            _ if year.len() == 4 => digits(year)? as i32,
+            _ => return Err(err()),
+        };
+        let m = digits(&rest[1..3])?;
+        let d = digits(&rest[4..6])?;
+        Date::from_ymd(y, m, d).ok_or_else(err)
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Date, D::Error> {
+        deserializer.deserialize_str(EventTimeVisitor).and_then(|t| match t {
+            EventTime::Date(d) => Ok(d),
+            EventTime::Offset(_) => Err(de::Error::custom("expected a date")),
+        })
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// EventTime
+
+/// The begin or end time of an event: a calendar date or an integer offset.
+///
+/// Times of the same kind compare and subtract as expected. A date and an
+/// offset cannot be compared (`partial_cmp` returns `None`) and have no
+/// difference in days; use [`EventTime::total_cmp`] where a total order is
+/// needed, e.g. for sorting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventTime {
+    Date(Date),
+    Offset(i64),
+}
+
+impl EventTime {
+    pub fn as_date(&self) -> Option<Date> {
+        match self {
+            EventTime::Date(d) => Some(*d),
+            EventTime::Offset(_) => None,
+        }
+    }
+
+    pub fn as_offset(&self) -> Option<i64> {
+        match self {
+            EventTime::Date(_) => None,
+            EventTime::Offset(i) => Some(*i),
+        }
+    }
+
+    /// Number of days from `self` to `other`, or `None` if one is a date and
+    /// the other an offset. Saturates for offsets further apart than an `i64`
+    /// can count, so the sign is always right.
+    pub fn days_until(&self, other: &EventTime) -> Option<i64> {
+        match (self, other) {
+            (EventTime::Date(a), EventTime::Date(b)) => Some(a.days_until(*b)),
+            (EventTime::Offset(a), EventTime::Offset(b)) => Some(b.saturating_sub(*a)),
+            _ => None,
+        }
+    }
+
+    /// The time `n` days later (earlier if `n` is negative), saturating at
+    /// the earliest and latest dates or offsets, so that windows of any
+    /// length can be built from configuration.
+    pub fn add_days(&self, n: i64) -> EventTime {
+        match self {
+            EventTime::Date(d) => EventTime::Date(d.add_days(n)),
+            EventTime::Offset(i) => EventTime::Offset(i.saturating_add(n)),
+        }
+    }
+
+    /// A total order in which all offsets come before all dates.
+    pub fn total_cmp(&self, other: &EventTime) -> Ordering {
+        match (self, other) {
+            (EventTime::Date(a), EventTime::Date(b)) => a.cmp(b),
+            (EventTime::Offset(a), EventTime::Offset(b)) => a.cmp(b),
+            (EventTime::Offset(_), EventTime::Date(_)) => Ordering::Less,
+            (EventTime::Date(_), EventTime::Offset(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for EventTime {
+    fn partial_cmp(&self, other: &EventTime) -> Option<Ordering> {
+        match (self, other) {
+            (EventTime::Date(a), EventTime::Date(b)) => Some(a.cmp(b)),
+            (EventTime::Offset(a), EventTime::Offset(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl From<Date> for EventTime {
+    fn from(d: Date) -> EventTime {
+        EventTime::Date(d)
+    }
+}
+
+impl From<i64> for EventTime {
+    fn from(i: i64) -> EventTime {
+        EventTime::Offset(i)
+    }
+}
+
+impl fmt::Display for EventTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventTime::Date(d) => d.fmt(f),
+            EventTime::Offset(i) => i.fmt(f),
+        }
+    }
+}
+
+impl FromStr for EventTime {
+    type Err = ParseTimeError;
+
+    /// Parses an integer offset or a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<EventTime, ParseTimeError> {
+        match s.parse::<i64>() {
+            Ok(i) => Ok(EventTime::Offset(i)),
+            Err(_) => s.parse().map(EventTime::Date),
+        }
+    }
+}
+
+impl Serialize for EventTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            EventTime::Date(d) => d.serialize(serializer),
+            EventTime::Offset(i) => serializer.serialize_i64(*i),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for EventTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<EventTime, D::Error> {
+        deserializer.deserialize_any(EventTimeVisitor)
+    }
+}
+
+struct EventTimeVisitor;
+
+impl<'de> Visitor<'de> for EventTimeVisitor {
+    type Value = EventTime;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an integer or a date string of the form YYYY-MM-DD")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<EventTime, E> {
+        Ok(EventTime::Offset(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<EventTime, E> {
+        i64::try_from(v)
+            .map(EventTime::Offset)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<EventTime, E> {
+        v.parse::<Date>()
+            .map(EventTime::Date)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Errors
+
+/// A string that is not a valid date or offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTimeError {
+    pub input: String,
+}
+
+impl fmt::Display for ParseTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid event time {:?} (expected an integer or YYYY-MM-DD)",
+               self.input)
+    }
+}
+
+impl std::error::Error for ParseTimeError {}
+
+#[cfg(test)]
+mod test_time {
+    use crate::time::*;
+
+    #[test]
+    fn test_date() {
+        let d : Date = "2000-02-29".parse().unwrap();
+        assert_eq!((d.year(), d.month(), d.day()), (2000, 2, 29));
+        assert_eq!(d.to_string(), "2000-02-29");
+        assert_eq!(Date::from_days(0).to_string(), "1970-01-01");
+        assert_eq!(d.add_days(1).to_string(), "2000-03-01");
+        assert_eq!(d.days_until("2001-02-28".parse().unwrap()), 365);
+        assert_eq!(d.add_days(i64::MAX), Date::from_days(i32::MAX));
+        assert_eq!(d.add_days(i64::MIN), Date::from_days(i32::MIN));
+        assert_eq!(d.add_days(1 << 40).year(), Date::from_days(i32::MAX).year());
+        assert_eq!(Date::from_days(i32::MAX).to_string(), "+5881580-07-11");
+        assert_eq!(Date::from_days(i32::MIN).to_string(), "-5877641-06-23");
+
+        assert!("1900-02-29".parse::<Date>().is_err());
+        assert!("2001-5-01".parse::<Date>().is_err());
+        assert!("2001-13-01".parse::<Date>().is_err());
+        assert!("2001-01-0a".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn test_date_bounds() {
+        let first = Date::from_ymd(0, 1, 1).unwrap();
+        let last = Date::from_ymd(9999, 12, 31).unwrap();
+        assert_eq!(first.to_string(), "0000-01-01");
+        assert_eq!(last.to_string(), "9999-12-31");
+        assert_eq!(first.add_days(-1).to_string(), "-0001-12-31");
+        assert_eq!(last.add_days(1).to_string(), "+10000-01-01");
+
+        let dates = [Date::from_days(i32::MIN), Date::from_days(i32::MIN + 1), first.add_days(-1), first,
+                     last, last.add_days(1), Date::from_days(i32::MAX - 1), Date::from_days(i32::MAX)];
+        for d in dates.iter() {
+            assert_eq!(d.to_string().parse::<Date>(), Ok(*d));
+            let json = serde_json::to_string(d).unwrap();
+            assert_eq!(serde_json::from_str::<Date>(&json).unwrap(), *d);
+        }
+
+        assert!("+5881580-07-12".parse::<Date>().is_err());
+        assert!("-5877641-06-22".parse::<Date>().is_err());
+        assert!("+99999999999-01-01".parse::<Date>().is_err());
+        assert!("10000-01-01".parse::<Date>().is_err());
+        assert!("+100-01-01".parse::<Date>().is_err());
+        assert!(Date::from_ymd(i32::MAX, 1, 1).is_none());
+    }
+
+    #[test]
+    fn test_event_time() {
+        let a : EventTime = "2001-05-01".parse().unwrap();
+        let b : EventTime = "2001-12-31".parse().unwrap();
+        let i : EventTime = "-3".parse().unwrap();
+
+        assert!(a < b);
+        assert_eq!(a.days_until(&b), Some(244));
+        assert_eq!(i.days_until(&EventTime::Offset(4)), Some(7));
+        assert_eq!(a.partial_cmp(&i), None);
+        assert_eq!(a.days_until(&i), None);
+        let (min, max) = (EventTime::Offset(i64::MIN), EventTime::Offset(i64::MAX));
+        assert_eq!(min.days_until(&max), Some(i64::MAX));
+        assert_eq!(max.days_until(&min), Some(i64::MIN));
+        assert_eq!(i.days_until(&max), Some(i64::MAX));
+        assert_eq!(i.total_cmp(&a), Ordering::Less);
+        assert_eq!(i.add_days(i64::MIN), EventTime::Offset(i64::MIN));
+        assert_eq!(EventTime::Offset(5).add_days(i64::MAX), EventTime::Offset(i64::MAX));
+        assert_eq!(a.add_days(-(1 << 40)), EventTime::Date(Date::from_days(i32::MIN)));
+    }
+
+    #[test]
+    fn test_serde() {
+        use serde_json::{from_str, to_string};
+
+        let json = "[\"2001-05-01\",0,null,-12]";
+        let times : Vec<Option<EventTime>> = from_str(json).unwrap();
+        assert_eq!(times[0], Some(EventTime::Date(Date::from_ymd(2001, 5, 1).unwrap())));
+        assert_eq!(times[2], None);
+        assert_eq!(json, to_string(&times).unwrap());
+
+        assert!(from_str::<EventTime>("\"May 1, 2001\"").is_err());
+        assert!(from_str::<EventTime>("1.5").is_err());
+    }
+}
@@ -0,0 +1,450 @@
+//! Events grouped by subject into timelines.
+//!
+//! A [`Timeline`] holds all events of one subject, sorted by
+//! [`compare_events`]. Timelines are built from a stream of events in one of
+//! three ways:
+//!
+//! * [`group_sorted`] groups an iterator of events whose subjects are already
+//!   contiguous (e.g. sorted by patient), holding one timeline at a time.
+//! * [`timelines`] does the same as the output of a
+//!   [`process`](crate::process) pipeline.
+//! * [`group_unsorted`] accepts events in any order. It buffers them in
+//!   memory and, past [`SpillOptions::max_events`], spills them to temporary
+//!   files partitioned by subject, which are then grouped one at a time.
+//!
+//! Example:
+//! ```
+//! use eddeserus::timeline::*;
+//! use eddeserus::types::Event;
+//!
+//! let json = "\
+//!     [\"abc\",5,null,\"Death\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":5,\"end\":null},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n\
+//!     [\"abc\",0,9,\"Enrollment\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":9},\
+//!       \"domain\":\"Enrollment\",\"facts\":{}}]\n";
+//!
+//! let events = json.lines().map(|l| serde_json::from_str::<Event>(l).unwrap());
+//! let timelines : Vec<Timeline> = group_sorted(events).collect::<Result<_, _>>().unwrap();
+//! assert_eq!(timelines.len(), 1);
+//! assert_eq!(timelines[0].events[0].d, "Enrollment");
+//! ```
+
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+use crate::process::OutHandler;
+use crate::types::{Event, EventTime, SubjectID};
+
+/// The order of events in a timeline: by begin, then by end, then by domain.
+///
+/// Times are compared by [`EventTime::total_cmp`](crate::types::EventTime::total_cmp),
+/// and an open end (`None`) comes after every closed end.
+pub fn compare_events(a: &Event, b: &Event) -> Ordering {
+    a.b.total_cmp(&b.b)
+        .then_with(|| compare_ends(&a.e, &b.e))
+        .then_with(|| a.d.cmp(&b.d))
+}
+
+/// Compares ends, with an open end after every closed end.
+pub(crate) fn compare_ends(a: &Option<EventTime>, b: &Option<EventTime>) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => x.total_cmp(y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// All events of one subject.
+#[derive(Debug, Clone)]
+pub struct Timeline<'a> {
+    pub subject: SubjectID<'a>,
+    pub events: Vec<Event<'a>>,
+}
+
+impl<'a> Timeline<'a> {
+    pub fn new(subject: SubjectID<'a>) -> Self {
+        Timeline { subject, events: Vec::new() }
+    }
+
+    /// Sorts the events by [`compare_events`], keeping events that compare
+    /// equal in their original order.
+    pub fn sort(&mut self) {
+        self.events.sort_by(compare_events);
+    }
+
+    pub fn into_owned(self) -> Timeline<'static> {
+        Timeline {
+            subject: self.subject.into_owned(),
+            events: self.events.into_iter().map(Event::into_owned).collect(),
+        }
+    }
+}
+
+/// A subject whose events are not contiguous in input that should be grouped
+/// by subject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsorted {
+    pub subject: String,
+}
+
+impl fmt::Display for Unsorted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "events of subject {} are not contiguous", self.subject)
+    }
+}
+
+impl std::error::Error for Unsorted {}
+
+/*----------------------------------------------------------------------------*/
+// Grouping sorted input
+
+/// Groups events whose subjects are contiguous into sorted timelines.
+///
+/// If a subject's events appear in more than one run, the second run is
+/// yielded as an [`Unsorted`] error and grouping goes on.
+pub fn group_sorted<'a, I>(events: I) -> GroupSorted<'a, I::IntoIter>
+where I: IntoIterator<Item = Event<'a>>
+{
+    GroupSorted { events: events.into_iter().peekable(), seen: HashSet::new() }
+}
+
+/// An iterator of timelines. See [`group_sorted`].
+pub struct GroupSorted<'a, I: Iterator<Item = Event<'a>>> {
+    events: Peekable<I>,
+    seen: HashSet<SubjectID<'a>>,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Iterator for GroupSorted<'a, I> {
+    type Item = Result<Timeline<'a>, Unsorted>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.events.next()?;
+        let mut timeline = Timeline::new(first.p.clone());
+        timeline.events.push(first);
+        while let Some(event) = self.events.next_if(|e| e.p == timeline.subject) {
+            timeline.events.push(event);
+        }
+        timeline.sort();
+
+        if self.seen.insert(timeline.subject.clone()) {
+            Some(Ok(timeline))
+        } else {
+            Some(Err(Unsorted { subject: timeline.subject.to_string() }))
+        }
+    }
+}
+
+/// Groups the output of a pipeline into timelines, passing each to a
+/// function. See [`timelines`].
+pub struct Timelines<F> {
+    current: Option<Timeline<'static>>,
+    seen: HashSet<SubjectID<'static>>,
+    f: F,
+}
+
+/// Creates an output handler that groups events whose subjects are contiguous
+/// into sorted timelines and passes each to `f`.
+///
+/// A subject whose events appear in more than one run stops processing with
+/// an `InvalidData` error wrapping [`Unsorted`].
+pub fn timelines<F>(f: F) -> Timelines<F>
+where F: FnMut(Timeline<'static>) -> io::Result<()>
+{
+    Timelines { current: None, seen: HashSet::new(), f }
+}
+
+impl<F> Timelines<F>
+where F: FnMut(Timeline<'static>) -> io::Result<()>
+{
+    fn emit(&mut self) -> io::Result<()> {
+        let mut timeline = match self.current.take() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+        if !self.seen.insert(timeline.subject.clone()) {
+            let error = Unsorted { subject: timeline.subject.to_string() };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+        }
+        timeline.sort();
+        (self.f)(timeline)
+    }
+}
+
+impl<'a, F> OutHandler<'a> for Timelines<F>
+where F: FnMut(Timeline<'static>) -> io::Result<()>
+{
+    fn write_event(&mut self, event: Event<'a>) -> io::Result<()> {
+        match &mut self.current {
+            Some(t) if t.subject == event.p => t.events.push(event.into_owned()),
+            _ => {
+                self.emit()?;
+                let mut timeline = Timeline::new(event.p.clone().into_owned());
+                timeline.events.push(event.into_owned());
+                self.current = Some(timeline);
+            },
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.emit()
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Grouping unsorted input
+
+/// Settings for [`group_unsorted`].
+#[derive(Debug, Clone)]
+pub struct SpillOptions {
+    /// Most events to hold in memory before spilling to files. Defaults to
+    /// one million.
+    pub max_events: usize,
+    /// Number of files to spill to. Each is read back whole, so it should
+    /// hold at most about `max_events` events. Defaults to 64.
+    pub partitions: usize,
+    /// Directory for the files. Defaults to the system's temporary directory.
+    pub dir: PathBuf,
+}
+
+impl Default for SpillOptions {
+    fn default() -> Self {
+        SpillOptions {
+            max_events: 1_000_000,
+            partitions: 64,
+            dir: std::env::temp_dir(),
+        }
+    }
+}
+
+/// Groups events in any order into sorted timelines.
+///
+/// Timelines held in memory come in order of subject. After a spill, they
+/// come in order of subject within each file; the files are removed as they
+/// are read, or when the iterator is dropped.
+pub fn group_unsorted<'a, I>(events: I, options: &SpillOptions) -> io::Result<GroupUnsorted<'a>>
+where I: IntoIterator<Item = Event<'a>>
+{
+    let mut buffer : BTreeMap<SubjectID<'a>, Vec<Event<'a>>> = BTreeMap::new();
+    let mut events = events.into_iter();
+    let mut n = 0;
+
+    for event in &mut events {
+        buffer.entry(event.p.clone()).or_default().push(event);
+        n += 1;
+        if n > options.max_events {
+            let mut spill = Spill::create(options)?;
+            for event in buffer.into_values().flatten().chain(events) {
+                spill.write(&event)?;
+            }
+            spill.finish()?;
+            return Ok(GroupUnsorted { current: Vec::new().into_iter(), spill });
+        }
+    }
+
+    let timelines : Vec<Timeline<'a>> = buffer.into_iter()
+        .map(|(subject, events)| {
+            let mut timeline = Timeline { subject, events };
+            timeline.sort();
+            timeline
+        })
+        .collect();
+    Ok(GroupUnsorted { current: timelines.into_iter(), spill: Spill::default() })
+}
+
+/// An iterator of timelines. See [`group_unsorted`].
+pub struct GroupUnsorted<'a> {
+    current: std::vec::IntoIter<Timeline<'a>>,
+    spill: Spill,
+}
+
+impl<'a> Iterator for GroupUnsorted<'a> {
+    type Item = io::Result<Timeline<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(timeline) = self.current.next() {
+                return Some(Ok(timeline));
+            }
+            match self.spill.read_next()? {
+                Ok(timelines) => self.current = timelines.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Files of spilled events, partitioned by subject.
+#[derive(Default)]
+struct Spill {
+    paths: Vec<PathBuf>,
+    writers: Vec<BufWriter<File>>,
+}
+
+/// Distinguishes the spills of one process.
+static SPILLS : AtomicUsize = AtomicUsize::new(0);
+
+impl Spill {
+    fn create(options: &SpillOptions) -> io::Result<Spill> {
+        let id = SPILLS.fetch_add(1, AtomicOrdering::Relaxed);
+        let mut spill = Spill::default();
+        for i in 0..options.partitions.max(1) {
+            let path = options.dir.join(
+                format!("eddeserus-spill-{}-{}-{}.json", std::process::id(), id, i));
+            let file = File::create(&path)?;
+            spill.paths.push(path);
+            spill.writers.push(BufWriter::new(file));
+        }
+        Ok(spill)
+    }
+
+    fn write(&mut self, event: &Event) -> io::Result<()> {
+        let mut hasher = DefaultHasher::new();
+        event.p.hash(&mut hasher);
+        let i = hasher.finish() as usize % self.writers.len();
+        let writer = &mut self.writers[i];
+        serde_json::to_writer(&mut *writer, event)?;
+        writer.write_all(b"\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        for mut writer in self.writers.drain(..) {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reads and removes the next file, grouping its events into timelines.
+    fn read_next(&mut self) -> Option<io::Result<Vec<Timeline<'static>>>> {
+        let path = self.paths.pop()?;
+        let result = fs::read_to_string(&path).and_then(|json| {
+            let mut events = Vec::new();
+            for line in json.lines() {
+                let event : Event = serde_json::from_str(line)?;
+                events.push(event.into_owned());
+            }
+            let mut timelines = Vec::new();
+            for timeline in group_unsorted(events, &SpillOptions { max_events: usize::MAX,
+                                                                   ..SpillOptions::default() })? {
+                timelines.push(timeline?);
+            }
+            Ok(timelines)
+        });
+        let _ = fs::remove_file(&path);
+        Some(result)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        self.writers.clear();
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_timeline {
+    use std::cmp::Ordering;
+
+    use crate::process::*;
+    use crate::timeline::*;
+    use crate::types::*;
+
+    fn event(p: &str, b: i64, e: Option<i64>, d: &str) -> OwnedEvent {
+        crate::test_support::event(p, b, e, d, &[], "{}")
+    }
+
+    fn shape(t: &Timeline) -> (String, Vec<(i64, Option<i64>, String)>) {
+        (t.subject.to_string(),
+         t.events.iter()
+             .map(|e| (e.b.as_offset().unwrap(), e.e.and_then(|e| e.as_offset()), e.d.to_string()))
+             .collect())
+    }
+
+    #[test]
+    fn test_compare_events() {
+        let a = event("x", 0, Some(5), "Enrollment");
+        assert_eq!(compare_events(&a, &event("x", 1, Some(2), "Death")), Ordering::Less);
+        assert_eq!(compare_events(&a, &event("x", 0, Some(6), "Death")), Ordering::Less);
+        assert_eq!(compare_events(&a, &event("x", 0, None, "Death")), Ordering::Less);
+        assert_eq!(compare_events(&a, &event("x", 0, Some(5), "Death")), Ordering::Greater);
+        assert_eq!(compare_events(&a, &a), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_group_sorted() {
+        let events = vec![
+            event("a", 3, None, "Death"),
+            event("a", 0, Some(9), "Enrollment"),
+            event("b", 1, Some(2), "Enrollment"),
+            event("a", 4, None, "Death"),
+        ];
+        let groups : Vec<_> = group_sorted(events).collect();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(shape(groups[0].as_ref().unwrap()),
+                   ("a".to_string(), vec![(0, Some(9), "Enrollment".to_string()),
+                                          (3, None, "Death".to_string())]));
+        assert_eq!(groups[1].as_ref().unwrap().events.len(), 1);
+        assert_eq!(groups[2].as_ref().unwrap_err(), &Unsorted { subject: "a".to_string() });
+    }
+
+    #[test]
+    fn test_timelines_out() {
+        let json : String = [event("a", 2, None, "Death"),
+                             event("a", 1, None, "Death"),
+                             event("b", 0, None, "Death")]
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap() + "\n")
+            .collect();
+
+        let mut subjects = Vec::new();
+        let mut out = timelines(|t| {
+            subjects.push((t.subject.to_string(), t.events[0].b));
+            Ok(())
+        });
+        process_str(&json, &mut map(|e| e), &mut out).unwrap();
+        assert_eq!(subjects, vec![("a".to_string(), EventTime::Offset(1)),
+                                  ("b".to_string(), EventTime::Offset(0))]);
+
+        let unsorted = json.clone() + &serde_json::to_string(&event("a", 0, None, "Death")).unwrap();
+        let mut out = timelines(|_| Ok(()));
+        let error = process_str(&unsorted, &mut map(|e| e), &mut out).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_group_unsorted() {
+        let events : Vec<OwnedEvent> = (0..100)
+            .map(|i| event(&format!("p{}", i % 7), 100 - i, None, "Death"))
+            .collect();
+
+        let in_memory : Vec<_> = group_unsorted(events.clone(), &SpillOptions::default())
+            .unwrap()
+            .map(|t| shape(&t.unwrap()))
+            .collect();
+        assert_eq!(in_memory.len(), 7);
+        assert_eq!(in_memory[0].0, "p0");
+        assert!(in_memory.iter().all(|(_, es)| es.windows(2).all(|w| w[0].0 < w[1].0)));
+
+        let options = SpillOptions { max_events: 10, partitions: 3, ..SpillOptions::default() };
+        let mut spilled : Vec<_> = group_unsorted(events, &options)
+            .unwrap()
+            .map(|t| shape(&t.unwrap()))
+            .collect();
+        spilled.sort();
+        assert_eq!(spilled, in_memory);
+    }
+}
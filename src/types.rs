@@ -1,42 +1,142 @@
 //! The Rust internal representations of NoviSci EDM data types.
 
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use serde_json::value::RawValue;
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_tuple::*;
 
+pub use crate::time::{Date, EventTime, ParseTimeError};
+
 /*----------------------------------------------------------------------------*/
 /// Shared types
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum Interval {
     IntervalInt { begin : u64,    end : Option<u64> },
     IntervalStr { begin : String, end : Option<String> },
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Interval {
+    /// The begin of the interval as an `EventTime`.
+    pub fn begin(&self) -> Result<EventTime, ParseTimeError> {
+        match self {
+            Interval::IntervalInt { begin, .. } => Ok(EventTime::Offset(*begin as i64)),
+            Interval::IntervalStr { begin, .. } => begin.parse(),
+        }
+    }
+
+    /// The end of the interval as an `EventTime`, if there is one.
+    pub fn end(&self) -> Result<Option<EventTime>, ParseTimeError> {
+        match self {
+            Interval::IntervalInt { end, .. } => Ok(end.map(|e| EventTime::Offset(e as i64))),
+            Interval::IntervalStr { end, .. } => end.as_deref().map(str::parse).transpose(),
+        }
+    }
+
+    /// An interval from `b` to `e`: offsets become `IntervalInt` and dates
+    /// `IntervalStr`. `None` if an offset is negative or dates are mixed with
+    /// offsets, which neither can hold.
+    pub fn from_times(b: EventTime, e: Option<EventTime>) -> Option<Interval> {
+        let offset = |t: i64| u64::try_from(t).ok();
+        match (b, e) {
+            (EventTime::Offset(b), None) =>
+                Some(Interval::IntervalInt { begin: offset(b)?, end: None }),
+            (EventTime::Offset(b), Some(EventTime::Offset(e))) =>
+                Some(Interval::IntervalInt { begin: offset(b)?, end: Some(offset(e)?) }),
+            (EventTime::Date(b), None) =>
+                Some(Interval::IntervalStr { begin: b.to_string(), end: None }),
+            (EventTime::Date(b), Some(EventTime::Date(e))) =>
+                Some(Interval::IntervalStr { begin: b.to_string(), end: Some(e.to_string()) }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_interval {
+    use crate::types::*;
+
+    #[test]
+    fn test_from_times() {
+        let json = |b, e| Interval::from_times(b, e).map(|i| serde_json::to_string(&i).unwrap());
+        let date = EventTime::Date(Date::from_ymd(2010, 1, 2).unwrap());
+        let t = EventTime::Offset;
+        assert_eq!(json(t(5), Some(t(9))).unwrap(), "{\"begin\":5,\"end\":9}");
+        assert_eq!(json(t(5), None).unwrap(), "{\"begin\":5,\"end\":null}");
+        assert_eq!(json(date, Some(date)).unwrap(), "{\"begin\":\"2010-01-02\",\"end\":\"2010-01-02\"}");
+        assert_eq!(json(date, None).unwrap(), "{\"begin\":\"2010-01-02\",\"end\":null}");
+        assert_eq!(json(t(5), Some(t(-3))), None);
+        assert_eq!(json(t(-5), Some(t(3))), None);
+        assert_eq!(json(t(-5), None), None);
+        assert_eq!(json(t(5), Some(date)), None);
+        assert_eq!(json(date, Some(t(5))), None);
+    }
+}
+
+/// Subject IDs compare by kind and then by value, so `"123"` and `123` are
+/// different subjects, and all string IDs order before all integer IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum SubjectID<'a> {
-    IDstr(&'a str),
+    IDstr(#[serde(borrow)] Cow<'a, str>),
     Idint(u64),
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq)]
+impl SubjectID<'_> {
+    pub fn into_owned(self) -> SubjectID<'static> {
+        match self {
+            SubjectID::IDstr(s) => SubjectID::IDstr(own(s)),
+            SubjectID::Idint(i) => SubjectID::Idint(i),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> SubjectID<'_> {
+        match self {
+            SubjectID::IDstr(s) => SubjectID::IDstr(Cow::Borrowed(s)),
+            SubjectID::Idint(i) => SubjectID::Idint(*i),
+        }
+    }
+}
+
+impl std::fmt::Display for SubjectID<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SubjectID::IDstr(s) => f.write_str(s),
+            SubjectID::Idint(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub enum Location {
     Unknown,
     Inpatient,
     Outpatient,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct Code<'a> {
-    pub code : &'a str,
+    #[serde(borrow)]
+    pub code : Cow<'a, str>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     // pub codebook : Option<&'a str>
     pub codebook : Option<Codebook>
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Hash)]
+impl Code<'_> {
+    pub fn into_owned(self) -> Code<'static> {
+        Code { code: own(self.code), codebook: self.codebook }
+    }
+
+    pub fn as_borrowed(&self) -> Code<'_> {
+        Code { code: Cow::Borrowed(&self.code), codebook: self.codebook }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub enum Codebook {
     CDT,
     CPT,
@@ -106,9 +206,10 @@ mod test_code {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Claim<'a> {
-    pub id:  &'a str,
+    #[serde(borrow)]
+    pub id:  Cow<'a, str>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub r#type: Option<String>,
@@ -120,23 +221,62 @@ pub struct Claim<'a> {
     pub procedure:  Option<String>,
 }
 
+impl Claim<'_> {
+    pub fn into_owned(self) -> Claim<'static> {
+        Claim { id: own(self.id), ..self }
+    }
+
+    pub fn as_borrowed(&self) -> Claim<'_> {
+        Claim {
+            id: Cow::Borrowed(&self.id),
+            r#type: self.r#type.clone(),
+            index: self.index,
+            procedure: self.procedure.clone(),
+        }
+    }
+}
+
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Cost<'a> {
 
+    #[serde(borrow)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub charge: Option<&'a str>,
+    pub charge: Option<Cow<'a, str>>,
 
-    pub cost: &'a str, //TODO: EDM type is <Text | Double>
+    #[serde(borrow)]
+    pub cost: Cow<'a, str>, //TODO: EDM type is <Text | Double>
 
+    #[serde(borrow)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub allowed:  Option<&'a str>, //TODO: EDM type is Optional <Text | Double>
+    pub allowed:  Option<Cow<'a, str>>, //TODO: EDM type is Optional <Text | Double>
 
+    #[serde(borrow)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub transaction:  Option<&'a str>,
+    pub transaction:  Option<Cow<'a, str>>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl Cost<'_> {
+    pub fn into_owned(self) -> Cost<'static> {
+        Cost {
+            charge: self.charge.map(own),
+            cost: own(self.cost),
+            allowed: self.allowed.map(own),
+            transaction: self.transaction.map(own),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> Cost<'_> {
+        Cost {
+            charge: borrow_opt(&self.charge),
+            cost: Cow::Borrowed(&self.cost),
+            allowed: borrow_opt(&self.allowed),
+            transaction: borrow_opt(&self.transaction),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Fill<'a> {
 
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -145,23 +285,141 @@ pub struct Fill<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub quantity: Option<i32>,
 
+    #[serde(borrow)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub strength:  Option<&'a str>,
+    pub strength:  Option<Cow<'a, str>>,
+}
+
+impl Fill<'_> {
+    pub fn into_owned(self) -> Fill<'static> {
+        Fill { strength: self.strength.map(own), ..self }
+    }
+
+    pub fn as_borrowed(&self) -> Fill<'_> {
+        Fill {
+            days_supply: self.days_supply,
+            quantity: self.quantity,
+            strength: borrow_opt(&self.strength),
+        }
+    }
+}
+
+/*----------------------------------------------------------------------------*/
+// Owned and borrowed data
+//
+// Strings are `Cow<'a, str>` and raw JSON is `Cow<'a, RawValue>`. Deserializing
+// borrows from the input wherever possible; `into_owned` copies the borrowed
+// data so that the result (e.g. an `OwnedEvent`) no longer depends on the
+// input, and `as_borrowed` gives a view of an owned value without copying
+// strings or raw JSON.
+
+fn own(x: Cow<'_, str>) -> Cow<'static, str> {
+    Cow::Owned(x.into_owned())
+}
+
+fn borrow_opt<'a>(x: &'a Option<Cow<'_, str>>) -> Option<Cow<'a, str>> {
+    x.as_deref().map(Cow::Borrowed)
+}
+
+fn own_raw(x: Cow<'_, RawValue>) -> Cow<'static, RawValue> {
+    Cow::Owned(x.into_owned())
+}
+
+/// Deserializes raw JSON by borrowing it from the input.
+fn borrow_raw_opt<'de: 'a, 'a, D>(deserializer: D)
+                                 -> Result<Option<Cow<'a, RawValue>>, D::Error>
+where D: Deserializer<'de>
+{
+    <Option<&'a RawValue>>::deserialize(deserializer)
+        .map(|x| x.map(Cow::Borrowed))
 }
 
 
 /*----------------------------------------------------------------------------*/
 // [`Event`](https://docs.novisci.com/schema/event-data-model/1.0/#event-schema)
-#[derive(Debug, Deserialize, Serialize_tuple)]
+//
+// Events serialize as arrays (`[p,b,e,d,concepts,context]`), or as objects
+// through `Event::keyed`. Either form deserializes.
+#[derive(Debug, Clone, Deserialize, Serialize_tuple)]
 pub struct Event<'a> {
+    #[serde(borrow, alias = "patient")]
     pub p : SubjectID<'a>,
-    pub b : &'a RawValue,
-    pub e : &'a RawValue,
-    pub d : &'a str,
+    #[serde(alias = "begin")]
+    pub b : EventTime,
+    #[serde(alias = "end")]
+    pub e : Option<EventTime>,
+    #[serde(borrow, alias = "domain")]
+    pub d : Cow<'a, str>,
     pub concepts : Vec<String>,
+    #[serde(borrow)]
     pub context : Context<'a>,
 }
 
+/// An `Event` that owns all of its data.
+///
+/// Events deserialize by borrowing from their input; `Event::into_owned` or
+/// `Event::to_owned` converts one to an `OwnedEvent`, which can outlive the
+/// input and be sent across threads. An `&OwnedEvent` can be used wherever an
+/// `&Event` is expected.
+pub type OwnedEvent = Event<'static>;
+
+impl Event<'_> {
+    /// Converts to an `OwnedEvent`, copying only the data that is borrowed.
+    pub fn into_owned(self) -> OwnedEvent {
+        Event {
+            p: self.p.into_owned(),
+            b: self.b,
+            e: self.e,
+            d: own(self.d),
+            concepts: self.concepts,
+            context: self.context.into_owned(),
+        }
+    }
+
+    /// Copies to an `OwnedEvent`.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn to_owned(&self) -> OwnedEvent {
+        self.as_borrowed().into_owned()
+    }
+
+    /// Borrows the strings and raw JSON of this event.
+    pub fn as_borrowed(&self) -> Event<'_> {
+        Event {
+            p: self.p.as_borrowed(),
+            b: self.b,
+            e: self.e,
+            d: Cow::Borrowed(&self.d),
+            concepts: self.concepts.clone(),
+            context: self.context.as_borrowed(),
+        }
+    }
+}
+
+impl<'a> Event<'a> {
+    /// This event in object form. See [`Keyed`].
+    pub fn keyed(&self) -> Keyed<'_, 'a> {
+        Keyed(self)
+    }
+}
+
+/// Serializes an event as an object with the keys `patient`, `begin`, `end`,
+/// `domain`, `concepts` and `context`, rather than as an array.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyed<'e, 'a>(pub &'e Event<'a>);
+
+impl Serialize for Keyed<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Event", 6)?;
+        s.serialize_field("patient", &self.0.p)?;
+        s.serialize_field("begin", &self.0.b)?;
+        s.serialize_field("end", &self.0.e)?;
+        s.serialize_field("domain", &self.0.d)?;
+        s.serialize_field("concepts", &self.0.concepts)?;
+        s.serialize_field("context", &self.0.context)?;
+        s.end()
+    }
+}
+
 
 #[cfg(test)]
 mod test_events {
@@ -186,13 +444,96 @@ mod test_events {
         println!("Demographics event\n{:?}\n", &evnt);
         assert_eq!(json, to_string(&evnt.unwrap()).unwrap());
     }
+
+    #[test]
+    fn test_times() {
+        use serde_json::{from_str, to_string};
+
+        let json = include_str!("../resources/50events.json");
+        for line in json.lines() {
+            let evnt : Event = from_str(line).unwrap();
+            assert_eq!(evnt.b, EventTime::Date(Date::from_ymd(2001, 5, 1).unwrap()));
+            assert_eq!(evnt.b.days_until(&evnt.e.unwrap()), Some(244));
+            assert_eq!(evnt.context.time.begin(), Ok(evnt.b));
+            // (the context's keys are reordered, so compare only the header)
+            let header = &line[..line.find('{').unwrap()];
+            assert!(to_string(&evnt).unwrap().starts_with(header));
+        }
+    }
+
+    #[test]
+    fn test_owned() {
+        use serde_json::{from_str, to_string};
+
+        let json = "[\
+        \"xyz\",\"2010-01-01\",null,\"Medication\",[\"c\"],\
+        {\
+         \"patient_id\":\"xyz\",\
+         \"time\":{\"begin\":0,\"end\":1},\
+         \"domain\":\"Medication\",\
+         \"facts\":{\"code\":{\"code\":\"A\\\"21\"},\
+                    \"fill\":{\"days_supply\":30,\"strength\":\"5mg\"},\
+                    \"claim\":{\"id\":\"c1\"}},\
+         \"source\":{\"table\":\"somewhere\"}\
+        }]".to_string();
+
+        let owned : OwnedEvent = {
+            let input = json.clone();
+            let evnt : Event = from_str(&input).unwrap();
+            evnt.into_owned()
+        };
+        fn is_send<T: Send + 'static>(_: &T) {}
+        is_send(&owned);
+
+        assert_eq!(json, to_string(&owned).unwrap());
+        assert_eq!(json, to_string(&owned.as_borrowed()).unwrap());
+        assert_eq!(json, to_string(&owned.to_owned()).unwrap());
+    }
+
+    #[test]
+    fn test_keyed() {
+        use serde_json::{from_str, to_string};
+
+        let json = "[\
+        \"xyz\",\"2010-01-01\",null,\"Diagnosis\",[\"c\"],\
+        {\
+         \"patient_id\":\"xyz\",\
+         \"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+         \"domain\":\"Diagnosis\",\
+         \"facts\":{\"code\":{\"code\":\"I10\",\"codebook\":\"ICD10\"}},\
+         \"misc\":{\"k\": 1}\
+        }]";
+        let keyed = "{\
+        \"patient\":\"xyz\",\"begin\":\"2010-01-01\",\"end\":null,\"domain\":\"Diagnosis\",\
+        \"concepts\":[\"c\"],\
+        \"context\":{\
+         \"patient_id\":\"xyz\",\
+         \"time\":{\"begin\":\"2010-01-01\",\"end\":null},\
+         \"domain\":\"Diagnosis\",\
+         \"facts\":{\"code\":{\"code\":\"I10\",\"codebook\":\"ICD10\"}},\
+         \"misc\":{\"k\": 1}\
+        }}";
+
+        let evnt : Event = from_str(json).unwrap();
+        assert_eq!(to_string(&evnt.keyed()).unwrap(), keyed);
+        let evnt : Event = from_str(keyed).unwrap();
+        assert_eq!(to_string(&evnt).unwrap(), json);
+
+        // Keys may come in any order, and a missing end is null.
+        let evnt : Event = from_str("{\"context\":{\"patient_id\":7,\"time\":{\"begin\":3,\"end\":null},\
+            \"domain\":\"Death\",\"facts\":{}},\"concepts\":[],\"domain\":\"Death\",\
+            \"begin\":3,\"patient\":7}").unwrap();
+        assert_eq!(to_string(&evnt).unwrap(), "[7,3,null,\"Death\",[],\
+            {\"patient_id\":7,\"time\":{\"begin\":3,\"end\":null},\"domain\":\"Death\",\"facts\":{}}]");
+        assert!(from_str::<Event>("{\"patient\":7,\"begin\":3}").is_err());
+    }
 }
 
 
 /*----------------------------------------------------------------------------*/
 // Contexts
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Context<'a> {
     #[serde(bound(deserialize = "SubjectID<'a>: Deserialize<'de>"))]
     pub patient_id : SubjectID<'a>,
@@ -202,17 +543,39 @@ pub struct Context<'a> {
     #[serde(flatten)]
     pub facts : Domain<'a>,
 
-    #[serde(bound(deserialize = "&'a RawValue: Deserialize<'de>"))]
+    #[serde(borrow, default, deserialize_with = "borrow_raw_opt")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub source: Option<&'a RawValue>,
+    pub source: Option<Cow<'a, RawValue>>,
 
-    #[serde(bound(deserialize = "&'a RawValue: Deserialize<'de>"))]
+    #[serde(borrow, default, deserialize_with = "borrow_raw_opt")]
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub misc: Option<&'a RawValue>,
+    pub misc: Option<Cow<'a, RawValue>>,
+}
+
+impl Context<'_> {
+    pub fn into_owned(self) -> Context<'static> {
+        Context {
+            patient_id: self.patient_id.into_owned(),
+            time: self.time,
+            facts: self.facts.into_owned(),
+            source: self.source.map(own_raw),
+            misc: self.misc.map(own_raw),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> Context<'_> {
+        Context {
+            patient_id: self.patient_id.as_borrowed(),
+            time: self.time.clone(),
+            facts: self.facts.as_borrowed(),
+            source: self.source.as_deref().map(Cow::Borrowed),
+            misc: self.misc.as_deref().map(Cow::Borrowed),
+        }
+    }
 }
 
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "domain", content = "facts")]
 pub enum Domain<'a> {
     #[serde(bound(deserialize = "Claim<'a>: Deserialize<'de>, Cost<'a>: Deserialize<'de>"))]
@@ -248,8 +611,79 @@ pub enum Domain<'a> {
 
 }
 
+impl<'a> Domain<'a> {
+    /// The code of a Diagnosis, Labs, Medication or Procedure event.
+    pub fn code(&self) -> Option<&Code<'a>> {
+        match self {
+            Domain::Diagnosis(x)  => Some(&x.code),
+            Domain::Labs(x)       => Some(&x.code),
+            Domain::Medication(x) => Some(&x.code),
+            Domain::Procedure(x)  => Some(&x.code),
+            _ => None,
+        }
+    }
+
+    pub fn code_mut(&mut self) -> Option<&mut Code<'a>> {
+        match self {
+            Domain::Diagnosis(x)  => Some(&mut x.code),
+            Domain::Labs(x)       => Some(&mut x.code),
+            Domain::Medication(x) => Some(&mut x.code),
+            Domain::Procedure(x)  => Some(&mut x.code),
+            _ => None,
+        }
+    }
+}
+
+impl Domain<'_> {
+    /// The name of the domain, as written in the `domain` tag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Domain::Claim(_)        => "Claim",
+            Domain::Death(_)        => "Death",
+            Domain::Demographics(_) => "Demographics",
+            Domain::Diagnosis(_)    => "Diagnosis",
+            Domain::Eligibility(_)  => "Eligibility",
+            Domain::Enrollment(_)   => "Enrollment",
+            Domain::Labs(_)         => "Labs",
+            Domain::Medication(_)   => "Medication",
+            Domain::Procedure(_)    => "Procedure",
+            Domain::Undefined(_)    => "Undefined",
+        }
+    }
+
+    pub fn into_owned(self) -> Domain<'static> {
+        match self {
+            Domain::Claim(x)        => Domain::Claim(x.into_owned()),
+            Domain::Death(x)        => Domain::Death(x),
+            Domain::Demographics(x) => Domain::Demographics(x),
+            Domain::Diagnosis(x)    => Domain::Diagnosis(x.into_owned()),
+            Domain::Eligibility(x)  => Domain::Eligibility(x),
+            Domain::Enrollment(x)   => Domain::Enrollment(x),
+            Domain::Labs(x)         => Domain::Labs(x.into_owned()),
+            Domain::Medication(x)   => Domain::Medication(x.into_owned()),
+            Domain::Procedure(x)    => Domain::Procedure(x.into_owned()),
+            Domain::Undefined(x)    => Domain::Undefined(x),
+        }
+    }
 
-#[derive(Debug, Deserialize, Serialize)]
+    pub fn as_borrowed(&self) -> Domain<'_> {
+        match self {
+            Domain::Claim(x)        => Domain::Claim(x.as_borrowed()),
+            Domain::Death(x)        => Domain::Death(x.clone()),
+            Domain::Demographics(x) => Domain::Demographics(x.clone()),
+            Domain::Diagnosis(x)    => Domain::Diagnosis(x.as_borrowed()),
+            Domain::Eligibility(x)  => Domain::Eligibility(x.clone()),
+            Domain::Enrollment(x)   => Domain::Enrollment(x.clone()),
+            Domain::Labs(x)         => Domain::Labs(x.as_borrowed()),
+            Domain::Medication(x)   => Domain::Medication(x.as_borrowed()),
+            Domain::Procedure(x)    => Domain::Procedure(x.as_borrowed()),
+            Domain::Undefined(x)    => Domain::Undefined(x.clone()),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ClaimFacts<'a> {
     #[serde(bound(deserialize = "Claim<'a>: Deserialize<'de>"))]
     pub claim : Claim<'a>,
@@ -263,6 +697,24 @@ pub struct ClaimFacts<'a> {
 
 }
 
+impl ClaimFacts<'_> {
+    pub fn into_owned(self) -> ClaimFacts<'static> {
+        ClaimFacts {
+            claim: self.claim.into_owned(),
+            location: self.location,
+            cost: self.cost.map(Cost::into_owned),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> ClaimFacts<'_> {
+        ClaimFacts {
+            claim: self.claim.as_borrowed(),
+            location: self.location,
+            cost: self.cost.as_ref().map(Cost::as_borrowed),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_claim_context {
     use serde_json::{from_str, to_string, Result};
@@ -305,7 +757,7 @@ mod test_claim_context {
 /*----------------------------------------------------------------------------*/
 // Death
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DeathFacts {}
 
 #[cfg(test)]
@@ -331,7 +783,7 @@ mod test_death_context {
 /*----------------------------------------------------------------------------*/
 // Demographics
 
-#[derive(PartialEq, Debug, Deserialize, Serialize)]
+#[derive(PartialEq, Debug, Clone, Copy, Deserialize, Serialize)]
 pub enum DemographicField {
     BirthYear,
     BirthDate,
@@ -347,12 +799,12 @@ pub enum DemographicField {
     UrbanRural
 }
 
-#[derive(PartialEq, Debug, Deserialize, Serialize)]
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
 pub struct DemographicFacts {
     pub demo: DemographicInfo,
 }
 
-#[derive(PartialEq, Debug, Deserialize, Serialize)]
+#[derive(PartialEq, Debug, Clone, Deserialize, Serialize)]
 pub struct DemographicInfo {
     pub field:  DemographicField,
     pub info:   Option<serde_json::Value>,
@@ -398,7 +850,7 @@ mod test_demographic_context {
 /*----------------------------------------------------------------------------*/
 // Diagnosis
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DiagnosisFacts<'a> {
     #[serde(bound(deserialize = "Code<'a>: Deserialize<'de>"))]
     pub code    : Code<'a>,
@@ -411,6 +863,24 @@ pub struct DiagnosisFacts<'a> {
     pub location: Option<Location>,
 }
 
+impl DiagnosisFacts<'_> {
+    pub fn into_owned(self) -> DiagnosisFacts<'static> {
+        DiagnosisFacts {
+            code: self.code.into_owned(),
+            claim: self.claim.map(Claim::into_owned),
+            location: self.location,
+        }
+    }
+
+    pub fn as_borrowed(&self) -> DiagnosisFacts<'_> {
+        DiagnosisFacts {
+            code: self.code.as_borrowed(),
+            claim: self.claim.as_ref().map(Claim::as_borrowed),
+            location: self.location,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_diagnosis_context {
     use serde_json::{from_str, to_string, Result};
@@ -435,7 +905,7 @@ mod test_diagnosis_context {
 /*----------------------------------------------------------------------------*/
 // Eligibility
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EligibilityFacts {}
 
 #[cfg(test)]
@@ -460,7 +930,7 @@ mod test_eligibility_context {
 /*----------------------------------------------------------------------------*/
 // Enrollment
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct EnrollmentFacts {}
 
 #[cfg(test)]
@@ -486,19 +956,39 @@ mod test_enrollment_context {
 /*----------------------------------------------------------------------------*/
 // Labs
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LabValue<'a> {
 
+  #[serde(borrow)]
   #[serde(skip_serializing_if = "Option::is_none")]
-  pub text: Option<&'a str>,
+  pub text: Option<Cow<'a, str>>,
 
   #[serde(skip_serializing_if = "Option::is_none")]
   pub number: Option<f64>,
   
-  pub units: &'a str
+  #[serde(borrow)]
+  pub units: Cow<'a, str>
+}
+
+impl LabValue<'_> {
+    pub fn into_owned(self) -> LabValue<'static> {
+        LabValue {
+            text: self.text.map(own),
+            number: self.number,
+            units: own(self.units),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> LabValue<'_> {
+        LabValue {
+            text: borrow_opt(&self.text),
+            number: self.number,
+            units: Cow::Borrowed(&self.units),
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LabsFacts<'a> {
 
     #[serde(bound(deserialize = "Code<'a>: Deserialize<'de>"))]
@@ -515,6 +1005,26 @@ pub struct LabsFacts<'a> {
     pub location: Option<Location>,
 }
 
+impl LabsFacts<'_> {
+    pub fn into_owned(self) -> LabsFacts<'static> {
+        LabsFacts {
+            code: self.code.into_owned(),
+            value: self.value.into_owned(),
+            claim: self.claim.map(Claim::into_owned),
+            location: self.location,
+        }
+    }
+
+    pub fn as_borrowed(&self) -> LabsFacts<'_> {
+        LabsFacts {
+            code: self.code.as_borrowed(),
+            value: self.value.as_borrowed(),
+            claim: self.claim.as_ref().map(Claim::as_borrowed),
+            location: self.location,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_labs_context {
     use serde_json::{from_str, to_string, Result};
@@ -538,7 +1048,7 @@ mod test_labs_context {
 /*----------------------------------------------------------------------------*/
 // Medication
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MedicationFacts<'a> {
 
     #[serde(bound(deserialize = "Code<'a>: Deserialize<'de>"))]
@@ -556,6 +1066,26 @@ pub struct MedicationFacts<'a> {
     pub claim : Option<Claim<'a>>,
 }
 
+impl MedicationFacts<'_> {
+    pub fn into_owned(self) -> MedicationFacts<'static> {
+        MedicationFacts {
+            code: self.code.into_owned(),
+            fill: self.fill.map(Fill::into_owned),
+            location: self.location,
+            claim: self.claim.map(Claim::into_owned),
+        }
+    }
+
+    pub fn as_borrowed(&self) -> MedicationFacts<'_> {
+        MedicationFacts {
+            code: self.code.as_borrowed(),
+            fill: self.fill.as_ref().map(Fill::as_borrowed),
+            location: self.location,
+            claim: self.claim.as_ref().map(Claim::as_borrowed),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_medication_context {
     use serde_json::{from_str, to_string, Result};
@@ -580,7 +1110,7 @@ mod test_medication_context {
 /*----------------------------------------------------------------------------*/
 // Procedure
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ProcedureFacts<'a> {
     #[serde(bound(deserialize = "Code<'a>: Deserialize<'de>"))]
     pub code    : Code<'a>,
@@ -593,6 +1123,24 @@ pub struct ProcedureFacts<'a> {
     pub location: Option<Location>,
 }
 
+impl ProcedureFacts<'_> {
+    pub fn into_owned(self) -> ProcedureFacts<'static> {
+        ProcedureFacts {
+            code: self.code.into_owned(),
+            claim: self.claim.map(Claim::into_owned),
+            location: self.location,
+        }
+    }
+
+    pub fn as_borrowed(&self) -> ProcedureFacts<'_> {
+        ProcedureFacts {
+            code: self.code.as_borrowed(),
+            claim: self.claim.as_ref().map(Claim::as_borrowed),
+            location: self.location,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_procedure_context {
     use serde_json::{from_str, to_string, Result};
@@ -616,7 +1164,7 @@ mod test_procedure_context {
 
 /*----------------------------------------------------------------------------*/
 // Undefined
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UndefinedFacts {}
 
 #[cfg(test)]
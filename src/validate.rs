@@ -0,0 +1,240 @@
+//! Checks that an event's tuple header agrees with its context.
+//!
+//! An EDM event repeats itself: the header `[p, b, e, d, ...]` should match
+//! the context's `patient_id`, `time` and `domain`. [`validate`] lists the
+//! disagreements for one event; a [`Validator`] does the same as a stage in
+//! a [`process`](crate::process) pipeline.
+//!
+//! Example:
+//! ```
+//! use eddeserus::process::*;
+//! use eddeserus::validate::*;
+//!
+//! let json = "\
+//!     [\"xyz\",0,1,\"Death\",[],\
+//!      {\"patient_id\":\"xyz\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n\
+//!     [\"xyz\",0,1,\"Death\",[],\
+//!      {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":1},\
+//!       \"domain\":\"Death\",\"facts\":{}}]\n";
+//!
+//! let mut reports = Vec::new();
+//! let mut out = WriterOut::new(Vec::new());
+//! let mut validator = Validator::new(Mode::Strict, |r| reports.push(r));
+//! process_str(json, &mut validator, &mut out).unwrap();
+//!
+//! assert_eq!(reports.len(), 1);
+//! assert_eq!(reports[0].index, 2);
+//! ```
+
+use std::fmt;
+
+use crate::process::Processor;
+use crate::types::{Event, EventTime, ParseTimeError};
+
+/// A disagreement between an event's header and its context.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// `p` differs from `context.patient_id`. The IDs are written as JSON,
+    /// so that `"123"` and `123` can be told apart.
+    Patient { header: String, context: String },
+    /// `d` differs from the context's `domain` tag.
+    Domain { header: String, context: &'static str },
+    /// `b` differs from `context.time.begin`.
+    Begin { header: EventTime, context: EventTime },
+    /// `e` differs from `context.time.end`.
+    End { header: Option<EventTime>, context: Option<EventTime> },
+    /// `context.time` is not a valid time, so it cannot be compared.
+    Time(ParseTimeError),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn opt(t: &Option<EventTime>) -> String {
+            t.map_or_else(|| "null".to_string(), |t| t.to_string())
+        }
+        match self {
+            Violation::Patient { header, context } =>
+                write!(f, "p is {} but context.patient_id is {}", header, context),
+            Violation::Domain { header, context } =>
+                write!(f, "d is {} but context.domain is {}", header, context),
+            Violation::Begin { header, context } =>
+                write!(f, "b is {} but context.time.begin is {}", header, context),
+            Violation::End { header, context } =>
+                write!(f, "e is {} but context.time.end is {}", opt(header), opt(context)),
+            Violation::Time(e) =>
+                write!(f, "context.time: {}", e),
+        }
+    }
+}
+
+/// Lists the disagreements between an event's header and its context.
+///
+/// Patient IDs are compared as `SubjectID`s, as timelines group them, so
+/// `"123"` and `123` disagree. Times are compared as [`EventTime`]s, so an
+/// integer offset never agrees with a date.
+pub fn validate(event: &Event) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let context = &event.context;
+
+    if event.p != context.patient_id {
+        let json = |id| serde_json::to_string(id).expect("IDs serialize");
+        violations.push(Violation::Patient { header: json(&event.p), context: json(&context.patient_id) });
+    }
+
+    let domain = context.facts.name();
+    if event.d != domain {
+        violations.push(Violation::Domain {
+            header: event.d.to_string(),
+            context: domain,
+        });
+    }
+
+    match context.time.begin() {
+        Ok(b) if b != event.b =>
+            violations.push(Violation::Begin { header: event.b, context: b }),
+        Ok(_) => (),
+        Err(e) => violations.push(Violation::Time(e)),
+    }
+
+    match context.time.end() {
+        Ok(e) if e != event.e =>
+            violations.push(Violation::End { header: event.e, context: e }),
+        Ok(_) => (),
+        Err(e) => violations.push(Violation::Time(e)),
+    }
+
+    violations
+}
+
+/// What a [`Validator`] does with an event that has violations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Report the violations and drop the event.
+    Strict,
+    /// Report the violations and keep the event.
+    Warn,
+}
+
+/// The violations of one event.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// 1-based position of the event among those seen by the validator.
+    pub index: usize,
+    pub patient: String,
+    pub violations: Vec<Violation>,
+}
+
+/// A processor that validates each event, passing a [`Report`] to `on_invalid`
+/// for each event with violations.
+pub struct Validator<F> {
+    mode: Mode,
+    on_invalid: F,
+    seen: usize,
+}
+
+impl<F: FnMut(Report)> Validator<F> {
+    pub fn new(mode: Mode, on_invalid: F) -> Self {
+        Validator { mode, on_invalid, seen: 0 }
+    }
+}
+
+impl<F: FnMut(Report)> Processor for Validator<F> {
+    fn process<'a>(&mut self, event: Event<'a>) -> Option<Event<'a>> {
+        self.seen += 1;
+        let violations = validate(&event);
+        if violations.is_empty() {
+            return Some(event);
+        }
+
+        (self.on_invalid)(Report {
+            index: self.seen,
+            patient: event.p.to_string(),
+            violations,
+        });
+        match self.mode {
+            Mode::Strict => None,
+            Mode::Warn   => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_validate {
+    use serde_json::from_str;
+    use crate::process::*;
+    use crate::types::*;
+    use crate::validate::*;
+
+    #[test]
+    fn test_consistent() {
+        let json = "[\
+        123,\"2001-05-01\",null,\"Enrollment\",[],\
+        {\"patient_id\":123,\
+         \"time\":{\"begin\":\"2001-05-01\",\"end\":null},\
+         \"domain\":\"Enrollment\",\"facts\":{}}]";
+        let evnt : Event = from_str(json).unwrap();
+        assert!(validate(&evnt).is_empty());
+    }
+
+    #[test]
+    fn test_patient_kind() {
+        // An integer and a string ID are different subjects to timelines.
+        let json = "[\
+        123,\"2001-05-01\",null,\"Enrollment\",[],\
+        {\"patient_id\":\"123\",\
+         \"time\":{\"begin\":\"2001-05-01\",\"end\":null},\
+         \"domain\":\"Enrollment\",\"facts\":{}}]";
+        let evnt : Event = from_str(json).unwrap();
+        let violations = validate(&evnt);
+        assert_eq!(violations, vec![
+            Violation::Patient { header: "123".to_string(), context: "\"123\"".to_string() },
+        ]);
+        assert_eq!(violations[0].to_string(), "p is 123 but context.patient_id is \"123\"");
+    }
+
+    #[test]
+    fn test_inconsistent() {
+        let json = "[\
+        \"xyz\",2,null,\"Demographics\",[],\
+        {\"patient_id\":\"abc\",\
+         \"time\":{\"begin\":0,\"end\":1},\
+         \"domain\":\"Death\",\"facts\":{}}]";
+        let evnt : Event = from_str(json).unwrap();
+        assert_eq!(validate(&evnt), vec![
+            Violation::Patient { header: "\"xyz\"".to_string(), context: "\"abc\"".to_string() },
+            Violation::Domain { header: "Demographics".to_string(), context: "Death" },
+            Violation::Begin { header: EventTime::Offset(2), context: EventTime::Offset(0) },
+            Violation::End { header: None, context: Some(EventTime::Offset(1)) },
+        ]);
+    }
+
+    #[test]
+    fn test_bad_context_time() {
+        let json = "[\
+        \"xyz\",\"2001-05-01\",null,\"Death\",[],\
+        {\"patient_id\":\"xyz\",\
+         \"time\":{\"begin\":\"05/01/2001\",\"end\":null},\
+         \"domain\":\"Death\",\"facts\":{}}]";
+        let evnt : Event = from_str(json).unwrap();
+        let violations = validate(&evnt);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(violations[0], Violation::Time(_)));
+    }
+
+    #[test]
+    fn test_warn_mode() {
+        let json = "\
+        [\"xyz\",0,1,\"Death\",[],\
+         {\"patient_id\":\"abc\",\"time\":{\"begin\":0,\"end\":1},\
+          \"domain\":\"Death\",\"facts\":{}}]\n";
+
+        let mut n = 0;
+        let mut out : Vec<Event> = Vec::new();
+        let mut validator = Validator::new(Mode::Warn, |_| n += 1);
+        process_str(json, &mut validator, &mut out).unwrap();
+
+        assert_eq!(n, 1);
+        assert_eq!(out.len(), 1);
+    }
+}
@@ -0,0 +1,272 @@
+//! Value sets: lists of codes that define concepts.
+//!
+//! A [`ValueSet`] maps codes, or code prefixes, in a codebook to the names of
+//! concepts. It is read from a CSV or JSON file of entries with the fields
+//! `concept`, `codebook` and `code`:
+//!
+//! ```text
+//! concept,codebook,code
+//! diabetes,ICD10,E11*
+//! diabetes,ICD9,250*
+//! hiv,ICD10,Z21
+//! ```
+//!
+//! A code ending in `*` matches every code that starts with it. An empty
+//! `codebook` matches codes that have no codebook. ICD codes are compared
+//! without dots and ignoring case (see [`Code::normalize_icd`]); other codes
+//! are compared as they are, so NDCs should be normalized on both sides (see
+//! [`NormalizeNdc`]).
+//!
+//! A [`Tagger`] adds the concepts of each event's code to the event's
+//! `concepts`.
+//!
+//! Matching is by hash lookups of the code and of each of its prefixes that
+//! has the length of some prefix in the value set, so it takes time in
+//! proportion to the length of the code, not to the size of the value set.
+//!
+//! [`Code::normalize_icd`]: crate::types::Code::normalize_icd
+//! [`NormalizeNdc`]: crate::codes::NormalizeNdc
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::codes::strip_dots;
+use crate::process::Processor;
+use crate::types::{Code, Codebook, Event};
+
+/// One row of a value set file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entry {
+    pub concept: String,
+    pub codebook: Option<Codebook>,
+    /// A code, or a prefix followed by `*`.
+    pub code: String,
+}
+
+#[derive(Debug, Default)]
+struct Prefixes {
+    by_prefix: HashMap<String, Vec<usize>>,
+    /// Distinct lengths of the prefixes, in increasing order.
+    lengths: Vec<usize>,
+}
+
+/// Codes and code prefixes mapped to concepts.
+#[derive(Debug, Default)]
+pub struct ValueSet {
+    concepts: Vec<String>,
+    concept_ids: HashMap<String, usize>,
+    exact: HashMap<Option<Codebook>, HashMap<String, Vec<usize>>>,
+    prefixes: HashMap<Option<Codebook>, Prefixes>,
+}
+
+/// The form in which a code is stored and looked up.
+fn key(codebook: Option<Codebook>, code: &str) -> Cow<'_, str> {
+    match codebook {
+        Some(cb) if cb.is_icd()
+            && code.bytes().any(|c| c == b'.' || c.is_ascii_lowercase()
+                                    || c.is_ascii_whitespace()) =>
+            Cow::Owned(strip_dots(code)),
+        _ => Cow::Borrowed(code),
+    }
+}
+
+fn add_id(ids: &mut Vec<usize>, id: usize) {
+    if !ids.contains(&id) {
+        ids.push(id);
+    }
+}
+
+impl ValueSet {
+    pub fn new() -> Self {
+        ValueSet::default()
+    }
+
+    /// Adds a code, or a prefix followed by `*`, to `concept`.
+    pub fn insert(&mut self, concept: &str, codebook: Option<Codebook>, code: &str) {
+        let id = match self.concept_ids.get(concept) {
+            Some(id) => *id,
+            None => {
+                let id = self.concepts.len();
+                self.concepts.push(concept.to_string());
+                self.concept_ids.insert(concept.to_string(), id);
+                id
+            }
+        };
+
+        match code.strip_suffix('*') {
+            Some(prefix) => {
+                let prefix = key(codebook, prefix).into_owned();
+                let prefixes = self.prefixes.entry(codebook).or_default();
+                if let Err(i) = prefixes.lengths.binary_search(&prefix.len()) {
+                    prefixes.lengths.insert(i, prefix.len());
+                }
+                add_id(prefixes.by_prefix.entry(prefix).or_default(), id);
+            },
+            None => {
+                let code = key(codebook, code).into_owned();
+                add_id(self.exact.entry(codebook).or_default().entry(code).or_default(), id);
+            },
+        }
+    }
+
+    pub fn from_entries<I: IntoIterator<Item = Entry>>(entries: I) -> Self {
+        let mut value_set = ValueSet::new();
+        for e in entries {
+            value_set.insert(&e.concept, e.codebook, &e.code);
+        }
+        value_set
+    }
+
+    /// Reads a value set from CSV with a header row.
+    pub fn from_csv<R: io::Read>(reader: R) -> csv::Result<Self> {
+        csv::Reader::from_reader(reader)
+            .into_deserialize::<Entry>()
+            .collect::<csv::Result<Vec<Entry>>>()
+            .map(ValueSet::from_entries)
+    }
+
+    /// Reads a value set from a JSON array of entries.
+    pub fn from_json<R: io::Read>(reader: R) -> serde_json::Result<Self> {
+        serde_json::from_reader::<_, Vec<Entry>>(io::BufReader::new(reader))
+            .map(ValueSet::from_entries)
+    }
+
+    /// Reads a value set from a file, as JSON if its extension is `.json` and
+    /// as CSV otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        if path.extension().is_some_and(|x| x == "json") {
+            Ok(ValueSet::from_json(file)?)
+        } else {
+            Ok(ValueSet::from_csv(file)?)
+        }
+    }
+
+    /// The names of all concepts, in the order they were first added.
+    pub fn concept_names(&self) -> &[String] {
+        &self.concepts
+    }
+
+    fn for_each_match<F: FnMut(usize)>(&self, code: &Code, mut f: F) {
+        let key = key(code.codebook, &code.code);
+
+        if let Some(ids) = self.exact.get(&code.codebook).and_then(|m| m.get(key.as_ref())) {
+            ids.iter().for_each(|id| f(*id));
+        }
+
+        if let Some(prefixes) = self.prefixes.get(&code.codebook) {
+            for n in prefixes.lengths.iter().take_while(|n| **n <= key.len()) {
+                if !key.is_char_boundary(*n) {
+                    continue;
+                }
+                if let Some(ids) = prefixes.by_prefix.get(&key[..*n]) {
+                    ids.iter().for_each(|id| f(*id));
+                }
+            }
+        }
+    }
+
+    /// The concepts that `code` belongs to.
+    pub fn concepts(&self, code: &Code) -> Vec<&str> {
+        let mut ids = Vec::new();
+        self.for_each_match(code, |id| add_id(&mut ids, id));
+        ids.into_iter().map(|id| self.concepts[id].as_str()).collect()
+    }
+
+    /// A processor that tags events with the concepts of their codes.
+    pub fn tagger(&self) -> Tagger<'_> {
+        Tagger { value_set: self }
+    }
+}
+
+/// A processor that adds the concepts of the code of each Diagnosis,
+/// Procedure, Medication and Labs event to the event's `concepts`, skipping
+/// those it already has. See [`ValueSet::tagger`].
+pub struct Tagger<'v> {
+    value_set: &'v ValueSet,
+}
+
+impl Processor for Tagger<'_> {
+    fn process<'a>(&mut self, mut event: Event<'a>) -> Option<Event<'a>> {
+        if let Some(code) = event.context.facts.code() {
+            let names = &self.value_set.concepts;
+            let concepts = &mut event.concepts;
+            self.value_set.for_each_match(code, |id| {
+                if !concepts.contains(&names[id]) {
+                    concepts.push(names[id].clone());
+                }
+            });
+        }
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod test_valueset {
+    use crate::process::*;
+    use crate::types::*;
+    use crate::valueset::*;
+
+    const CSV: &str = "\
+concept,codebook,code
+diabetes,ICD10,E11*
+diabetes,ICD9,250*
+diabetes_complicated,ICD10,E11.6*
+hiv,ICD10,Z21
+hiv,ICD10,Z21
+unknown,,aaaaaaaaaa
+";
+
+    fn code(cb: Codebook, code: &str) -> Code<'_> {
+        Code { code: code.into(), codebook: Some(cb) }
+    }
+
+    #[test]
+    fn test_csv() {
+        let vs = ValueSet::from_csv(CSV.as_bytes()).unwrap();
+        assert_eq!(vs.concept_names(), ["diabetes", "diabetes_complicated", "hiv", "unknown"]);
+        assert_eq!(vs.concepts(&code(Codebook::ICD10, "E11.65")),
+                   vec!["diabetes", "diabetes_complicated"]);
+        assert_eq!(vs.concepts(&code(Codebook::ICD10, "e119")), vec!["diabetes"]);
+        assert_eq!(vs.concepts(&code(Codebook::ICD9, "250.00")), vec!["diabetes"]);
+        assert_eq!(vs.concepts(&code(Codebook::ICD10, "Z21")), vec!["hiv"]);
+        assert!(vs.concepts(&code(Codebook::ICD10, "Z210")).is_empty());
+        assert!(vs.concepts(&code(Codebook::ICD9, "E11")).is_empty());
+        assert_eq!(vs.concepts(&Code { code: "aaaaaaaaaa".into(), codebook: None }),
+                   vec!["unknown"]);
+    }
+
+    #[test]
+    fn test_json() {
+        let json = "[\
+            {\"concept\":\"hiv\",\"codebook\":\"ICD10\",\"code\":\"Z21\"},\
+            {\"concept\":\"insulin\",\"codebook\":\"NDC\",\"code\":\"00002*\"}]";
+        let vs = ValueSet::from_json(json.as_bytes()).unwrap();
+        assert_eq!(vs.concepts(&code(Codebook::NDC, "00002143380")), vec!["insulin"]);
+    }
+
+    #[test]
+    fn test_tagger() {
+        let vs = ValueSet::from_csv(CSV.as_bytes()).unwrap();
+        let json = include_str!("../resources/50events.json");
+
+        let mut out : Vec<Event> = Vec::new();
+        process_str(json, &mut vs.tagger(), &mut out).unwrap();
+        assert_eq!(out.len(), 50);
+        assert!(out.iter().all(|e| e.concepts == vec!["unknown"]));
+
+        // Tagging twice adds nothing.
+        let mut again : Vec<Event> = Vec::new();
+        let mut tagger = vs.tagger();
+        for e in out {
+            again.push(tagger.process(e).unwrap());
+        }
+        assert!(again.iter().all(|e| e.concepts == vec!["unknown"]));
+    }
+}